@@ -0,0 +1,437 @@
+use crate::block::Content;
+use crate::blockchain::{BlockChain, TransactionStatus};
+use crate::blockdb::BlockDatabase;
+use crate::crypto::hash::{Hashable, H256};
+use crate::ledger_manager::Handle as LedgerHandle;
+use crate::miner::memory_pool::MemoryPool;
+use crate::transaction::{Address, CoinId, Transaction};
+use crate::utxodb::UtxoDatabase;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longest that a client can wait for a transaction in one request.
+const MAX_TRANSACTION_WAIT: Duration = Duration::from_secs(600);
+
+#[derive(Serialize)]
+pub struct HeaderResponse {
+    parent: String,
+    timestamp: u128,
+    nonce: u32,
+    content_merkle_root: String,
+    extra_content: String,
+    difficulty: String,
+    miner: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ContentResponse {
+    Proposer {
+        level: Option<u64>,
+        transaction_refs: Vec<String>,
+        proposer_refs: Vec<String>,
+    },
+    Voter {
+        chain_number: u16,
+        level: Option<u64>,
+        voter_parent: String,
+        votes: Vec<String>,
+    },
+    Transaction {
+        transactions: Vec<String>,
+    },
+}
+
+#[derive(Serialize)]
+pub struct BlockResponse {
+    hash: String,
+    /// Whether the block is inserted into the blockchain, rather than only stored.
+    in_blockchain: bool,
+    header: HeaderResponse,
+    content: ContentResponse,
+}
+
+#[derive(Serialize)]
+pub struct InputResponse {
+    hash: String,
+    index: u32,
+    value: u64,
+    owner: String,
+}
+
+#[derive(Serialize)]
+pub struct OutputResponse {
+    value: u64,
+    recipient: String,
+}
+
+#[derive(Serialize)]
+pub struct MultisigResponse {
+    address: String,
+    threshold: u32,
+    pubkeys: Vec<String>,
+}
+
+#[derive(Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionState {
+    /// In the memory pool.
+    Pending,
+    /// In a transaction block that no proposer block refers to.
+    Included,
+    /// In a transaction block that a proposer block refers to, but not in the ledger.
+    Referred,
+    /// In the ledger.
+    Confirmed,
+    /// In the ledger, but does not apply, e.g. because it spends a coin that is already spent.
+    Rejected,
+    /// Was in the ledger, but dropped out when a leader changed.
+    Deconfirmed,
+}
+
+#[derive(Serialize)]
+pub struct TransactionResponse {
+    hash: String,
+    status: TransactionState,
+    /// The transaction block that carries the transaction.
+    block: Option<String>,
+    /// The proposer block that refers to the transaction block.
+    proposer: Option<String>,
+    /// The ledger level of the proposer block, if the transaction is (or was) in the ledger.
+    level: Option<u64>,
+    input: Vec<InputResponse>,
+    output: Vec<OutputResponse>,
+    /// What the inputs are worth more than the outputs.
+    fee: u64,
+    /// The multisig locks of the inputs that are locked to one.
+    multisig: Vec<MultisigResponse>,
+}
+
+#[derive(Serialize)]
+pub struct LeaderResponse {
+    level: u64,
+    leader: String,
+}
+
+#[derive(Serialize)]
+pub struct CoinResponse {
+    hash: String,
+    index: u32,
+    value: u64,
+    recipient: String,
+}
+
+#[derive(Serialize)]
+pub struct AddressCoinResponse {
+    hash: String,
+    index: u32,
+    value: u64,
+}
+
+#[derive(Serialize)]
+pub struct AddressCoinsResponse {
+    address: String,
+    coins: Vec<AddressCoinResponse>,
+}
+
+#[derive(Serialize)]
+pub struct AddressBalanceResponse {
+    address: String,
+    coins: usize,
+    balance: u64,
+}
+
+/// Parse a hash in hex. `name` is what the hash is, for the error message.
+pub fn parse_hash(value: &str, name: &str) -> Result<H256, String> {
+    let bytes = hex::decode(value).map_err(|e| format!("error parsing {}: {}", name, e))?;
+    if bytes.len() != 32 {
+        return Err(format!("error parsing {}: not 32 bytes", name));
+    }
+    let mut raw: [u8; 32] = [0; 32];
+    raw.copy_from_slice(&bytes);
+    Ok(raw.into())
+}
+
+/// Parse a hash in hex from the given query parameter.
+pub fn hash_param(params: &HashMap<String, String>, name: &str) -> Result<H256, String> {
+    match params.get(name) {
+        Some(v) => parse_hash(v, name),
+        None => Err(format!("missing {}", name)),
+    }
+}
+
+fn hashes_to_strings(hashes: &[H256]) -> Vec<String> {
+    hashes.iter().map(|h| h.to_string()).collect()
+}
+
+/// Look up a block by hash, or return `None` if we don't have it.
+pub fn block(
+    hash: &H256,
+    blockdb: &BlockDatabase,
+    blockchain: &BlockChain,
+) -> Result<Option<BlockResponse>, rocksdb::Error> {
+    let block = match blockdb.get(hash)? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    let header = HeaderResponse {
+        parent: block.header.parent.to_string(),
+        timestamp: block.header.timestamp,
+        nonce: block.header.nonce,
+        content_merkle_root: block.header.content_merkle_root.to_string(),
+        extra_content: hex::encode(&block.header.extra_content),
+        difficulty: block.header.difficulty.to_string(),
+        miner: block.header.miner.to_string(),
+    };
+    let (in_blockchain, content) = match &block.content {
+        Content::Proposer(c) => {
+            let in_blockchain = blockchain.contains_proposer(hash)?;
+            let level = if in_blockchain {
+                Some(blockchain.proposer_level(hash)?)
+            } else {
+                None
+            };
+            let content = ContentResponse::Proposer {
+                level,
+                transaction_refs: hashes_to_strings(&c.transaction_refs),
+                proposer_refs: hashes_to_strings(&c.proposer_refs),
+            };
+            (in_blockchain, content)
+        }
+        Content::Voter(c) => {
+            let in_blockchain = blockchain.contains_voter(hash)?;
+            let level = if in_blockchain {
+                Some(blockchain.voter_level(hash)?)
+            } else {
+                None
+            };
+            let content = ContentResponse::Voter {
+                chain_number: c.chain_number,
+                level,
+                voter_parent: c.voter_parent.to_string(),
+                votes: hashes_to_strings(&c.votes),
+            };
+            (in_blockchain, content)
+        }
+        Content::Transaction(c) => {
+            let in_blockchain = blockchain.contains_transaction(hash)?;
+            let content = ContentResponse::Transaction {
+                transactions: c
+                    .transactions
+                    .iter()
+                    .map(|t| t.hash().to_string())
+                    .collect(),
+            };
+            (in_blockchain, content)
+        }
+    };
+    Ok(Some(BlockResponse {
+        hash: hash.to_string(),
+        in_blockchain,
+        header,
+        content,
+    }))
+}
+
+fn transaction_response(
+    transaction: &Transaction,
+    status: TransactionState,
+    block: Option<H256>,
+    proposer: Option<H256>,
+    level: Option<u64>,
+) -> TransactionResponse {
+    TransactionResponse {
+        hash: transaction.hash().to_string(),
+        status,
+        block: block.map(|h| h.to_string()),
+        proposer: proposer.map(|h| h.to_string()),
+        level,
+        fee: transaction.fee().unwrap_or(0),
+        input: transaction
+            .input
+            .iter()
+            .map(|i| InputResponse {
+                hash: i.coin.hash.to_string(),
+                index: i.coin.index,
+                value: i.value,
+                owner: i.owner.to_string(),
+            })
+            .collect(),
+        output: transaction
+            .output
+            .iter()
+            .map(|o| OutputResponse {
+                value: o.value,
+                recipient: o.recipient.to_string(),
+            })
+            .collect(),
+        multisig: transaction
+            .multisig
+            .iter()
+            .map(|m| MultisigResponse {
+                address: m.address().to_string(),
+                threshold: m.threshold,
+                pubkeys: m.pubkeys.iter().map(hex::encode).collect(),
+            })
+            .collect(),
+    }
+}
+
+/// Look up a transaction by hash in the memory pool and in the blockchain, or return `None` if
+/// we can't find it.
+pub fn transaction(
+    hash: &H256,
+    mempool: &Mutex<MemoryPool>,
+    blockdb: &BlockDatabase,
+    blockchain: &BlockChain,
+    utxodb: &UtxoDatabase,
+) -> Result<Option<TransactionResponse>, rocksdb::Error> {
+    if let Some(entry) = mempool.lock().unwrap().get(hash) {
+        return Ok(Some(transaction_response(
+            &entry.transaction,
+            TransactionState::Pending,
+            None,
+            None,
+            None,
+        )));
+    }
+    let (state, block, proposer, level) = match blockchain.transaction_status(hash, utxodb)? {
+        Some(TransactionStatus::Included { block }) => {
+            (TransactionState::Included, block, None, None)
+        }
+        Some(TransactionStatus::Referred { block, proposer }) => {
+            (TransactionState::Referred, block, Some(proposer), None)
+        }
+        Some(TransactionStatus::Confirmed {
+            block,
+            proposer,
+            level,
+        }) => (
+            TransactionState::Confirmed,
+            block,
+            Some(proposer),
+            Some(level),
+        ),
+        Some(TransactionStatus::Rejected {
+            block,
+            proposer,
+            level,
+        }) => (
+            TransactionState::Rejected,
+            block,
+            Some(proposer),
+            Some(level),
+        ),
+        Some(TransactionStatus::Deconfirmed {
+            block,
+            proposer,
+            level,
+        }) => (
+            TransactionState::Deconfirmed,
+            block,
+            Some(proposer),
+            Some(level),
+        ),
+        None => return Ok(None),
+    };
+    let content = match blockdb.get(&block)? {
+        Some(b) => match b.content {
+            Content::Transaction(c) => c,
+            _ => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    Ok(content
+        .transactions
+        .iter()
+        .find(|t| t.hash() == *hash)
+        .map(|t| transaction_response(t, state, Some(block), proposer, level)))
+}
+
+/// Wait until the given transaction is in the ledger, whether or not it applies, or until the
+/// timeout (at most `MAX_TRANSACTION_WAIT`), and then look it up.
+pub fn wait_transaction(
+    hash: &H256,
+    timeout: Duration,
+    ledger: &LedgerHandle,
+    mempool: &Mutex<MemoryPool>,
+    blockdb: &BlockDatabase,
+    blockchain: &BlockChain,
+    utxodb: &UtxoDatabase,
+) -> Result<Option<TransactionResponse>, rocksdb::Error> {
+    let deadline = Instant::now() + timeout.min(MAX_TRANSACTION_WAIT);
+    loop {
+        // read the counter before the status, so that we don't miss a change in between
+        let updates = ledger.updates();
+        let resp = transaction(hash, mempool, blockdb, blockchain, utxodb)?;
+        let confirmed = match &resp {
+            Some(r) => {
+                r.status == TransactionState::Confirmed || r.status == TransactionState::Rejected
+            }
+            None => false,
+        };
+        let now = Instant::now();
+        if confirmed || now >= deadline {
+            return Ok(resp);
+        }
+        ledger.wait_update(updates, deadline - now);
+    }
+}
+
+/// Look up the leader of a proposer level, or return `None` if the level is not confirmed yet.
+pub fn leader(
+    level: u64,
+    blockchain: &BlockChain,
+) -> Result<Option<LeaderResponse>, rocksdb::Error> {
+    Ok(blockchain
+        .ledger_leader(level)?
+        .map(|leader| LeaderResponse {
+            level,
+            leader: leader.to_string(),
+        }))
+}
+
+/// Look up an unspent coin, or return `None` if it is not in the UTXO set.
+pub fn coin(coin: &CoinId, utxodb: &UtxoDatabase) -> Result<Option<CoinResponse>, rocksdb::Error> {
+    Ok(utxodb.get(coin)?.map(|output| CoinResponse {
+        hash: coin.hash.to_string(),
+        index: coin.index,
+        value: output.value,
+        recipient: output.recipient.to_string(),
+    }))
+}
+
+/// List the unspent coins of an address. The UTXO database must index coins by address.
+pub fn address_coins(
+    address: &Address,
+    skip: usize,
+    limit: usize,
+    utxodb: &UtxoDatabase,
+) -> Result<AddressCoinsResponse, rocksdb::Error> {
+    let coins = utxodb.address_coins(address, skip, limit)?;
+    Ok(AddressCoinsResponse {
+        address: address.to_string(),
+        coins: coins
+            .iter()
+            .map(|(coin, value)| AddressCoinResponse {
+                hash: coin.hash.to_string(),
+                index: coin.index,
+                value: *value,
+            })
+            .collect(),
+    })
+}
+
+/// Get the balance of an address. The UTXO database must index coins by address.
+pub fn address_balance(
+    address: &Address,
+    utxodb: &UtxoDatabase,
+) -> Result<AddressBalanceResponse, rocksdb::Error> {
+    let (coins, balance) = utxodb.address_balance(address)?;
+    Ok(AddressBalanceResponse {
+        address: address.to_string(),
+        coins,
+        balance,
+    })
+}
@@ -1,28 +1,50 @@
 use crate::blockchain::BlockChain;
+use crate::blockdb::BlockDatabase;
+use crate::crypto::hash::Hashable;
+use crate::event::EVENTS;
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::experiment::transaction_generator;
+use crate::handler::new_transaction;
+use crate::ledger_manager::Handle as LedgerHandle;
 use crate::miner::memory_pool::MemoryPool;
 use crate::miner::Handle as MinerHandle;
 use crate::network::server::Handle as ServerHandle;
+use crate::transaction::{CoinId, Multisig, Transaction};
 use crate::utxodb::UtxoDatabase;
-use crate::wallet::Wallet;
+use crate::validation::TransactionRejection;
+use crate::wallet::{CoinSelection, Wallet, WalletError, DEFAULT_ACCOUNT};
 
 use log::info;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use tiny_http::Header;
+use tiny_http::Method;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
 use url::Url;
 
+mod explorer;
+mod rpc;
+
+/// Number of coins to list for an address if the client does not say.
+const DEFAULT_ADDRESS_COINS_LIMIT: usize = 1000;
+/// Number of events to return at once if the client does not say.
+const DEFAULT_EVENTS_LIMIT: usize = 1000;
+
 pub struct Server {
     transaction_generator_handle: crossbeam::Sender<transaction_generator::ControlSignal>,
     handle: HTTPServer,
     miner: MinerHandle,
+    server: ServerHandle,
     wallet: Arc<Wallet>,
     utxodb: Arc<UtxoDatabase>,
+    blockdb: Arc<BlockDatabase>,
     blockchain: Arc<BlockChain>,
+    mempool: Arc<Mutex<MemoryPool>>,
+    ledger: LedgerHandle,
 }
 
 #[derive(Serialize)]
@@ -36,6 +58,61 @@ struct WalletBalanceResponse {
     balance: u64,
 }
 
+#[derive(Serialize)]
+struct WalletAccountResponse {
+    name: String,
+    balance: u64,
+    addresses: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct WalletAccountsResponse {
+    accounts: Vec<WalletAccountResponse>,
+    /// Total value of the coins that are being spent.
+    pending: u64,
+}
+
+#[derive(Serialize)]
+struct WalletAddressResponse {
+    address: String,
+}
+
+#[derive(Serialize)]
+struct WalletPublicKeyResponse {
+    address: String,
+    pubkey: String,
+}
+
+#[derive(Serialize)]
+struct WalletMultisigResponse {
+    address: String,
+    threshold: u32,
+    pubkeys: Vec<String>,
+    balance: u64,
+}
+
+#[derive(Serialize)]
+struct PartialTransactionResponse {
+    hash: String,
+    /// Whether the transaction has all the signatures it needs, and is ready to submit.
+    complete: bool,
+    transaction: Transaction,
+}
+
+#[derive(Serialize)]
+struct WalletStatusResponse {
+    encrypted: bool,
+    locked: bool,
+}
+
+#[derive(Serialize)]
+struct TransactionSubmitResponse {
+    success: bool,
+    hash: String,
+    message: String,
+    rejection: Option<TransactionRejection>,
+}
+
 #[derive(Serialize)]
 struct UtxoSnapshotResponse {
     checksum: String,
@@ -68,15 +145,157 @@ macro_rules! respond_json {
     }};
 }
 
+/// Parse the given query parameter, or return the default if it is missing.
+fn optional_param<T: std::str::FromStr>(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: T,
+) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    match params.get(name) {
+        Some(v) => v
+            .parse::<T>()
+            .map_err(|e| format!("error parsing {}: {}", name, e)),
+        None => Ok(default),
+    }
+}
+
+/// Decode a transaction, either in JSON, or in bincode and then hex or base64.
+fn decode_transaction(encoding: &str, data: &str) -> Result<Transaction, String> {
+    let transaction: Result<Transaction, String> = match encoding {
+        "json" => serde_json::from_str(data).map_err(|e| e.to_string()),
+        "hex" => hex::decode(data.trim())
+            .map_err(|e| e.to_string())
+            .and_then(|b| bincode::deserialize(&b).map_err(|e| e.to_string())),
+        "base64" => base64::decode(data.trim())
+            .map_err(|e| e.to_string())
+            .and_then(|b| bincode::deserialize(&b).map_err(|e| e.to_string())),
+        e => return Err(format!("invalid encoding: {}", e)),
+    };
+    transaction.map_err(|e| format!("error parsing transaction: {}", e))
+}
+
+/// Parse the JSON body of a POST request, for requests that carry secrets we don't want in URLs.
+fn read_json_body<T: DeserializeOwned>(req: &mut tiny_http::Request) -> Result<T, String> {
+    if *req.method() != Method::Post {
+        return Err("request must be POSTed".to_string());
+    }
+    let mut body = String::new();
+    req.as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| format!("error reading request body: {}", e))?;
+    serde_json::from_str(&body).map_err(|e| format!("error parsing request body: {}", e))
+}
+
+/// Parse a multisig lock with public keys in hex.
+fn parse_multisig(threshold: u32, pubkeys: &[String]) -> Result<Multisig, String> {
+    let mut decoded = vec![];
+    for pubkey in pubkeys {
+        let bytes = hex::decode(pubkey.trim())
+            .map_err(|e| format!("error decoding public key {}: {}", pubkey, e))?;
+        if let Err(e) = ed25519_dalek::PublicKey::from_bytes(&bytes) {
+            return Err(format!("invalid public key {}: {}", pubkey, e));
+        }
+        decoded.push(bytes);
+    }
+    Ok(Multisig::new(threshold, decoded))
+}
+
+fn wallet_multisig_locks(wallet: &Wallet) -> Result<Vec<WalletMultisigResponse>, WalletError> {
+    let mut locks = vec![];
+    for (address, lock) in wallet.multisig_locks() {
+        locks.push(WalletMultisigResponse {
+            balance: wallet
+                .multisig_coins(&address)?
+                .iter()
+                .map(|(_, o)| o.value)
+                .sum(),
+            address: address.to_string(),
+            threshold: lock.threshold,
+            pubkeys: lock.pubkeys.iter().map(hex::encode).collect(),
+        });
+    }
+    Ok(locks)
+}
+
+fn partial_transaction(transaction: Transaction) -> PartialTransactionResponse {
+    PartialTransactionResponse {
+        hash: transaction.hash().to_string(),
+        complete: {
+            let authorized = transaction.authorized_addresses();
+            transaction
+                .input
+                .iter()
+                .all(|i| authorized.contains(&i.owner))
+        },
+        transaction,
+    }
+}
+
+/// Merge the signatures of copies of a transaction.
+fn combine_transactions(transactions: &[Transaction]) -> Result<Transaction, String> {
+    let (first, rest) = match transactions.split_first() {
+        Some(s) => s,
+        None => return Err("no transactions to combine".to_string()),
+    };
+    let mut combined = first.clone();
+    for t in rest {
+        combined = match combined.combine(t) {
+            Some(c) => c,
+            None => return Err("transactions to combine differ".to_string()),
+        };
+    }
+    Ok(combined)
+}
+
+fn wallet_accounts(wallet: &Wallet) -> Result<WalletAccountsResponse, WalletError> {
+    let mut accounts = vec![];
+    for name in wallet.accounts() {
+        accounts.push(WalletAccountResponse {
+            balance: wallet.account_balance(&name)?,
+            addresses: wallet
+                .account_addresses(&name)
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+            name,
+        });
+    }
+    Ok(WalletAccountsResponse {
+        accounts,
+        pending: wallet.pending_balance()?,
+    })
+}
+
+/// Submit a transaction the wallet created. The coins go back to the wallet if the transaction is
+/// rejected.
+fn submit_own_transaction(
+    transaction: Transaction,
+    wallet: &Wallet,
+    mempool: &Mutex<MemoryPool>,
+    utxodb: &UtxoDatabase,
+    server: &ServerHandle,
+) -> Result<(), TransactionRejection> {
+    let result = new_transaction(transaction.clone(), mempool, utxodb, server);
+    if result.is_err() {
+        wallet.abandon_transaction(&transaction).unwrap();
+    }
+    result
+}
+
 impl Server {
     pub fn start(
         addr: std::net::SocketAddr,
         wallet: &Arc<Wallet>,
+        blockdb: &Arc<BlockDatabase>,
         blockchain: &Arc<BlockChain>,
         utxodb: &Arc<UtxoDatabase>,
-        _server: &ServerHandle,
+        server: &ServerHandle,
         miner: &MinerHandle,
-        _mempool: &Arc<Mutex<MemoryPool>>,
+        mempool: &Arc<Mutex<MemoryPool>>,
+        ledger: &LedgerHandle,
         txgen_control_chan: crossbeam::Sender<transaction_generator::ControlSignal>,
     ) {
         let handle = HTTPServer::http(&addr).unwrap();
@@ -84,17 +303,25 @@ impl Server {
             handle,
             transaction_generator_handle: txgen_control_chan,
             miner: miner.clone(),
+            server: server.clone(),
             wallet: Arc::clone(wallet),
             utxodb: Arc::clone(utxodb),
+            blockdb: Arc::clone(blockdb),
             blockchain: Arc::clone(blockchain),
+            mempool: Arc::clone(mempool),
+            ledger: ledger.clone(),
         };
         thread::spawn(move || {
             for req in server.handle.incoming_requests() {
                 let transaction_generator_handle = server.transaction_generator_handle.clone();
                 let miner = server.miner.clone();
+                let p2p_server = server.server.clone();
                 let wallet = Arc::clone(&server.wallet);
                 let utxodb = Arc::clone(&server.utxodb);
+                let blockdb = Arc::clone(&server.blockdb);
                 let blockchain = Arc::clone(&server.blockchain);
+                let mempool = Arc::clone(&server.mempool);
+                let ledger = server.ledger.clone();
                 thread::spawn(move || {
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
@@ -122,9 +349,583 @@ impl Server {
                             };
                             respond_json!(req, resp);
                         }
+                        "/block" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match explorer::hash_param(&params, "hash") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match explorer::block(&hash, &blockdb, &blockchain).unwrap() {
+                                Some(resp) => respond_json!(req, resp),
+                                None => respond_result!(req, false, "block not found"),
+                            }
+                        }
+                        "/transaction" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match explorer::hash_param(&params, "hash") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match explorer::transaction(
+                                &hash,
+                                &mempool,
+                                &blockdb,
+                                &blockchain,
+                                &utxodb,
+                            )
+                            .unwrap()
+                            {
+                                Some(resp) => respond_json!(req, resp),
+                                None => respond_result!(req, false, "transaction not found"),
+                            }
+                        }
+                        "/transaction/wait" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match explorer::hash_param(&params, "hash") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let timeout = match params.get("timeout") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing timeout");
+                                    return;
+                                }
+                            };
+                            let timeout = match timeout.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing timeout: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            match explorer::wait_transaction(
+                                &hash,
+                                Duration::from_millis(timeout),
+                                &ledger,
+                                &mempool,
+                                &blockdb,
+                                &blockchain,
+                                &utxodb,
+                            )
+                            .unwrap()
+                            {
+                                Some(resp) => respond_json!(req, resp),
+                                None => respond_result!(req, false, "transaction not found"),
+                            }
+                        }
+                        "/proposer/leader" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let level = match params.get("level") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing level");
+                                    return;
+                                }
+                            };
+                            let level = match level.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing level: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            match explorer::leader(level, &blockchain).unwrap() {
+                                Some(resp) => respond_json!(req, resp),
+                                None => respond_result!(req, false, "level not confirmed"),
+                            }
+                        }
+                        "/utxo/coin" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match explorer::hash_param(&params, "hash") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let index = match params.get("index") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing index");
+                                    return;
+                                }
+                            };
+                            let index = match index.parse::<u32>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing index: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let coin = CoinId { hash, index };
+                            match explorer::coin(&coin, &utxodb).unwrap() {
+                                Some(resp) => respond_json!(req, resp),
+                                None => respond_result!(req, false, "coin not found or spent"),
+                            }
+                        }
+                        "/address/balance" => {
+                            if !utxodb.has_address_index() {
+                                respond_result!(req, false, "address index is disabled");
+                                return;
+                            }
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match explorer::hash_param(&params, "address") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            respond_json!(
+                                req,
+                                explorer::address_balance(&address, &utxodb).unwrap()
+                            );
+                        }
+                        "/address/coins" => {
+                            if !utxodb.has_address_index() {
+                                respond_result!(req, false, "address index is disabled");
+                                return;
+                            }
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match explorer::hash_param(&params, "address") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let skip = match optional_param(&params, "skip", 0) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let limit =
+                                match optional_param(&params, "limit", DEFAULT_ADDRESS_COINS_LIMIT)
+                                {
+                                    Ok(v) => v,
+                                    Err(e) => {
+                                        respond_result!(req, false, e);
+                                        return;
+                                    }
+                                };
+                            respond_json!(
+                                req,
+                                explorer::address_coins(&address, skip, limit, &utxodb).unwrap()
+                            );
+                        }
                         "/wallet/balance" => {
-                            let resp = WalletBalanceResponse {
-                                balance: wallet.balance().unwrap(),
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let balance = match params.get("account") {
+                                Some(account) => wallet.account_balance(account).unwrap(),
+                                None => wallet.balance().unwrap(),
+                            };
+                            let resp = WalletBalanceResponse { balance };
+                            respond_json!(req, resp);
+                        }
+                        "/wallet/accounts" => {
+                            respond_json!(req, wallet_accounts(&wallet).unwrap());
+                        }
+                        "/wallet/address/new" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let account = match params.get("account") {
+                                Some(v) => v.as_ref(),
+                                None => DEFAULT_ACCOUNT,
+                            };
+                            match wallet.new_address(account) {
+                                Ok(a) => {
+                                    let resp = WalletAddressResponse {
+                                        address: a.to_string(),
+                                    };
+                                    respond_json!(req, resp);
+                                }
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error generating address: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/status" => {
+                            let resp = WalletStatusResponse {
+                                encrypted: wallet.is_encrypted(),
+                                locked: wallet.is_locked(),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/wallet/encrypt" => {
+                            let mut req = req;
+                            let p: rpc::PassphraseParams = match read_json_body(&mut req) {
+                                Ok(p) => p,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match wallet.encrypt(&p.passphrase) {
+                                Ok(()) => respond_result!(req, true, "ok"),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error encrypting wallet: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/unlock" => {
+                            let mut req = req;
+                            let p: rpc::WalletUnlockParams = match read_json_body(&mut req) {
+                                Ok(p) => p,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let timeout = p.timeout.map(Duration::from_secs);
+                            match wallet.unlock(&p.passphrase, timeout) {
+                                Ok(()) => respond_result!(req, true, "ok"),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error unlocking wallet: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/pubkey" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match explorer::hash_param(&params, "address") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match wallet.public_key(&address) {
+                                Ok(k) => {
+                                    let resp = WalletPublicKeyResponse {
+                                        address: address.to_string(),
+                                        pubkey: hex::encode(&k),
+                                    };
+                                    respond_json!(req, resp);
+                                }
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error reading public key: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/multisig" => match wallet_multisig_locks(&wallet) {
+                            Ok(locks) => respond_json!(req, locks),
+                            Err(e) => respond_result!(
+                                req,
+                                false,
+                                format!("error reading multisig locks: {}", e)
+                            ),
+                        },
+                        "/wallet/multisig/add" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let threshold = match params.get("threshold") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing threshold");
+                                    return;
+                                }
+                            };
+                            let threshold = match threshold.parse::<u32>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing threshold: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let pubkeys: Vec<String> = match params.get("pubkeys") {
+                                Some(v) => v.split(',').map(|k| k.to_string()).collect(),
+                                None => {
+                                    respond_result!(req, false, "missing pubkeys");
+                                    return;
+                                }
+                            };
+                            let lock = match parse_multisig(threshold, &pubkeys) {
+                                Ok(l) => l,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match wallet.add_multisig(lock) {
+                                Ok(a) => {
+                                    let resp = WalletAddressResponse {
+                                        address: a.to_string(),
+                                    };
+                                    respond_json!(req, resp);
+                                }
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error adding multisig lock: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/multisig/create" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match explorer::hash_param(&params, "address") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let recipient = match explorer::hash_param(&params, "recipient") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let value = match params.get("value") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing value");
+                                    return;
+                                }
+                            };
+                            let value = match value.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing value: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let fee = match optional_param(&params, "fee", 0) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match wallet
+                                .create_multisig_transaction(&address, recipient, value, fee)
+                            {
+                                Ok(t) => respond_json!(req, partial_transaction(t)),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error creating transaction: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/sign" => {
+                            let mut req = req;
+                            let transaction: Transaction = match read_json_body(&mut req) {
+                                Ok(t) => t,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match wallet.sign_transaction(&transaction) {
+                                Ok(t) => respond_json!(req, partial_transaction(t)),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error signing transaction: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/combine" => {
+                            let mut req = req;
+                            let transactions: Vec<Transaction> = match read_json_body(&mut req) {
+                                Ok(t) => t,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match combine_transactions(&transactions) {
+                                Ok(t) => respond_json!(req, partial_transaction(t)),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/lock" => match wallet.lock() {
+                            Ok(()) => respond_result!(req, true, "ok"),
+                            Err(e) => {
+                                respond_result!(req, false, format!("error locking wallet: {}", e))
+                            }
+                        },
+                        "/wallet/export" => {
+                            let mut req = req;
+                            let p: rpc::WalletExportParams = match read_json_body(&mut req) {
+                                Ok(p) => p,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let address = match explorer::parse_hash(&p.address, "address") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match wallet.export_keypair(&address, &p.passphrase) {
+                                Ok(k) => respond_json!(req, k),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error exporting key pair: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/import" => {
+                            let mut req = req;
+                            let p: rpc::WalletImportParams = match read_json_body(&mut req) {
+                                Ok(p) => p,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let account = p.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+                            match wallet.import_keypair(&p.key, &p.passphrase, account) {
+                                Ok(a) => {
+                                    let resp = WalletAddressResponse {
+                                        address: a.to_string(),
+                                    };
+                                    respond_json!(req, resp);
+                                }
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error importing key pair: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/pay" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let recipient = match explorer::hash_param(&params, "recipient") {
+                                Ok(h) => h,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let value = match params.get("value") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing value");
+                                    return;
+                                }
+                            };
+                            let value = match value.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing value: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let account = match params.get("account") {
+                                Some(v) => v.as_ref(),
+                                None => DEFAULT_ACCOUNT,
+                            };
+                            let selection = match params.get("selection") {
+                                Some(v) => match v.parse::<CoinSelection>() {
+                                    Ok(v) => v,
+                                    Err(e) => {
+                                        respond_result!(req, false, e);
+                                        return;
+                                    }
+                                },
+                                None => CoinSelection::default(),
+                            };
+                            let fee = match optional_param(&params, "fee", 0) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let transaction = match wallet
+                                .create_transaction(account, recipient, value, fee, selection)
+                            {
+                                Ok(t) => t,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error creating transaction: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let hash = transaction.hash();
+                            let resp = match submit_own_transaction(
+                                transaction,
+                                &wallet,
+                                &mempool,
+                                &utxodb,
+                                &p2p_server,
+                            ) {
+                                Ok(()) => TransactionSubmitResponse {
+                                    success: true,
+                                    hash: hash.to_string(),
+                                    message: "ok".to_string(),
+                                    rejection: None,
+                                },
+                                Err(r) => TransactionSubmitResponse {
+                                    success: false,
+                                    hash: hash.to_string(),
+                                    message: format!("transaction rejected: {}", r),
+                                    rejection: Some(r),
+                                },
                             };
                             respond_json!(req, resp);
                         }
@@ -174,6 +975,126 @@ impl Server {
                             miner.step();
                             respond_result!(req, true, "ok");
                         }
+                        "/peer/banned" => {
+                            respond_json!(req, p2p_server.banned_peers());
+                        }
+                        "/transaction/submit" => {
+                            let mut req = req;
+                            if *req.method() != Method::Post {
+                                respond_result!(req, false, "transactions must be POSTed");
+                                return;
+                            }
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let encoding = match params.get("encoding") {
+                                Some(v) => v.clone(),
+                                None => "json".to_string(),
+                            };
+                            let mut body = String::new();
+                            if let Err(e) = req.as_reader().read_to_string(&mut body) {
+                                respond_result!(
+                                    req,
+                                    false,
+                                    format!("error reading request body: {}", e)
+                                );
+                                return;
+                            }
+                            let transaction = match decode_transaction(&encoding, &body) {
+                                Ok(t) => t,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let hash = transaction.hash();
+                            let resp = match new_transaction(
+                                transaction,
+                                &mempool,
+                                &utxodb,
+                                &p2p_server,
+                            ) {
+                                Ok(()) => TransactionSubmitResponse {
+                                    success: true,
+                                    hash: hash.to_string(),
+                                    message: "ok".to_string(),
+                                    rejection: None,
+                                },
+                                Err(r) => TransactionSubmitResponse {
+                                    success: false,
+                                    hash: hash.to_string(),
+                                    message: format!("transaction rejected: {}", r),
+                                    rejection: Some(r),
+                                },
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/events" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let since = match optional_param(&params, "since", 0) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let timeout = match optional_param(&params, "timeout", 0) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let limit = match optional_param(&params, "limit", DEFAULT_EVENTS_LIMIT)
+                            {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let batch = EVENTS.read(since, limit, Duration::from_millis(timeout));
+                            respond_json!(req, batch);
+                        }
+                        "/rpc" => {
+                            let mut req = req;
+                            if *req.method() != Method::Post {
+                                respond_result!(req, false, "RPC requests must be POSTed");
+                                return;
+                            }
+                            let mut body = String::new();
+                            if let Err(e) = req.as_reader().read_to_string(&mut body) {
+                                respond_result!(
+                                    req,
+                                    false,
+                                    format!("error reading request body: {}", e)
+                                );
+                                return;
+                            }
+                            let context = rpc::Context {
+                                wallet: &wallet,
+                                blockdb: &blockdb,
+                                blockchain: &blockchain,
+                                utxodb: &utxodb,
+                                mempool: &mempool,
+                                server: &p2p_server,
+                                miner: &miner,
+                                ledger: &ledger,
+                                transaction_generator: &transaction_generator_handle,
+                            };
+                            match rpc::handle(&body, &context) {
+                                Some(resp) => {
+                                    let content_type =
+                                        "Content-Type: application/json".parse::<Header>().unwrap();
+                                    req.respond(
+                                        Response::from_string(resp).with_header(content_type),
+                                    )
+                                    .unwrap();
+                                }
+                                // nothing to respond to notifications
+                                None => req.respond(Response::empty(204)).unwrap(),
+                            }
+                        }
                         "/telematics/snapshot" => {
                             respond_json!(req, PERFORMANCE_COUNTER.snapshot());
                         }
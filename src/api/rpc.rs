@@ -0,0 +1,736 @@
+use super::explorer;
+use super::{
+    combine_transactions, decode_transaction, parse_multisig, partial_transaction,
+    submit_own_transaction, wallet_accounts, wallet_multisig_locks, BlockchainSnapshotResponse,
+    UtxoSnapshotResponse, WalletAddressResponse, WalletBalanceResponse, WalletPublicKeyResponse,
+    WalletStatusResponse, DEFAULT_ADDRESS_COINS_LIMIT, DEFAULT_EVENTS_LIMIT,
+};
+use crate::blockchain::BlockChain;
+use crate::blockdb::BlockDatabase;
+use crate::crypto::hash::Hashable;
+use crate::event::EVENTS;
+use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crate::experiment::transaction_generator;
+use crate::handler::new_transaction;
+use crate::ledger_manager::Handle as LedgerHandle;
+use crate::miner::memory_pool::MemoryPool;
+use crate::miner::Handle as MinerHandle;
+use crate::network::server::Handle as ServerHandle;
+use crate::transaction::{CoinId, Transaction};
+use crate::utxodb::UtxoDatabase;
+use crate::wallet::{CoinSelection, EncryptedKeypair, Wallet, WalletError, DEFAULT_ACCOUNT};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Error codes defined by JSON-RPC 2.0
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+// Error codes of our own
+/// The requested block, transaction, coin or level does not exist.
+pub const NOT_FOUND: i64 = -32001;
+/// The submitted transaction is rejected. The reason is in the data of the error.
+pub const TRANSACTION_REJECTED: i64 = -32002;
+/// The node is not set up to serve the request.
+pub const UNAVAILABLE: i64 = -32003;
+/// The wallet can't create the transaction, e.g. because the balance is not enough.
+pub const WALLET_ERROR: i64 = -32004;
+
+/// Everything that the methods need from the node.
+pub struct Context<'a> {
+    pub wallet: &'a Wallet,
+    pub blockdb: &'a BlockDatabase,
+    pub blockchain: &'a BlockChain,
+    pub utxodb: &'a UtxoDatabase,
+    pub mempool: &'a Mutex<MemoryPool>,
+    pub server: &'a ServerHandle,
+    pub miner: &'a MinerHandle,
+    pub ledger: &'a LedgerHandle,
+    pub transaction_generator: &'a crossbeam::Sender<transaction_generator::ControlSignal>,
+}
+
+#[derive(Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Error {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl Error {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Response {
+    pub jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    pub id: Value,
+}
+
+impl Response {
+    fn new(id: Value, result: Result<Value, Error>) -> Self {
+        match result {
+            Ok(v) => Self {
+                jsonrpc: "2.0",
+                result: Some(v),
+                error: None,
+                id,
+            },
+            Err(e) => Self {
+                jsonrpc: "2.0",
+                result: None,
+                error: Some(e),
+                id,
+            },
+        }
+    }
+}
+
+// Parameters of the methods
+
+#[derive(Deserialize, Default)]
+pub struct AccountParams {
+    pub account: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WalletPayParams {
+    pub recipient: String,
+    pub value: u64,
+    /// Fee for the miners, 0 if missing.
+    pub fee: Option<u64>,
+    pub account: Option<String>,
+    /// One of `in-order`, `largest-first`, `smallest-first` and `branch-and-bound`.
+    pub selection: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PassphraseParams {
+    pub passphrase: String,
+}
+
+#[derive(Deserialize)]
+pub struct WalletUnlockParams {
+    pub passphrase: String,
+    /// Seconds until the wallet locks again. It stays unlocked if missing.
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct WalletExportParams {
+    pub address: String,
+    /// The passphrase to encrypt the exported key pair with.
+    pub passphrase: String,
+}
+
+#[derive(Deserialize)]
+pub struct WalletImportParams {
+    pub key: EncryptedKeypair,
+    pub passphrase: String,
+    pub account: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AddressParams {
+    pub address: String,
+}
+
+#[derive(Deserialize)]
+pub struct WalletMultisigAddParams {
+    pub threshold: u32,
+    /// Public keys in hex.
+    pub pubkeys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WalletMultisigCreateParams {
+    /// Address of the multisig lock to spend from.
+    pub address: String,
+    pub recipient: String,
+    pub value: u64,
+    pub fee: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct WalletSignParams {
+    pub transaction: Transaction,
+}
+
+#[derive(Deserialize)]
+pub struct WalletCombineParams {
+    pub transactions: Vec<Transaction>,
+}
+
+#[derive(Deserialize)]
+pub struct MinerStartParams {
+    pub lambda: u64,
+    pub lazy: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum TransactionSubmitParams {
+    Json { transaction: Transaction },
+    Hex { hex: String },
+    Base64 { base64: String },
+}
+
+#[derive(Deserialize)]
+pub struct HashParams {
+    pub hash: String,
+}
+
+#[derive(Deserialize)]
+pub struct TransactionWaitParams {
+    pub hash: String,
+    /// In milliseconds.
+    pub timeout: u64,
+}
+
+#[derive(Deserialize)]
+pub struct LevelParams {
+    pub level: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CoinParams {
+    pub hash: String,
+    pub index: u32,
+}
+
+#[derive(Deserialize)]
+pub struct AddressBalanceParams {
+    pub address: String,
+}
+
+#[derive(Deserialize)]
+pub struct AddressCoinsParams {
+    pub address: String,
+    #[serde(default)]
+    pub skip: usize,
+    pub limit: Option<usize>,
+}
+
+/// The node keeps the events in memory only, so a `since` from before a restart of the node reads
+/// from the oldest event instead.
+#[derive(Deserialize, Default)]
+pub struct EventsParams {
+    #[serde(default)]
+    pub since: u64,
+    /// In milliseconds.
+    #[serde(default)]
+    pub timeout: u64,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct TransactionGeneratorStartParams {
+    pub throttle: u64,
+}
+
+#[derive(Deserialize)]
+pub struct TransactionGeneratorStepParams {
+    pub count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "distribution", rename_all = "lowercase")]
+pub enum ArrivalDistributionParams {
+    Uniform { interval: u64 },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "distribution", rename_all = "lowercase")]
+pub enum ValueDistributionParams {
+    Uniform { min: u64, max: u64 },
+}
+
+// Results of the methods, in addition to the ones shared with the HTTP routes
+
+#[derive(Serialize)]
+pub struct TransactionSubmitResult {
+    pub hash: String,
+}
+
+/// Handle the body of a JSON-RPC request, which may be a single request or a batch. Returns
+/// `None` if there is nothing to respond, i.e. the requests are all notifications.
+pub fn handle(body: &str, context: &Context) -> Option<String> {
+    let value: Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => {
+            let resp = Response::new(Value::Null, Err(Error::new(PARSE_ERROR, e)));
+            return Some(serde_json::to_string_pretty(&resp).unwrap());
+        }
+    };
+    match value {
+        Value::Array(requests) => {
+            if requests.is_empty() {
+                let resp =
+                    Response::new(Value::Null, Err(Error::new(INVALID_REQUEST, "empty batch")));
+                return Some(serde_json::to_string_pretty(&resp).unwrap());
+            }
+            let resps: Vec<Response> = requests
+                .into_iter()
+                .filter_map(|r| handle_request(r, context))
+                .collect();
+            if resps.is_empty() {
+                None
+            } else {
+                Some(serde_json::to_string_pretty(&resps).unwrap())
+            }
+        }
+        request => {
+            handle_request(request, context).map(|r| serde_json::to_string_pretty(&r).unwrap())
+        }
+    }
+}
+
+/// Handle a single request, and return `None` if it is a notification.
+fn handle_request(request: Value, context: &Context) -> Option<Response> {
+    // a request without an id is a notification
+    let id = match &request {
+        Value::Object(o) => o.get("id").cloned(),
+        _ => Some(Value::Null),
+    };
+    let request: Request = match serde_json::from_value(request) {
+        Ok(r) => r,
+        Err(e) => {
+            return Some(Response::new(
+                id.unwrap_or(Value::Null),
+                Err(Error::new(INVALID_REQUEST, e)),
+            ))
+        }
+    };
+    if request.jsonrpc != "2.0" {
+        return Some(Response::new(
+            id.unwrap_or(Value::Null),
+            Err(Error::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")),
+        ));
+    }
+    let result = call(&request.method, request.params, context);
+    id.map(|id| Response::new(id, result))
+}
+
+/// Parse the parameters of a method. Missing parameters are the same as an empty object.
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    let params = match params {
+        Value::Null => Value::Object(Default::default()),
+        p => p,
+    };
+    serde_json::from_value(params).map_err(|e| Error::new(INVALID_PARAMS, e))
+}
+
+fn to_result<T: Serialize>(result: T) -> Result<Value, Error> {
+    Ok(serde_json::to_value(result).unwrap())
+}
+
+fn internal(e: impl ToString) -> Error {
+    Error::new(INTERNAL_ERROR, e)
+}
+
+fn parse_hash(value: &str, name: &str) -> Result<crate::crypto::hash::H256, Error> {
+    explorer::parse_hash(value, name).map_err(|e| Error::new(INVALID_PARAMS, e))
+}
+
+fn wallet_error(what: &str, e: WalletError) -> Error {
+    Error::new(WALLET_ERROR, format!("{}: {}", what, e))
+}
+
+fn found<T: Serialize>(result: Option<T>, what: &str) -> Result<Value, Error> {
+    match result {
+        Some(r) => to_result(r),
+        None => Err(Error::new(NOT_FOUND, format!("{} not found", what))),
+    }
+}
+
+fn control_transaction_generator(
+    context: &Context,
+    signal: transaction_generator::ControlSignal,
+) -> Result<Value, Error> {
+    match context.transaction_generator.send(signal) {
+        Ok(()) => Ok(Value::Null),
+        Err(e) => Err(internal(format!(
+            "error sending control signal to transaction generator: {}",
+            e
+        ))),
+    }
+}
+
+fn call(method: &str, p: Value, context: &Context) -> Result<Value, Error> {
+    match method {
+        "blockchain_snapshot" => {
+            let leaders = context.blockchain.proposer_leaders().map_err(internal)?;
+            to_result(BlockchainSnapshotResponse {
+                leaders: leaders.iter().map(|x| x.to_string()).collect(),
+            })
+        }
+        "utxo_snapshot" => {
+            let checksum = context.utxodb.snapshot().map_err(internal)?;
+            to_result(UtxoSnapshotResponse {
+                checksum: base64::encode(&checksum),
+            })
+        }
+        "wallet_balance" => {
+            let p: AccountParams = params(p)?;
+            let balance = match p.account {
+                Some(account) => context.wallet.account_balance(&account),
+                None => context.wallet.balance(),
+            };
+            to_result(WalletBalanceResponse {
+                balance: balance.map_err(internal)?,
+            })
+        }
+        "wallet_accounts" => to_result(wallet_accounts(context.wallet).map_err(internal)?),
+        "wallet_new_address" => {
+            let p: AccountParams = params(p)?;
+            let account = p.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+            let address = context
+                .wallet
+                .new_address(account)
+                .map_err(|e| wallet_error("error generating address", e))?;
+            to_result(WalletAddressResponse {
+                address: address.to_string(),
+            })
+        }
+        "wallet_status" => to_result(WalletStatusResponse {
+            encrypted: context.wallet.is_encrypted(),
+            locked: context.wallet.is_locked(),
+        }),
+        "wallet_encrypt" => {
+            let p: PassphraseParams = params(p)?;
+            context
+                .wallet
+                .encrypt(&p.passphrase)
+                .map_err(|e| wallet_error("error encrypting wallet", e))?;
+            Ok(Value::Null)
+        }
+        "wallet_unlock" => {
+            let p: WalletUnlockParams = params(p)?;
+            context
+                .wallet
+                .unlock(&p.passphrase, p.timeout.map(Duration::from_secs))
+                .map_err(|e| wallet_error("error unlocking wallet", e))?;
+            Ok(Value::Null)
+        }
+        "wallet_public_key" => {
+            let p: AddressParams = params(p)?;
+            let address = parse_hash(&p.address, "address")?;
+            let pubkey = context
+                .wallet
+                .public_key(&address)
+                .map_err(|e| wallet_error("error reading public key", e))?;
+            to_result(WalletPublicKeyResponse {
+                address: address.to_string(),
+                pubkey: hex::encode(&pubkey),
+            })
+        }
+        "wallet_multisig_list" => {
+            to_result(wallet_multisig_locks(context.wallet).map_err(internal)?)
+        }
+        "wallet_multisig_add" => {
+            let p: WalletMultisigAddParams = params(p)?;
+            let lock = parse_multisig(p.threshold, &p.pubkeys)
+                .map_err(|e| Error::new(INVALID_PARAMS, e))?;
+            let address = context
+                .wallet
+                .add_multisig(lock)
+                .map_err(|e| wallet_error("error adding multisig lock", e))?;
+            to_result(WalletAddressResponse {
+                address: address.to_string(),
+            })
+        }
+        "wallet_multisig_create" => {
+            let p: WalletMultisigCreateParams = params(p)?;
+            let address = parse_hash(&p.address, "address")?;
+            let recipient = parse_hash(&p.recipient, "recipient")?;
+            let transaction = context
+                .wallet
+                .create_multisig_transaction(&address, recipient, p.value, p.fee.unwrap_or(0))
+                .map_err(|e| wallet_error("error creating transaction", e))?;
+            to_result(partial_transaction(transaction))
+        }
+        "wallet_sign" => {
+            let p: WalletSignParams = params(p)?;
+            let transaction = context
+                .wallet
+                .sign_transaction(&p.transaction)
+                .map_err(|e| wallet_error("error signing transaction", e))?;
+            to_result(partial_transaction(transaction))
+        }
+        "wallet_combine" => {
+            let p: WalletCombineParams = params(p)?;
+            let transaction =
+                combine_transactions(&p.transactions).map_err(|e| Error::new(INVALID_PARAMS, e))?;
+            to_result(partial_transaction(transaction))
+        }
+        "wallet_lock" => {
+            context
+                .wallet
+                .lock()
+                .map_err(|e| wallet_error("error locking wallet", e))?;
+            Ok(Value::Null)
+        }
+        "wallet_export" => {
+            let p: WalletExportParams = params(p)?;
+            let address = parse_hash(&p.address, "address")?;
+            let key = context
+                .wallet
+                .export_keypair(&address, &p.passphrase)
+                .map_err(|e| wallet_error("error exporting key pair", e))?;
+            to_result(key)
+        }
+        "wallet_import" => {
+            let p: WalletImportParams = params(p)?;
+            let account = p.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+            let address = context
+                .wallet
+                .import_keypair(&p.key, &p.passphrase, account)
+                .map_err(|e| wallet_error("error importing key pair", e))?;
+            to_result(WalletAddressResponse {
+                address: address.to_string(),
+            })
+        }
+        "wallet_pay" => {
+            let p: WalletPayParams = params(p)?;
+            let recipient = parse_hash(&p.recipient, "recipient")?;
+            let selection = match p.selection {
+                Some(s) => s
+                    .parse::<CoinSelection>()
+                    .map_err(|e| Error::new(INVALID_PARAMS, e))?,
+                None => CoinSelection::default(),
+            };
+            let account = p.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+            let transaction = context
+                .wallet
+                .create_transaction(account, recipient, p.value, p.fee.unwrap_or(0), selection)
+                .map_err(|e| {
+                    Error::new(WALLET_ERROR, format!("error creating transaction: {}", e))
+                })?;
+            let hash = transaction.hash();
+            match submit_own_transaction(
+                transaction,
+                context.wallet,
+                context.mempool,
+                context.utxodb,
+                context.server,
+            ) {
+                Ok(()) => to_result(TransactionSubmitResult {
+                    hash: hash.to_string(),
+                }),
+                Err(r) => Err(Error {
+                    code: TRANSACTION_REJECTED,
+                    message: format!("transaction rejected: {}", r),
+                    data: Some(serde_json::to_value(r).unwrap()),
+                }),
+            }
+        }
+        "miner_start" => {
+            let p: MinerStartParams = params(p)?;
+            context.miner.start(p.lambda, p.lazy);
+            Ok(Value::Null)
+        }
+        "miner_step" => {
+            context.miner.step();
+            Ok(Value::Null)
+        }
+        "peer_banned" => to_result(context.server.banned_peers()),
+        "transaction_submit" => {
+            let transaction = match params(p)? {
+                TransactionSubmitParams::Json { transaction } => transaction,
+                TransactionSubmitParams::Hex { hex } => {
+                    decode_transaction("hex", &hex).map_err(|e| Error::new(INVALID_PARAMS, e))?
+                }
+                TransactionSubmitParams::Base64 { base64 } => decode_transaction("base64", &base64)
+                    .map_err(|e| Error::new(INVALID_PARAMS, e))?,
+            };
+            let hash = transaction.hash();
+            match new_transaction(transaction, context.mempool, context.utxodb, context.server) {
+                Ok(()) => to_result(TransactionSubmitResult {
+                    hash: hash.to_string(),
+                }),
+                Err(r) => Err(Error {
+                    code: TRANSACTION_REJECTED,
+                    message: format!("transaction rejected: {}", r),
+                    data: Some(serde_json::to_value(r).unwrap()),
+                }),
+            }
+        }
+        "transaction_get" => {
+            let p: HashParams = params(p)?;
+            let hash = parse_hash(&p.hash, "hash")?;
+            let resp = explorer::transaction(
+                &hash,
+                context.mempool,
+                context.blockdb,
+                context.blockchain,
+                context.utxodb,
+            )
+            .map_err(internal)?;
+            found(resp, "transaction")
+        }
+        "transaction_wait" => {
+            let p: TransactionWaitParams = params(p)?;
+            let hash = parse_hash(&p.hash, "hash")?;
+            let resp = explorer::wait_transaction(
+                &hash,
+                Duration::from_millis(p.timeout),
+                context.ledger,
+                context.mempool,
+                context.blockdb,
+                context.blockchain,
+                context.utxodb,
+            )
+            .map_err(internal)?;
+            found(resp, "transaction")
+        }
+        "block_get" => {
+            let p: HashParams = params(p)?;
+            let hash = parse_hash(&p.hash, "hash")?;
+            let resp =
+                explorer::block(&hash, context.blockdb, context.blockchain).map_err(internal)?;
+            found(resp, "block")
+        }
+        "proposer_leader" => {
+            let p: LevelParams = params(p)?;
+            let resp = explorer::leader(p.level, context.blockchain).map_err(internal)?;
+            found(resp, "confirmed level")
+        }
+        "utxo_coin" => {
+            let p: CoinParams = params(p)?;
+            let coin = CoinId {
+                hash: parse_hash(&p.hash, "hash")?,
+                index: p.index,
+            };
+            let resp = explorer::coin(&coin, context.utxodb).map_err(internal)?;
+            found(resp, "unspent coin")
+        }
+        "address_balance" => {
+            if !context.utxodb.has_address_index() {
+                return Err(Error::new(UNAVAILABLE, "address index is disabled"));
+            }
+            let p: AddressBalanceParams = params(p)?;
+            let address = parse_hash(&p.address, "address")?;
+            to_result(explorer::address_balance(&address, context.utxodb).map_err(internal)?)
+        }
+        "address_coins" => {
+            if !context.utxodb.has_address_index() {
+                return Err(Error::new(UNAVAILABLE, "address index is disabled"));
+            }
+            let p: AddressCoinsParams = params(p)?;
+            let address = parse_hash(&p.address, "address")?;
+            let limit = p.limit.unwrap_or(DEFAULT_ADDRESS_COINS_LIMIT);
+            to_result(
+                explorer::address_coins(&address, p.skip, limit, context.utxodb)
+                    .map_err(internal)?,
+            )
+        }
+        "events" => {
+            let p: EventsParams = params(p)?;
+            let limit = p.limit.unwrap_or(DEFAULT_EVENTS_LIMIT);
+            to_result(EVENTS.read(p.since, limit, Duration::from_millis(p.timeout)))
+        }
+        "telematics_snapshot" => to_result(PERFORMANCE_COUNTER.snapshot()),
+        "transaction_generator_start" => {
+            let p: TransactionGeneratorStartParams = params(p)?;
+            control_transaction_generator(
+                context,
+                transaction_generator::ControlSignal::Start(p.throttle),
+            )
+        }
+        "transaction_generator_stop" => {
+            control_transaction_generator(context, transaction_generator::ControlSignal::Stop)
+        }
+        "transaction_generator_step" => {
+            let p: TransactionGeneratorStepParams = params(p)?;
+            control_transaction_generator(
+                context,
+                transaction_generator::ControlSignal::Step(p.count),
+            )
+        }
+        "transaction_generator_set_arrival_distribution" => {
+            let distribution = match params(p)? {
+                ArrivalDistributionParams::Uniform { interval } => {
+                    transaction_generator::ArrivalDistribution::Uniform(
+                        transaction_generator::UniformArrival { interval },
+                    )
+                }
+            };
+            control_transaction_generator(
+                context,
+                transaction_generator::ControlSignal::SetArrivalDistribution(distribution),
+            )
+        }
+        "transaction_generator_set_value_distribution" => {
+            let distribution = match params(p)? {
+                ValueDistributionParams::Uniform { min, max } => {
+                    if min > max {
+                        return Err(Error::new(
+                            INVALID_PARAMS,
+                            "min value is bigger than max value",
+                        ));
+                    }
+                    transaction_generator::ValueDistribution::Uniform(
+                        transaction_generator::UniformValue { min, max },
+                    )
+                }
+            };
+            control_transaction_generator(
+                context,
+                transaction_generator::ControlSignal::SetValueDistribution(distribution),
+            )
+        }
+        m => Err(Error::new(
+            METHOD_NOT_FOUND,
+            format!("method not found: {}", m),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_params() {
+        // missing parameters are the same as an empty object
+        let p: EventsParams = params(Value::Null).unwrap();
+        assert_eq!(p.since, 0);
+        assert!(p.limit.is_none());
+        let p: TransactionSubmitParams = params(serde_json::json!({"hex": "00"})).unwrap();
+        assert!(match p {
+            TransactionSubmitParams::Hex { hex } => hex == "00",
+            _ => false,
+        });
+        let e = params::<LevelParams>(serde_json::json!({"level": "x"})).err();
+        assert_eq!(e.unwrap().code, INVALID_PARAMS);
+        let e = params::<ValueDistributionParams>(
+            serde_json::json!({"distribution": "normal", "min": 1, "max": 2}),
+        )
+        .err();
+        assert_eq!(e.unwrap().code, INVALID_PARAMS);
+    }
+}
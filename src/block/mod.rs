@@ -2,8 +2,10 @@ pub mod header;
 pub mod proposer;
 pub mod transaction;
 pub mod voter;
+use crate::config::{FIRST_VOTER_INDEX, PROPOSER_INDEX, TRANSACTION_INDEX};
 use crate::crypto::hash::{Hashable, H256};
 use crate::experiment::performance_counter::PayloadSize;
+use crate::transaction::Address;
 
 /// A block in the Prism blockchain.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,6 +31,7 @@ impl Block {
         content: Content,
         extra_content: [u8; 32],
         difficulty: H256,
+        miner: Address,
     ) -> Self {
         let header = header::Header::new(
             parent,
@@ -37,6 +40,7 @@ impl Block {
             content_merkle_root,
             extra_content,
             difficulty,
+            miner,
         );
         Self {
             header,
@@ -45,6 +49,16 @@ impl Block {
         }
     }
 
+    /// Get the header of the block along with its sortition proof, without the content.
+    pub fn header_proof(&self) -> HeaderProof {
+        HeaderProof {
+            header: self.header,
+            sortition_id: self.content.sortition_id(),
+            content_hash: self.content.hash(),
+            sortition_proof: self.sortition_proof.clone(),
+        }
+    }
+
     // TODO: use another name
     /// Create a new block from header.
     pub fn from_header(
@@ -67,6 +81,74 @@ impl Hashable for Block {
     }
 }
 
+/// A block or a part of a block whose PoW and sortition could be checked.
+pub trait SortitionProof: Hashable {
+    /// The header of the block.
+    fn header(&self) -> &header::Header;
+    /// The sortition id that the content claims.
+    fn sortition_id(&self) -> u16;
+    /// The hash of the content.
+    fn content_hash(&self) -> H256;
+    /// The Merkle proof of the content hash against the content Merkle root in the header.
+    fn sortition_proof(&self) -> &[H256];
+}
+
+impl SortitionProof for Block {
+    fn header(&self) -> &header::Header {
+        &self.header
+    }
+
+    fn sortition_id(&self) -> u16 {
+        self.content.sortition_id()
+    }
+
+    fn content_hash(&self) -> H256 {
+        self.content.hash()
+    }
+
+    fn sortition_proof(&self) -> &[H256] {
+        &self.sortition_proof
+    }
+}
+
+/// The header of a block along with the hash and the sortition proof of its content. It allows us
+/// to check the PoW and the sortition of a block before downloading its content.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeaderProof {
+    /// The header of the block.
+    pub header: header::Header,
+    /// The sortition id that the content claims, which tells the type of the block.
+    pub sortition_id: u16,
+    /// The hash of the content.
+    pub content_hash: H256,
+    /// The sortition proof of the content.
+    pub sortition_proof: Vec<H256>,
+}
+
+impl Hashable for HeaderProof {
+    fn hash(&self) -> H256 {
+        self.header.hash()
+    }
+}
+
+impl SortitionProof for HeaderProof {
+    fn header(&self) -> &header::Header {
+        &self.header
+    }
+
+    fn sortition_id(&self) -> u16 {
+        self.sortition_id
+    }
+
+    fn content_hash(&self) -> H256 {
+        self.content_hash
+    }
+
+    fn sortition_proof(&self) -> &[H256] {
+        &self.sortition_proof
+    }
+}
+
 impl PayloadSize for Block {
     fn size(&self) -> usize {
         std::mem::size_of::<header::Header>()
@@ -97,6 +179,17 @@ impl Hashable for Content {
     }
 }
 
+impl Content {
+    /// Get the sortition id that this type of content should have.
+    pub fn sortition_id(&self) -> u16 {
+        match self {
+            Content::Proposer(_) => PROPOSER_INDEX,
+            Content::Transaction(_) => TRANSACTION_INDEX,
+            Content::Voter(c) => c.chain_number + FIRST_VOTER_INDEX,
+        }
+    }
+}
+
 impl PayloadSize for Content {
     fn size(&self) -> usize {
         // TODO: we are not counting the 2 bits that are used to store block type
@@ -144,6 +237,7 @@ pub mod tests {
             content,
             [0u8; 32],
             *config::DEFAULT_DIFFICULTY,
+            Address::default(),
         )
     }
 
@@ -169,6 +263,7 @@ pub mod tests {
             content,
             [0u8; 32],
             *config::DEFAULT_DIFFICULTY,
+            Address::default(),
         )
     }
 
@@ -188,6 +283,7 @@ pub mod tests {
             content,
             [0u8; 32],
             *config::DEFAULT_DIFFICULTY,
+            Address::default(),
         )
     }
 }
@@ -12,8 +12,6 @@ pub struct Content {
     pub transaction_refs: Vec<H256>,
     /// List of proposer blocks referred by this proposer block.
     pub proposer_refs: Vec<H256>,
-    // TODO: coinbase transaction, and maybe refer to voter blocks to include their coinbase
-    // transactions.
 }
 
 impl Content {
@@ -43,8 +41,9 @@ impl Hashable for Content {
     }
 }
 
-/// Generate the genesis block of the proposer chain.
-pub fn genesis() -> Block {
+/// Generate the genesis block of the proposer chain, which carries the digest of the genesis in
+/// its extra content.
+pub fn genesis(digest: H256) -> Block {
     let content = Content {
         transaction_refs: vec![],
         proposer_refs: vec![],
@@ -58,8 +57,9 @@ pub fn genesis() -> Block {
         all_zero.into(),
         vec![],
         BlockContent::Proposer(content),
-        all_zero,
+        digest.into(),
         *DEFAULT_DIFFICULTY,
+        all_zero.into(),
     )
 }
 
@@ -6,7 +6,7 @@ use crate::transaction::Transaction;
 /// The content of a transaction block.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Content {
-    pub transactions: Vec<Transaction>, // TODO: No coinbase transaction for now
+    pub transactions: Vec<Transaction>,
 }
 
 impl Content {
@@ -47,8 +47,9 @@ impl Hashable for Content {
     }
 }
 
-/// Generate the genesis block of the voter chain with the given chain ID.
-pub fn genesis(chain_num: u16) -> Block {
+/// Generate the genesis block of the voter chain with the given chain ID, which carries the digest
+/// of the genesis in its extra content.
+pub fn genesis(chain_num: u16, digest: H256) -> Block {
     let all_zero: [u8; 32] = [0; 32];
     let content = Content {
         chain_number: chain_num,
@@ -64,8 +65,9 @@ pub fn genesis(chain_num: u16) -> Block {
         all_zero.into(),
         vec![],
         BlockContent::Voter(content),
-        all_zero,
+        digest.into(),
         *DEFAULT_DIFFICULTY,
+        all_zero.into(),
     )
 }
 
@@ -1,6 +1,8 @@
 use crate::block::{Block, Content};
 use crate::config::*;
 use crate::crypto::hash::{Hashable, H256};
+use crate::event::{Event, EVENTS};
+use crate::utxodb::UtxoDatabase;
 
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use bincode::{deserialize, serialize};
@@ -26,6 +28,18 @@ const PROPOSER_LEDGER_ORDER_CF: &str = "PROPOSER_LEDGER_ORDER"; // level (u64) t
                                                                 // by this level, including the leader itself. The list
                                                                 // is in the order that those blocks should live in the ledger.
 const PROPOSER_VOTE_COUNT_CF: &str = "PROPOSER_VOTE_COUNT"; // number of all votes on a block
+const VOTER_LEDGER_TIP_CF: &str = "VOTER_LEDGER_TIP"; // chain number (u16) to the voter block whose votes
+                                                      // are applied to PROPOSER_NODE_VOTE_CF (hash)
+const LEDGER_UPDATE_PROGRESS_CF: &str = "LEDGER_UPDATE_PROGRESS"; // work left by an ongoing ledger update
+const TRANSACTION_BLOCK_CF: &str = "TRANSACTION_BLOCK"; // transaction hash to transaction blocks carrying it (Vec<hash>)
+const TRANSACTION_REFERRER_CF: &str = "TRANSACTION_REFERRER"; // transaction block to proposer blocks referring it (Vec<hash>)
+const TRANSACTION_LEDGER_POSITION_CF: &str = "TRANSACTION_LEDGER_POSITION"; // transaction block to the proposer blocks
+                                                                            // in the ledger that refer to it, and where
+                                                                            // it was if it left the ledger (LedgerPosition)
+
+// Keys in LEDGER_UPDATE_PROGRESS_CF
+const UNFINISHED_LEADER_RANGE_KEY: &[u8] = b"leader"; // range of levels to recompute the leader (u64, u64)
+const UNFINISHED_LEDGER_BEGIN_KEY: &[u8] = b"ledger"; // first level to recompute the ledger (u64)
 
 // Column family names for graph neighbors
 const PARENT_NEIGHBOR_CF: &str = "GRAPH_PARENT_NEIGHBOR"; // the proposer parent of a block
@@ -36,6 +50,129 @@ const PROPOSER_REF_NEIGHBOR_CF: &str = "GRAPH_PROPOSER_REF_NEIGHBOR";
 
 pub type Result<T> = std::result::Result<T, rocksdb::Error>;
 
+/// Where a transaction block is in the ledger.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct LedgerPosition {
+    /// The proposer blocks in the ledger that refer to the transaction block, and their ledger
+    /// levels, in the order that they entered the ledger. The first one puts it in the ledger.
+    confirmed_by: Vec<(H256, u64)>,
+    /// The proposer block that put the transaction block in the ledger last, and its ledger
+    /// level, if the transaction block has left the ledger.
+    deconfirmed_by: Option<(H256, u64)>,
+}
+
+impl LedgerPosition {
+    /// Note that a proposer block at the given ledger level refers to the transaction block.
+    fn confirm(&mut self, proposer: H256, level: u64) {
+        self.confirmed_by.push((proposer, level));
+        self.deconfirmed_by = None;
+    }
+
+    /// Get the proposer block that put the transaction block in the ledger, if it is in the
+    /// ledger.
+    fn first_referrer(&self) -> Option<H256> {
+        self.confirmed_by.first().map(|(proposer, _)| *proposer)
+    }
+
+    /// Note that a proposer block that refers to the transaction block left the ledger. The
+    /// transaction block stays in the ledger as long as another proposer block there refers to it.
+    fn deconfirm(&mut self, proposer: &H256) {
+        if let Some(i) = self.confirmed_by.iter().position(|(p, _)| p == proposer) {
+            let removed = self.confirmed_by.remove(i);
+            if self.confirmed_by.is_empty() {
+                self.deconfirmed_by = Some(removed);
+            }
+        }
+    }
+}
+
+/// Where a transaction is in the blockchain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionStatus {
+    /// Carried by a transaction block that no proposer block refers to.
+    Included { block: H256 },
+    /// Carried by a transaction block that a proposer block refers to, but not in the ledger, or
+    /// in the ledger but not applied to the UTXO set yet.
+    Referred { block: H256, proposer: H256 },
+    /// In the ledger, through the proposer block confirmed at the given ledger level.
+    Confirmed {
+        block: H256,
+        proposer: H256,
+        level: u64,
+    },
+    /// In the ledger like `Confirmed`, but does not apply to the UTXO set, e.g. because it spends
+    /// a coin that an earlier transaction of the ledger spent.
+    Rejected {
+        block: H256,
+        proposer: H256,
+        level: u64,
+    },
+    /// Was in the ledger through the proposer block at the given ledger level, but the leader of
+    /// that level has changed.
+    Deconfirmed {
+        block: H256,
+        proposer: H256,
+        level: u64,
+    },
+}
+
+impl TransactionStatus {
+    /// How far the transaction has got, so that we can report the most advanced status of a
+    /// transaction carried by several transaction blocks.
+    fn progress(&self) -> u8 {
+        match self {
+            TransactionStatus::Included { .. } => 0,
+            TransactionStatus::Referred { .. } => 1,
+            TransactionStatus::Deconfirmed { .. } => 2,
+            TransactionStatus::Rejected { .. } => 3,
+            TransactionStatus::Confirmed { .. } => 4,
+        }
+    }
+}
+
+/// The blocks that enter and leave the ledger in an update of the ledger, each in the order that
+/// they enter or entered the ledger.
+#[derive(Debug, Clone, Default)]
+pub struct LedgerDiff {
+    /// Voter blocks that join the main chains whose votes the ledger counts.
+    pub added_voters: Vec<H256>,
+    /// Voter blocks that leave the main chains whose votes the ledger counts.
+    pub removed_voters: Vec<H256>,
+    /// Proposer blocks that enter the ledger, along with the transaction blocks that enter the
+    /// ledger with them. A transaction block that several proposer blocks refer to enters the
+    /// ledger with the first one, and is not listed again for the others.
+    pub added_proposers: Vec<(H256, Vec<H256>)>,
+    /// Proposer blocks that leave the ledger, along with the transaction blocks that leave the
+    /// ledger with them, which are those that entered the ledger with them and that no other
+    /// proposer block in the ledger refers to.
+    pub removed_proposers: Vec<(H256, Vec<H256>)>,
+}
+
+impl LedgerDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_voters.is_empty()
+            && self.removed_voters.is_empty()
+            && self.added_proposers.is_empty()
+            && self.removed_proposers.is_empty()
+    }
+
+    /// Get the transaction blocks that enter the ledger.
+    pub fn added_transaction_blocks(&self) -> Vec<H256> {
+        self.added_proposers
+            .iter()
+            .flat_map(|(_, t)| t.iter().copied())
+            .collect()
+    }
+
+    /// Get the transaction blocks that leave the ledger.
+    pub fn removed_transaction_blocks(&self) -> Vec<H256> {
+        self.removed_proposers
+            .iter()
+            .flat_map(|(_, t)| t.iter().copied())
+            .collect()
+    }
+}
+
 // cf_handle is a lightweight operation, it takes 44000 micro seconds to get 100000 cf handles
 
 pub struct BlockChain {
@@ -47,6 +184,10 @@ pub struct BlockChain {
     unconfirmed_proposers: Mutex<HashSet<H256>>,
     proposer_ledger_tip: Mutex<u64>,
     voter_ledger_tips: Mutex<Vec<H256>>,
+    /// Levels whose leader was left to recompute by an interrupted ledger update.
+    unfinished_leader_range: Mutex<Option<Range<u64>>>,
+    /// First level whose ledger was left to recompute by an interrupted ledger update.
+    unfinished_ledger_begin: Mutex<Option<u64>>,
     config: BlockchainConfig,
 }
 
@@ -85,6 +226,11 @@ impl BlockChain {
         add_cf!(VOTER_PARENT_NEIGHBOR_CF, h256_vec_append_merge);
         add_cf!(TRANSACTION_REF_NEIGHBOR_CF, h256_vec_append_merge);
         add_cf!(PROPOSER_REF_NEIGHBOR_CF, h256_vec_append_merge);
+        add_cf!(VOTER_LEDGER_TIP_CF);
+        add_cf!(LEDGER_UPDATE_PROGRESS_CF);
+        add_cf!(TRANSACTION_BLOCK_CF, h256_vec_append_merge);
+        add_cf!(TRANSACTION_REFERRER_CF, h256_vec_append_merge);
+        add_cf!(TRANSACTION_LEDGER_POSITION_CF);
 
         let mut opts = Options::default();
         opts.create_if_missing(true);
@@ -104,6 +250,8 @@ impl BlockChain {
             unconfirmed_proposers: Mutex::new(HashSet::new()),
             proposer_ledger_tip: Mutex::new(0),
             voter_ledger_tips: Mutex::new(vec![H256::default(); config.voter_chains as usize]),
+            unfinished_leader_range: Mutex::new(None),
+            unfinished_ledger_begin: Mutex::new(None),
             config,
         };
 
@@ -129,6 +277,7 @@ impl BlockChain {
         let proposer_ledger_order_cf = db.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
         let proposer_ref_neighbor_cf = db.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
         let transaction_ref_neighbor_cf = db.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+        let voter_ledger_tip_cf = db.db.cf_handle(VOTER_LEDGER_TIP_CF).unwrap();
 
         // insert genesis blocks
         let mut wb = WriteBatch::default();
@@ -212,6 +361,11 @@ impl BlockChain {
                 serialize(&(chain_num as u16, 0 as u64)).unwrap(),
                 serialize(&(1 as u64)).unwrap(),
             )?;
+            wb.put_cf(
+                voter_ledger_tip_cf,
+                serialize(&(chain_num as u16)).unwrap(),
+                serialize(&db.config.voter_genesis[chain_num as usize]).unwrap(),
+            )?;
             let mut voter_best = db.voter_best[chain_num as usize].lock().unwrap();
             voter_best.0 = db.config.voter_genesis[chain_num as usize];
             drop(voter_best);
@@ -223,6 +377,163 @@ impl BlockChain {
         Ok(db)
     }
 
+    /// Load an existing database at the given path, and rebuild the metadata fields from its
+    /// content.
+    pub fn load<P: AsRef<std::path::Path>>(path: P, config: BlockchainConfig) -> Result<Self> {
+        let db = Self::open(&path, config)?;
+        // get cf handles
+        let proposer_node_level_cf = db.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
+        let voter_node_level_cf = db.db.cf_handle(VOTER_NODE_LEVEL_CF).unwrap();
+        let voter_node_chain_cf = db.db.cf_handle(VOTER_NODE_CHAIN_CF).unwrap();
+        let parent_neighbor_cf = db.db.cf_handle(PARENT_NEIGHBOR_CF).unwrap();
+        let proposer_ledger_order_cf = db.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
+        let proposer_ref_neighbor_cf = db.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
+        let transaction_ref_neighbor_cf = db.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+        let voter_ledger_tip_cf = db.db.cf_handle(VOTER_LEDGER_TIP_CF).unwrap();
+        let ledger_update_progress_cf = db.db.cf_handle(LEDGER_UPDATE_PROGRESS_CF).unwrap();
+
+        // proposer blocks and the best proposer level
+        let mut proposers: HashSet<H256> = HashSet::new();
+        let mut proposer_best_level: u64 = 0;
+        for (k, v) in db
+            .db
+            .iterator_cf(proposer_node_level_cf, rocksdb::IteratorMode::Start)?
+        {
+            let hash: H256 = deserialize(k.as_ref()).unwrap();
+            let level: u64 = deserialize(v.as_ref()).unwrap();
+            if level > proposer_best_level {
+                proposer_best_level = level;
+            }
+            proposers.insert(hash);
+        }
+        *db.proposer_best_level.lock().unwrap() = proposer_best_level;
+
+        // voter blocks and the best voter of each chain. among the voters at the same level, the
+        // one that arrived first is lost, so we pick the smallest hash to be deterministic
+        let mut voters: HashSet<H256> = HashSet::new();
+        for chain_num in 0..db.config.voter_chains {
+            let mut voter_best = db.voter_best[chain_num as usize].lock().unwrap();
+            *voter_best = (db.config.voter_genesis[chain_num as usize], 0);
+        }
+        for (k, v) in db
+            .db
+            .iterator_cf(voter_node_chain_cf, rocksdb::IteratorMode::Start)?
+        {
+            let hash: H256 = deserialize(k.as_ref()).unwrap();
+            let chain: u16 = deserialize(v.as_ref()).unwrap();
+            voters.insert(hash);
+            if chain >= db.config.voter_chains {
+                continue;
+            }
+            let level: u64 = match db.db.get_pinned_cf(voter_node_level_cf, k.as_ref())? {
+                Some(d) => deserialize(&d).unwrap(),
+                None => unreachable!("voter should have level"),
+            };
+            let mut voter_best = db.voter_best[chain as usize].lock().unwrap();
+            if level > voter_best.1 {
+                *voter_best = (hash, level);
+            }
+        }
+
+        // proposer blocks that are not referred by any other proposer block
+        let mut unreferred_proposers = proposers.clone();
+        for (_, v) in db
+            .db
+            .iterator_cf(proposer_ref_neighbor_cf, rocksdb::IteratorMode::Start)?
+        {
+            let refs: Vec<H256> = deserialize(v.as_ref()).unwrap();
+            for r in &refs {
+                unreferred_proposers.remove(r);
+            }
+        }
+        *db.unreferred_proposers.lock().unwrap() = unreferred_proposers;
+
+        // transaction blocks are the blocks with a parent that are neither proposer nor voter
+        // blocks
+        let mut unreferred_transactions: HashSet<H256> = HashSet::new();
+        for (k, _) in db
+            .db
+            .iterator_cf(parent_neighbor_cf, rocksdb::IteratorMode::Start)?
+        {
+            let hash: H256 = deserialize(k.as_ref()).unwrap();
+            if !proposers.contains(&hash) && !voters.contains(&hash) {
+                unreferred_transactions.insert(hash);
+            }
+        }
+        for (_, v) in db
+            .db
+            .iterator_cf(transaction_ref_neighbor_cf, rocksdb::IteratorMode::Start)?
+        {
+            let refs: Vec<H256> = deserialize(v.as_ref()).unwrap();
+            for r in &refs {
+                unreferred_transactions.remove(r);
+            }
+        }
+        *db.unreferred_transactions.lock().unwrap() = unreferred_transactions;
+
+        // the ledger is continuous from level 0, and the proposer blocks in it are confirmed
+        let mut unconfirmed_proposers = proposers;
+        let mut proposer_ledger_tip: u64 = 0;
+        for level in 0u64.. {
+            match db
+                .db
+                .get_pinned_cf(proposer_ledger_order_cf, serialize(&level).unwrap())?
+            {
+                Some(d) => {
+                    let order: Vec<H256> = deserialize(&d).unwrap();
+                    for hash in &order {
+                        unconfirmed_proposers.remove(hash);
+                    }
+                    proposer_ledger_tip = level;
+                }
+                None => break,
+            }
+        }
+        *db.proposer_ledger_tip.lock().unwrap() = proposer_ledger_tip;
+        *db.unconfirmed_proposers.lock().unwrap() = unconfirmed_proposers;
+
+        // voter blocks whose votes are counted in the ledger
+        let mut voter_ledger_tips = db.voter_ledger_tips.lock().unwrap();
+        for chain_num in 0..db.config.voter_chains {
+            voter_ledger_tips[chain_num as usize] = match db
+                .db
+                .get_pinned_cf(voter_ledger_tip_cf, serialize(&chain_num).unwrap())?
+            {
+                Some(d) => deserialize(&d).unwrap(),
+                None => db.config.voter_genesis[chain_num as usize],
+            };
+        }
+        drop(voter_ledger_tips);
+
+        // pick up the work left by an interrupted ledger update, it will be finished by the
+        // next ledger update
+        if let Some(d) = db
+            .db
+            .get_pinned_cf(ledger_update_progress_cf, UNFINISHED_LEADER_RANGE_KEY)?
+        {
+            let (start, end): (u64, u64) = deserialize(&d).unwrap();
+            warn!(
+                "Resuming an interrupted ledger update, recomputing leaders of levels {} to {}",
+                start,
+                end - 1
+            );
+            *db.unfinished_leader_range.lock().unwrap() = Some(Range { start, end });
+        }
+        if let Some(d) = db
+            .db
+            .get_pinned_cf(ledger_update_progress_cf, UNFINISHED_LEDGER_BEGIN_KEY)?
+        {
+            let begin: u64 = deserialize(&d).unwrap();
+            warn!(
+                "Resuming an interrupted ledger update, recomputing the ledger from level {}",
+                begin
+            );
+            *db.unfinished_ledger_begin.lock().unwrap() = Some(begin);
+        }
+
+        Ok(db)
+    }
+
     /// Insert a new block into the ledger. Returns the list of added transaction blocks and
     /// removed transaction blocks.
     pub fn insert_block(&self, block: &Block) -> Result<()> {
@@ -239,6 +550,8 @@ impl BlockChain {
         let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
         let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
         let voter_tree_level_count_cf = self.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
+        let transaction_block_cf = self.db.cf_handle(TRANSACTION_BLOCK_CF).unwrap();
+        let transaction_referrer_cf = self.db.cf_handle(TRANSACTION_REFERRER_CF).unwrap();
 
         let mut wb = WriteBatch::default();
 
@@ -283,6 +596,9 @@ impl BlockChain {
                     block_hash,
                     content.transaction_refs
                 );
+                for ref_hash in &content.transaction_refs {
+                    merge_value!(transaction_referrer_cf, ref_hash, block_hash);
+                }
                 // get current block level
                 let parent_level: u64 = get_value!(proposer_node_level_cf, parent_hash);
                 let self_level = parent_level + 1;
@@ -319,6 +635,10 @@ impl BlockChain {
                 if self_level > *proposer_best {
                     *proposer_best = self_level;
                     PERFORMANCE_COUNTER.record_update_proposer_main_chain(self_level as usize);
+                    EVENTS.publish(Event::ProposerMainChain {
+                        level: self_level,
+                        hash: block_hash.to_string(),
+                    });
                 }
                 drop(proposer_best);
 
@@ -396,7 +716,12 @@ impl BlockChain {
                     block_hash, self_chain, self_level
                 );
             }
-            Content::Transaction(_content) => {
+            Content::Transaction(content) => {
+                // index the transactions by the block carrying them
+                for transaction in &content.transactions {
+                    merge_value!(transaction_block_cf, transaction.hash(), block_hash);
+                }
+
                 // mark itself as unreferred
                 // Note that this could happen before committing to db, because no module will try
                 // to access transaction content based on pointers in unreferred_transactions.
@@ -404,20 +729,23 @@ impl BlockChain {
                 unreferred_transactions.insert(block_hash);
                 drop(unreferred_transactions);
 
-                // This db write is only to facilitate check_existence
                 self.db.write(wb)?;
             }
         }
         Ok(())
     }
 
-    pub fn update_ledger(&self) -> Result<(Vec<H256>, Vec<H256>)> {
+    pub fn update_ledger(&self) -> Result<LedgerDiff> {
         let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
         let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
         let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
         let proposer_ledger_order_cf = self.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
         let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
         let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+        let voter_ledger_tip_cf = self.db.cf_handle(VOTER_LEDGER_TIP_CF).unwrap();
+        let ledger_update_progress_cf = self.db.cf_handle(LEDGER_UPDATE_PROGRESS_CF).unwrap();
+        let transaction_ledger_position_cf =
+            self.db.cf_handle(TRANSACTION_LEDGER_POSITION_CF).unwrap();
 
         macro_rules! get_value {
             ($cf:expr, $key:expr) => {{
@@ -436,6 +764,7 @@ impl BlockChain {
             }};
         }
 
+        let mut ledger_diff = LedgerDiff::default();
         let mut voter_ledger_tips = self.voter_ledger_tips.lock().unwrap();
         let mut affected_range: Range<u64> = Range {
             start: std::u64::MAX,
@@ -449,8 +778,16 @@ impl BlockChain {
             let to = voter_best.0;
             drop(voter_best);
             voter_ledger_tips[chain_num as usize] = to;
+            wb.put_cf(
+                voter_ledger_tip_cf,
+                serialize(&chain_num).unwrap(),
+                serialize(&to).unwrap(),
+            )?;
 
             let (added, removed) = self.vote_diff(from, to)?;
+            let (added_voters, removed_voters) = self.voter_chain_diff(from, to)?;
+            ledger_diff.added_voters.extend(added_voters);
+            ledger_diff.removed_voters.extend(removed_voters);
 
             // apply the vote diff on the proposer main chain vote cf
             for vote in &removed {
@@ -484,6 +821,25 @@ impl BlockChain {
             }
         }
         drop(voter_ledger_tips);
+
+        // include the levels left by an interrupted ledger update
+        if let Some(unfinished) = self.unfinished_leader_range.lock().unwrap().take() {
+            if unfinished.start < affected_range.start {
+                affected_range.start = unfinished.start;
+            }
+            if unfinished.end > affected_range.end {
+                affected_range.end = unfinished.end;
+            }
+        }
+        // record the levels whose leader we are going to recompute, so that we can finish the
+        // job should we crash before the new leaders are committed
+        if affected_range.start < affected_range.end {
+            wb.put_cf(
+                ledger_update_progress_cf,
+                UNFINISHED_LEADER_RANGE_KEY,
+                serialize(&(affected_range.start, affected_range.end)).unwrap(),
+            )?;
+        }
         // commit the votes into the database
         self.db.write(wb)?;
 
@@ -546,6 +902,10 @@ impl BlockChain {
                     ),
                     None => warn!("Proposer leader deconfirmed for level {}", level),
                 }
+                EVENTS.publish(Event::NewLeader {
+                    level,
+                    leader: new_leader.map(|h| h.to_string()),
+                });
                 // mark it's the beginning of the change
                 if change_begin.is_none() {
                     change_begin = Some(level);
@@ -556,6 +916,22 @@ impl BlockChain {
                 };
             }
         }
+        // include the ledger left by an interrupted ledger update
+        if let Some(unfinished) = self.unfinished_ledger_begin.lock().unwrap().take() {
+            change_begin = match change_begin {
+                Some(begin) if begin <= unfinished => Some(begin),
+                _ => Some(unfinished),
+            };
+        }
+        // the leaders are done, and record where we are going to recompute the ledger from
+        wb.delete_cf(ledger_update_progress_cf, UNFINISHED_LEADER_RANGE_KEY)?;
+        if let Some(change_begin) = change_begin {
+            wb.put_cf(
+                ledger_update_progress_cf,
+                UNFINISHED_LEDGER_BEGIN_KEY,
+                serialize(&change_begin).unwrap(),
+            )?;
+        }
         // commit the new leaders into the database
         self.db.write(wb)?;
 
@@ -563,8 +939,6 @@ impl BlockChain {
         if let Some(change_begin) = change_begin {
             let mut proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
             let mut unconfirmed_proposers = self.unconfirmed_proposers.lock().unwrap();
-            let mut removed: Vec<H256> = vec![];
-            let mut added: Vec<H256> = vec![];
             let mut wb = WriteBatch::default();
             /*
             macro_rules! merge_value {
@@ -584,6 +958,24 @@ impl BlockChain {
                 }};
             }
 
+            // the ledger positions of the transaction blocks we touch, since a transaction block
+            // may leave the ledger and come back in this update, and the write batch is not
+            // readable. we also keep the proposer block that put each one in the ledger before
+            // this update
+            let mut positions: HashMap<H256, (Option<H256>, LedgerPosition)> = HashMap::new();
+            macro_rules! position {
+                ($block:expr) => {{
+                    if !positions.contains_key($block) {
+                        let position: LedgerPosition =
+                            get_value!(transaction_ledger_position_cf, $block).unwrap_or_default();
+                        positions.insert(*$block, (position.first_referrer(), position));
+                    }
+                    &mut positions.get_mut($block).unwrap().1
+                }};
+            }
+            let mut removed_proposers: Vec<(H256, Vec<H256>)> = vec![];
+            let mut added_proposers: Vec<(H256, Vec<H256>)> = vec![];
+
             // deconfirm the blocks from change_begin all the way to previous ledger tip
             for level in change_begin..=*proposer_ledger_tip {
                 let original_ledger: Vec<H256> =
@@ -591,7 +983,11 @@ impl BlockChain {
                 delete_value!(proposer_ledger_order_cf, level as u64);
                 for block in &original_ledger {
                     unconfirmed_proposers.insert(*block);
-                    removed.push(*block);
+                    let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
+                    for transaction_block in &t {
+                        position!(transaction_block).deconfirm(block);
+                    }
+                    removed_proposers.push((*block, t));
                 }
             }
 
@@ -633,27 +1029,37 @@ impl BlockChain {
                         .into_iter()
                         .filter(|h| unconfirmed_proposers.remove(h))
                         .collect();
+                    for block in &order {
+                        let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
+                        for transaction_block in &t {
+                            position!(transaction_block).confirm(*block, level);
+                        }
+                        added_proposers.push((*block, t));
+                    }
                     put_value!(proposer_ledger_order_cf, level as u64, order);
-                    added.extend(&order);
                 }
             }
-            // commit the new ledger into the database
-            self.db.write(wb)?;
-
-            let mut removed_transaction_blocks: Vec<H256> = vec![];
-            let mut added_transaction_blocks: Vec<H256> = vec![];
-            for block in &removed {
-                let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
-                removed_transaction_blocks.extend(&t);
+            // the transactions of a transaction block, and its reward, apply once no matter how
+            // many proposer blocks refer to it. so a transaction block leaves the ledger with the
+            // proposer block that put it there, and enters with the one that puts it there now
+            for (block, mut t) in removed_proposers {
+                t.retain(|transaction_block| positions[transaction_block].0 == Some(block));
+                ledger_diff.removed_proposers.push((block, t));
             }
-            for block in &added {
-                let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
-                added_transaction_blocks.extend(&t);
+            for (block, mut t) in added_proposers {
+                t.retain(|transaction_block| {
+                    positions[transaction_block].1.first_referrer() == Some(block)
+                });
+                ledger_diff.added_proposers.push((block, t));
             }
-            Ok((added_transaction_blocks, removed_transaction_blocks))
-        } else {
-            Ok((vec![], vec![]))
+            for (transaction_block, (_, position)) in &positions {
+                put_value!(transaction_ledger_position_cf, transaction_block, position);
+            }
+            wb.delete_cf(ledger_update_progress_cf, UNFINISHED_LEDGER_BEGIN_KEY)?;
+            // commit the new ledger into the database
+            self.db.write(wb)?;
         }
+        Ok(ledger_diff)
     }
 
     fn proposer_leader(&self, level: u64, quantile: f32) -> Result<Option<H256>> {
@@ -883,6 +1289,55 @@ impl BlockChain {
         Ok((added_votes, removed_votes))
     }
 
+    /// Given two voter blocks on the same chain, get the voter blocks that join and leave the main
+    /// chain when switching it from one to the other, from the lowest level up.
+    pub fn voter_chain_diff(&self, from: H256, to: H256) -> Result<(Vec<H256>, Vec<H256>)> {
+        let voter_node_level_cf = self.db.cf_handle(VOTER_NODE_LEVEL_CF).unwrap();
+        let voter_parent_neighbor_cf = self.db.cf_handle(VOTER_PARENT_NEIGHBOR_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                deserialize(
+                    &self
+                        .db
+                        .get_pinned_cf($cf, serialize(&$key).unwrap())?
+                        .unwrap(),
+                )
+                .unwrap()
+            }};
+        }
+
+        let mut to: H256 = to;
+        let mut from: H256 = from;
+        let mut to_level: u64 = get_value!(voter_node_level_cf, to);
+        let mut from_level: u64 = get_value!(voter_node_level_cf, from);
+        let mut added: Vec<H256> = vec![];
+        let mut removed: Vec<H256> = vec![];
+
+        // trace back the higher tip until the levels of the two tips are the same, and then both
+        // until they meet
+        while to != from {
+            if to_level >= from_level {
+                added.push(to);
+                to = get_value!(voter_parent_neighbor_cf, to);
+                to_level -= 1;
+            }
+            if from_level > to_level {
+                removed.push(from);
+                from = get_value!(voter_parent_neighbor_cf, from);
+                from_level -= 1;
+            }
+        }
+        added.reverse();
+        removed.reverse();
+        Ok((added, removed))
+    }
+
+    /// Get the voter block of each chain up to which the ledger counts the votes.
+    pub fn voter_ledger_tips(&self) -> Vec<H256> {
+        self.voter_ledger_tips.lock().unwrap().clone()
+    }
+
     pub fn best_proposer(&self) -> Result<H256> {
         let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
 
@@ -1025,6 +1480,37 @@ impl BlockChain {
         Ok(chain)
     }
 
+    /// Get the level of the voter block
+    pub fn voter_level(&self, hash: &H256) -> Result<u64> {
+        let voter_node_level_cf = self.db.cf_handle(VOTER_NODE_LEVEL_CF).unwrap();
+        let level: u64 = deserialize(
+            &self
+                .db
+                .get_pinned_cf(voter_node_level_cf, serialize(&hash).unwrap())?
+                .unwrap(),
+        )
+        .unwrap();
+        Ok(level)
+    }
+
+    /// Get the number of voter chains in the database, which could differ from the config if the
+    /// database was created with another config.
+    pub fn num_voter_chains(&self) -> Result<u16> {
+        let voter_tree_level_count_cf = self.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
+        let mut chains: u16 = 0;
+        while self
+            .db
+            .get_pinned_cf(
+                voter_tree_level_count_cf,
+                serialize(&(chains, 0u64)).unwrap(),
+            )?
+            .is_some()
+        {
+            chains += 1;
+        }
+        Ok(chains)
+    }
+
     /// Check whether the given proposer block exists in the database.
     pub fn contains_proposer(&self, hash: &H256) -> Result<bool> {
         let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
@@ -1062,6 +1548,90 @@ impl BlockChain {
         }
     }
 
+    /// Get where the given transaction is in the blockchain, or `None` if no transaction block
+    /// carries it. The UTXO set tells whether a transaction of the ledger applies.
+    pub fn transaction_status(
+        &self,
+        hash: &H256,
+        utxodb: &UtxoDatabase,
+    ) -> Result<Option<TransactionStatus>> {
+        let transaction_block_cf = self.db.cf_handle(TRANSACTION_BLOCK_CF).unwrap();
+        let transaction_referrer_cf = self.db.cf_handle(TRANSACTION_REFERRER_CF).unwrap();
+        let transaction_ledger_position_cf =
+            self.db.cf_handle(TRANSACTION_LEDGER_POSITION_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
+                }
+            }};
+        }
+
+        let blocks: Vec<H256> = match get_value!(transaction_block_cf, hash) {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let mut best: Option<TransactionStatus> = None;
+        for block in blocks {
+            let position: LedgerPosition =
+                get_value!(transaction_ledger_position_cf, block).unwrap_or_default();
+            let status = match (position.confirmed_by.first(), position.deconfirmed_by) {
+                (Some(&(proposer, level)), _) => match utxodb.applied(hash)? {
+                    Some(true) => TransactionStatus::Confirmed {
+                        block,
+                        proposer,
+                        level,
+                    },
+                    Some(false) => TransactionStatus::Rejected {
+                        block,
+                        proposer,
+                        level,
+                    },
+                    None => TransactionStatus::Referred { block, proposer },
+                },
+                (None, Some((proposer, level))) => TransactionStatus::Deconfirmed {
+                    block,
+                    proposer,
+                    level,
+                },
+                (None, None) => {
+                    let referrers: Option<Vec<H256>> = get_value!(transaction_referrer_cf, block);
+                    match referrers {
+                        Some(r) => TransactionStatus::Referred {
+                            block,
+                            proposer: r[0],
+                        },
+                        None => TransactionStatus::Included { block },
+                    }
+                }
+            };
+            best = match best {
+                Some(b) if b.progress() >= status.progress() => Some(b),
+                _ => Some(status),
+            };
+        }
+        Ok(best)
+    }
+
+    /// Get the proposer block that put the given transaction block in the ledger, or `None` if
+    /// the transaction block is not in the ledger.
+    pub fn transaction_block_ledger_proposer(&self, block: &H256) -> Result<Option<H256>> {
+        let transaction_ledger_position_cf =
+            self.db.cf_handle(TRANSACTION_LEDGER_POSITION_CF).unwrap();
+        match self
+            .db
+            .get_pinned_cf(transaction_ledger_position_cf, serialize(block).unwrap())?
+        {
+            Some(raw) => {
+                let position: LedgerPosition = deserialize(&raw).unwrap();
+                Ok(position.first_referrer())
+            }
+            None => Ok(None),
+        }
+    }
+
     pub fn proposer_leaders(&self) -> Result<Vec<H256>> {
         let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
         let proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
@@ -1080,6 +1650,40 @@ impl BlockChain {
         }
         Ok(leaders)
     }
+
+    /// Get the leader of the given proposer level, or `None` if the level is not confirmed yet.
+    pub fn ledger_leader(&self, level: u64) -> Result<Option<H256>> {
+        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+        let proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
+        if level > *proposer_ledger_tip {
+            return Ok(None);
+        }
+        let leader = match self
+            .db
+            .get_pinned_cf(proposer_leader_sequence_cf, serialize(&level).unwrap())?
+        {
+            Some(d) => deserialize(&d).unwrap(),
+            None => unreachable!("level <= ledger tip should have a leader"),
+        };
+        drop(proposer_ledger_tip);
+        Ok(Some(leader))
+    }
+
+    /// Get the level and the leader of the tip of the proposer ledger.
+    pub fn proposer_ledger_tip(&self) -> Result<(u64, H256)> {
+        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+        let proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
+        let level = *proposer_ledger_tip;
+        let leader = match self
+            .db
+            .get_pinned_cf(proposer_leader_sequence_cf, serialize(&level).unwrap())?
+        {
+            Some(d) => deserialize(&d).unwrap(),
+            None => unreachable!("ledger tip should have a leader"),
+        };
+        drop(proposer_ledger_tip);
+        Ok((level, leader))
+    }
 }
 
 impl BlockChain {
@@ -1655,6 +2259,33 @@ fn u64_plus_merge(
     Some(result)
 }
 
+#[cfg(test)]
+mod transaction_status_tests {
+    use super::*;
+
+    #[test]
+    fn shared_transaction_block() {
+        let first: H256 = [1u8; 32].into();
+        let second: H256 = [2u8; 32].into();
+        let mut position = LedgerPosition::default();
+        position.confirm(first, 3);
+        position.confirm(second, 4);
+
+        // the other proposer block still keeps the transaction block in the ledger
+        position.deconfirm(&first);
+        assert_eq!(position.confirmed_by, vec![(second, 4)]);
+        assert_eq!(position.deconfirmed_by, None);
+
+        position.deconfirm(&second);
+        assert!(position.confirmed_by.is_empty());
+        assert_eq!(position.deconfirmed_by, Some((second, 4)));
+
+        position.confirm(first, 5);
+        assert_eq!(position.confirmed_by, vec![(first, 5)]);
+        assert_eq!(position.deconfirmed_by, None);
+    }
+}
+
 /*
 #[cfg(test)]
 mod tests {
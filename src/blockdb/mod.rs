@@ -7,6 +7,7 @@ use bincode::{deserialize, serialize};
 use rocksdb::{self, ColumnFamilyDescriptor, Options, SliceTransform, DB};
 use std::convert::TryInto;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 const BLOCK_CF: &str = "BLOCK";
 const BLOCK_ARRIVAL_ORDER_CF: &str = "BLOCK_ARRIVAL_ORDER";
@@ -16,8 +17,10 @@ const BLOCK_SEQUENCE_NUMBER_CF: &str = "BLOCK_SEQUENCE_NUMBER";
 pub struct BlockDatabase {
     /// The underlying RocksDB handle.
     db: rocksdb::DB,
-    /// The number of blocks in this database.
+    /// The number of blocks in this database. Only counts blocks that are fully written.
     count: AtomicU64,
+    /// Lock held while assigning a sequence number to a block and writing it.
+    insert_lock: Mutex<()>,
 }
 
 impl BlockDatabase {
@@ -44,6 +47,7 @@ impl BlockDatabase {
         Ok(BlockDatabase {
             db,
             count: AtomicU64::new(0),
+            insert_lock: Mutex::new(()),
         })
     }
 
@@ -59,12 +63,13 @@ impl BlockDatabase {
         let block_arrival_order_cf = db.db.cf_handle(BLOCK_ARRIVAL_ORDER_CF).unwrap();
         let block_sequence_number_cf = db.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
 
+        let digest = config.hash();
         let mut counter: u64 = 0;
         // insert proposer genesis block
         db.db.put_cf(
             block_cf,
             &config.proposer_genesis,
-            &serialize(&proposer_genesis()).unwrap(),
+            &serialize(&proposer_genesis(digest)).unwrap(),
         )?;
         db.db.put_cf(
             block_arrival_order_cf,
@@ -83,7 +88,7 @@ impl BlockDatabase {
             db.db.put_cf(
                 block_cf,
                 &config.voter_genesis[i as usize],
-                &serialize(&voter_genesis(i as u16)).unwrap(),
+                &serialize(&voter_genesis(i as u16, digest)).unwrap(),
             )?;
             db.db.put_cf(
                 block_arrival_order_cf,
@@ -108,6 +113,20 @@ impl BlockDatabase {
         config: BlockchainConfig,
     ) -> Result<Self, rocksdb::Error> {
         let db = Self::open(&path, config)?;
+
+        // restore the block counter, which is one past the largest sequence number
+        let block_arrival_order_cf = db.db.cf_handle(BLOCK_ARRIVAL_ORDER_CF).unwrap();
+        let mut counter: u64 = 0;
+        for (k, _) in db
+            .db
+            .iterator_cf(block_arrival_order_cf, rocksdb::IteratorMode::Start)?
+        {
+            let seq = u64::from_ne_bytes(k.as_ref()[0..8].try_into().unwrap());
+            if seq >= counter {
+                counter = seq + 1;
+            }
+        }
+        db.count.store(counter, Ordering::Relaxed);
         Ok(db)
     }
 
@@ -118,12 +137,16 @@ impl BlockDatabase {
         let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
         let hash: H256 = block.hash();
         let serialized = serialize(block).unwrap();
-        let counter = self.count.fetch_add(1, Ordering::Relaxed);
-        self.db.put_cf(block_cf, &hash, &serialized)?;
-        self.db
-            .put_cf(block_arrival_order_cf, &counter.to_ne_bytes(), &hash)?;
-        self.db
-            .put_cf(block_sequence_number_cf, &hash, &counter.to_ne_bytes())?;
+        // readers stop at the block count, so only count the block once it is written, and don't
+        // let another block take its sequence number in the meantime
+        let _lock = self.insert_lock.lock().unwrap();
+        let counter = self.count.load(Ordering::Acquire);
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put_cf(block_cf, hash, &serialized)?;
+        batch.put_cf(block_arrival_order_cf, counter.to_ne_bytes(), hash)?;
+        batch.put_cf(block_sequence_number_cf, hash, counter.to_ne_bytes())?;
+        self.db.write(batch)?;
+        self.count.store(counter + 1, Ordering::Release);
         Ok(counter)
     }
 
@@ -131,12 +154,14 @@ impl BlockDatabase {
         let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
         let block_arrival_order_cf = self.db.cf_handle(BLOCK_ARRIVAL_ORDER_CF).unwrap();
         let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
-        let counter = self.count.fetch_add(1, Ordering::Relaxed);
-        self.db.put_cf(block_cf, &hash, &raw_block)?;
-        self.db
-            .put_cf(block_arrival_order_cf, &counter.to_ne_bytes(), &hash)?;
-        self.db
-            .put_cf(block_sequence_number_cf, &hash, &counter.to_ne_bytes())?;
+        let _lock = self.insert_lock.lock().unwrap();
+        let counter = self.count.load(Ordering::Acquire);
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put_cf(block_cf, hash, raw_block)?;
+        batch.put_cf(block_arrival_order_cf, counter.to_ne_bytes(), hash)?;
+        batch.put_cf(block_sequence_number_cf, hash, counter.to_ne_bytes())?;
+        self.db.write(batch)?;
+        self.count.store(counter + 1, Ordering::Release);
         Ok(counter)
     }
 
@@ -168,16 +193,14 @@ impl BlockDatabase {
         }
     }
 
+    /// Iterate over the blocks that arrived after the given block in batches, or over all blocks
+    /// if the given block is not in the database.
     pub fn blocks_after(&self, after: &H256, batch_size: u64) -> BlocksInArrivalOrder {
         let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
-        let start_seq = u64::from_ne_bytes(
-            self.db
-                .get_cf(block_sequence_number_cf, &after)
-                .unwrap()
-                .unwrap()[0..8]
-                .try_into()
-                .unwrap(),
-        ) + 1;
+        let start_seq = match self.db.get_cf(block_sequence_number_cf, &after).unwrap() {
+            Some(seq) => u64::from_ne_bytes(seq[0..8].try_into().unwrap()) + 1,
+            None => 0,
+        };
         BlocksInArrivalOrder {
             seq: start_seq,
             batch: batch_size,
@@ -226,16 +249,24 @@ impl<'a> std::iter::Iterator for BlocksInArrivalOrder<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let block_cf = self.db.db.cf_handle(BLOCK_CF).unwrap();
         let block_arrival_order_cf = self.db.db.cf_handle(BLOCK_ARRIVAL_ORDER_CF).unwrap();
-        let num_blocks = self.db.count.load(Ordering::Relaxed);
+        let num_blocks = self.db.count.load(Ordering::Acquire);
         let mut this_batch: u64 = 0;
         let mut result: Vec<Block> = vec![];
         while self.seq < num_blocks && this_batch < self.batch {
-            let hash_bytes = self
+            // blocks are counted only once written, but databases written by older versions may
+            // have sequence numbers taken by blocks that never got written
+            let hash_bytes = match self
                 .db
                 .db
                 .get_cf(block_arrival_order_cf, &self.seq.to_ne_bytes())
                 .unwrap()
-                .unwrap();
+            {
+                Some(h) => h,
+                None => {
+                    self.seq += 1;
+                    continue;
+                }
+            };
             let block: Block =
                 deserialize(&self.db.db.get_cf(block_cf, &hash_bytes).unwrap().unwrap()).unwrap();
             result.push(block);
@@ -307,3 +338,93 @@ mod tests {
     }
 }
 */
+
+#[cfg(test)]
+mod arrival_order_tests {
+    use super::*;
+    use crate::block::tests::transaction_block;
+    use crate::config::Genesis;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    #[test]
+    fn resume_after_block() {
+        let config = Genesis::default().config();
+        let db = BlockDatabase::new(
+            "/tmp/prism_test_blockdb_resume_after_block.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+        let blocks: Vec<Block> = (0..5)
+            .map(|i| transaction_block(H256::default(), i, vec![]))
+            .collect();
+        for block in &blocks {
+            db.insert(block).unwrap();
+        }
+        let hashes = |after: &H256, batch_size| -> Vec<H256> {
+            db.blocks_after(after, batch_size)
+                .flatten()
+                .map(|b| b.hash())
+                .collect()
+        };
+        let expected: Vec<H256> = blocks.iter().map(|b| b.hash()).collect();
+
+        // a peer that synced up to the second block resumes from the third one, in batches
+        assert_eq!(hashes(&expected[1], 2), expected[2..].to_vec());
+        assert_eq!(db.blocks_after(&expected[1], 2).count(), 2);
+        // nothing after the latest block
+        assert!(hashes(&expected[4], 2).is_empty());
+        // an unknown block starts from the beginning, genesis blocks included
+        let all = hashes(&[7u8; 32].into(), 100);
+        assert_eq!(all.len() as u64, db.num_blocks());
+        assert_eq!(all[all.len() - 5..].to_vec(), expected);
+    }
+
+    #[test]
+    fn concurrent_inserts() {
+        let config = Genesis::default().config();
+        let db = Arc::new(
+            BlockDatabase::new(
+                "/tmp/prism_test_blockdb_concurrent_inserts.rocksdb",
+                config.clone(),
+            )
+            .unwrap(),
+        );
+        let finished = Arc::new(AtomicUsize::new(0));
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let db = Arc::clone(&db);
+                let finished = Arc::clone(&finished);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let block = transaction_block(H256::default(), writer * 1000 + i, vec![]);
+                        db.insert(&block).unwrap();
+                    }
+                    finished.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        // follow the blocks as they arrive, resuming after the last one we got
+        let mut seen = HashSet::new();
+        let mut last = *config.voter_genesis.last().unwrap();
+        loop {
+            let done = finished.load(Ordering::SeqCst) == writers.len();
+            for batch in db.blocks_after(&last, 16) {
+                for block in batch {
+                    last = block.hash();
+                    assert!(seen.insert(last));
+                }
+            }
+            if done {
+                break;
+            }
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(seen.len(), 400);
+        assert_eq!(db.latest_block_hash().unwrap(), last);
+    }
+}
@@ -1,16 +1,421 @@
-use crate::crypto::hash::H256;
+use crate::block::{proposer, voter};
+use crate::crypto::hash::{Hashable, H256};
+use crate::network::limits::Limits;
+use crate::transaction::{Address, Output, Transaction};
+use crate::utxodb::Tuning;
 use bigint::uint::U256;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 const AVG_TX_SIZE: u32 = 168; // average size of a transaction (in Bytes)
 const PROPOSER_TX_REF_HEADROOM: f32 = 10.0;
 const SORTITION_PRECISION: u64 = std::u64::MAX;
 const DECONFIRM_HEADROOM: f32 = 1.05;
+const MAX_RETARGET_FACTOR: u64 = 4; // maximum change of the difficulty in one retarget
 
 // Chain IDs
 pub const PROPOSER_INDEX: u16 = 0;
 pub const TRANSACTION_INDEX: u16 = 1;
 pub const FIRST_VOTER_INDEX: u16 = 2;
 
+/// Block rewards, which the miner of a block gets once the block enters the ledger.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewardSchedule {
+    /// Reward of a proposer block.
+    pub proposer: u64,
+    /// Reward of a voter block.
+    pub voter: u64,
+    /// Reward of a transaction block, on top of the fees of its transactions.
+    pub transaction: u64,
+    /// Number of proposer levels after which the rewards halve, or 0 if they never do.
+    pub halving_interval: u64,
+}
+
+impl Default for RewardSchedule {
+    fn default() -> Self {
+        Self {
+            proposer: 50,
+            voter: 5,
+            transaction: 5,
+            halving_interval: 0,
+        }
+    }
+}
+
+impl RewardSchedule {
+    /// Get the reward of a block of the given type (chain ID) whose proposer parent is at the
+    /// given level.
+    pub fn reward(&self, sortition_id: u16, level: u64) -> u64 {
+        let base = match sortition_id {
+            PROPOSER_INDEX => self.proposer,
+            TRANSACTION_INDEX => self.transaction,
+            _ => self.voter,
+        };
+        if self.halving_interval == 0 {
+            return base;
+        }
+        let halvings = level / self.halving_interval;
+        if halvings >= 64 {
+            0
+        } else {
+            base >> halvings
+        }
+    }
+}
+
+/// Initial coins of an address, which exist from the genesis on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Allocation {
+    /// The address that owns the coins, in base64.
+    #[serde(with = "base64_address")]
+    pub address: Address,
+    /// The number of coins.
+    pub coins: u32,
+    /// The value of each coin.
+    pub value: u64,
+}
+
+/// The content of a genesis file: the parameters of the blockchain, and the initial coins. Nodes
+/// of the same network must start from the same genesis. Missing fields take the default values.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Genesis {
+    /// Number of voter chains.
+    pub voter_chains: u16,
+    /// Target transaction throughput in transactions/sec.
+    pub tx_throughput: u32,
+    /// Maximum size of a transaction block in Bytes.
+    pub tx_block_size: u32,
+    /// Proposer block mining rate in blocks/sec.
+    pub proposer_mining_rate: f32,
+    /// Voter block mining rate for one voter chain, in blocks/sec.
+    pub voter_mining_rate: f32,
+    /// Ratio of adversary hashing power.
+    pub adversary_ratio: f32,
+    /// -log(epsilon) for confirmation.
+    pub confirm_confidence: f32,
+    /// Number of proposer levels between difficulty retargets, or 0 to keep the difficulty fixed.
+    pub difficulty_interval: u64,
+    /// Block rewards.
+    pub rewards: RewardSchedule,
+    /// Initial coins.
+    pub allocations: Vec<Allocation>,
+}
+
+impl Default for Genesis {
+    fn default() -> Self {
+        Self {
+            voter_chains: 1000,
+            tx_throughput: 80000,
+            tx_block_size: 64000,
+            proposer_mining_rate: 0.1,
+            voter_mining_rate: 0.1,
+            adversary_ratio: 0.4,
+            confirm_confidence: 20.0,
+            difficulty_interval: 0,
+            rewards: RewardSchedule::default(),
+            allocations: vec![],
+        }
+    }
+}
+
+impl Genesis {
+    /// Load a genesis file in JSON from the given path, and check the parameters.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let genesis: Self = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+        genesis.check()?;
+        Ok(genesis)
+    }
+
+    /// Check that the parameters make a working blockchain, and that the initial coins do not
+    /// overflow.
+    pub fn check(&self) -> Result<(), String> {
+        if self.voter_chains == 0 {
+            return Err("voter_chains must be positive".to_string());
+        }
+        if self.tx_throughput == 0 {
+            return Err("tx_throughput must be positive".to_string());
+        }
+        if self.tx_block_size < AVG_TX_SIZE {
+            return Err(format!("tx_block_size must be at least {}", AVG_TX_SIZE));
+        }
+        if self.proposer_mining_rate <= 0.0 {
+            return Err("proposer_mining_rate must be positive".to_string());
+        }
+        if self.voter_mining_rate <= 0.0 {
+            return Err("voter_mining_rate must be positive".to_string());
+        }
+        if self.confirm_confidence <= 0.0 {
+            return Err("confirm_confidence must be positive".to_string());
+        }
+        let mut total: u64 = 0;
+        for a in &self.allocations {
+            total = u64::from(a.coins)
+                .checked_mul(a.value)
+                .and_then(|v| total.checked_add(v))
+                .ok_or_else(|| "total value of allocations overflows".to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Get the blockchain config of the genesis.
+    pub fn config(&self) -> BlockchainConfig {
+        let mut config = BlockchainConfig::new(
+            self.voter_chains,
+            self.tx_block_size,
+            self.tx_throughput,
+            self.proposer_mining_rate,
+            self.voter_mining_rate,
+            self.adversary_ratio,
+            self.confirm_confidence,
+        );
+        config.difficulty_interval = self.difficulty_interval;
+        config.rewards = self.rewards.clone();
+        config.allocations = self.allocations.clone();
+        config.set_genesis_hashes();
+        config
+    }
+}
+
+/// Parameters of a node that are up to each node, as opposed to the genesis. They come from a
+/// config file in TOML or JSON, and the command line flags of the same names override them.
+/// Missing fields take the default values.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Verbosity of logging.
+    pub verbose: usize,
+    /// Address of the P2P server.
+    pub p2p: SocketAddr,
+    /// Address of the API server.
+    pub api: SocketAddr,
+    /// Address of the visualization server, if it is enabled.
+    pub visual: Option<SocketAddr>,
+    /// Peers to connect to at start.
+    pub connect: Vec<SocketAddr>,
+    /// Number of outgoing peers to keep by connecting to known addresses.
+    pub outgoing_peers: usize,
+    /// Path to the address book of peers.
+    pub peerdb: PathBuf,
+    /// Path to the block database.
+    pub blockdb: PathBuf,
+    /// Path to the UTXO database.
+    pub utxodb: PathBuf,
+    /// Path to the blockchain database.
+    pub blockchaindb: PathBuf,
+    /// Path to the wallet database.
+    pub walletdb: PathBuf,
+    /// Whether to index the UTXO set by address.
+    pub address_index: bool,
+    /// Whether to reopen the existing databases instead of creating new ones.
+    pub resume: bool,
+    /// Path to the genesis file, or `None` for the default genesis.
+    pub genesis: Option<PathBuf>,
+    /// Paths to key pairs to load into the wallet.
+    pub load_key: Vec<PathBuf>,
+    /// Path to a wallet seed to restore the wallet from.
+    pub wallet_seed_file: Option<PathBuf>,
+    /// Number of addresses in a row without coins after which restoring a wallet stops looking.
+    pub wallet_gap_limit: u32,
+    /// Path to the passphrase of the wallet.
+    pub wallet_passphrase_file: Option<PathBuf>,
+    /// Maximum number of transactions in the memory pool.
+    pub mempool_size: u64,
+    /// Number of worker threads for transaction execution.
+    pub execution_workers: usize,
+    /// Size of the buffer between pipeline stages in transaction execution.
+    pub execution_buffer: usize,
+    /// Number of worker threads for the P2P server.
+    pub p2p_workers: usize,
+    /// Number of received messages to buffer for the P2P workers.
+    pub p2p_queue_size: usize,
+    /// Number of control signals, e.g. messages to send, to buffer for the P2P server.
+    pub p2p_control_queue_size: usize,
+    /// Maximum number of Bytes per second to read from each peer.
+    pub peer_byte_rate: u64,
+    /// Maximum number of messages per second to read from each peer.
+    pub peer_message_rate: u64,
+    /// Address to receive the rewards of the blocks we mine, in base64, or `None` for an address
+    /// of the wallet.
+    #[serde(with = "base64_address_option")]
+    pub miner_addr: Option<Address>,
+    /// Maximum size in Bytes of the message types that should not take the default.
+    pub max_message_size: BTreeMap<String, u32>,
+    /// Tuning of the UTXO database.
+    pub utxodb_tuning: Tuning,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            verbose: 0,
+            p2p: ([127, 0, 0, 1], 6000).into(),
+            api: ([127, 0, 0, 1], 7000).into(),
+            visual: None,
+            connect: vec![],
+            outgoing_peers: 8,
+            peerdb: "/tmp/prism-peers.rocksdb".into(),
+            blockdb: "/tmp/prism-blocks.rocksdb".into(),
+            utxodb: "/tmp/prism-utxo.rocksdb".into(),
+            blockchaindb: "/tmp/prism-blockchain.rocksdb".into(),
+            walletdb: "/tmp/prism-wallet.rocksdb".into(),
+            address_index: false,
+            resume: false,
+            genesis: None,
+            load_key: vec![],
+            wallet_seed_file: None,
+            wallet_gap_limit: 20,
+            wallet_passphrase_file: None,
+            mempool_size: 500_000,
+            execution_workers: 8,
+            execution_buffer: 3,
+            p2p_workers: 16,
+            p2p_queue_size: 100,
+            p2p_control_queue_size: 10000,
+            peer_byte_rate: limits.bytes_per_sec,
+            peer_message_rate: limits.messages_per_sec,
+            miner_addr: None,
+            max_message_size: BTreeMap::new(),
+            utxodb_tuning: Tuning::default(),
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Load a config file from the given path. It is in JSON if the path ends with `.json`, and in
+    /// TOML otherwise.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let is_json = path.as_ref().extension().and_then(|e| e.to_str()) == Some("json");
+        if is_json {
+            let value: serde_json::Value =
+                serde_json::from_str(&content).map_err(|e| e.to_string())?;
+            // serde_json does not say which field is bad, so we try them one by one
+            if let Some(fields) = value.as_object() {
+                for (name, field) in fields {
+                    let single = serde_json::json!({ name: field });
+                    serde_json::from_value::<Self>(single)
+                        .map_err(|e| format!("{} for key `{}`", e, name))?;
+                }
+            }
+            serde_json::from_value(value).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        }
+    }
+
+    /// Check the parameters, and name the first bad one in the error.
+    pub fn check(&self) -> Result<(), String> {
+        let positive = [
+            ("wallet_gap_limit", u64::from(self.wallet_gap_limit)),
+            ("mempool_size", self.mempool_size),
+            ("execution_workers", self.execution_workers as u64),
+            ("execution_buffer", self.execution_buffer as u64),
+            ("p2p_workers", self.p2p_workers as u64),
+            ("p2p_queue_size", self.p2p_queue_size as u64),
+            ("p2p_control_queue_size", self.p2p_control_queue_size as u64),
+            ("peer_byte_rate", self.peer_byte_rate),
+            ("peer_message_rate", self.peer_message_rate),
+            (
+                "utxodb_tuning.memtable_buckets",
+                self.utxodb_tuning.memtable_buckets as u64,
+            ),
+            (
+                "utxodb_tuning.parallelism",
+                self.utxodb_tuning.parallelism.max(0) as u64,
+            ),
+            (
+                "utxodb_tuning.max_background_flushes",
+                self.utxodb_tuning.max_background_flushes.max(0) as u64,
+            ),
+            (
+                "utxodb_tuning.max_write_buffer_number",
+                self.utxodb_tuning.max_write_buffer_number.max(0) as u64,
+            ),
+        ];
+        for (name, value) in &positive {
+            if *value == 0 {
+                return Err(format!("{} must be positive", name));
+            }
+        }
+        self.limits()?;
+        Ok(())
+    }
+
+    /// Get the limits on what a single peer can send us.
+    pub fn limits(&self) -> Result<Limits, String> {
+        let mut limits = Limits::default();
+        for (variant, size) in &self.max_message_size {
+            limits
+                .set_max_size(variant, *size)
+                .map_err(|e| format!("max_message_size: {}", e))?;
+        }
+        limits.bytes_per_sec = self.peer_byte_rate;
+        limits.messages_per_sec = self.peer_message_rate;
+        Ok(limits)
+    }
+}
+
+/// Parse an address in base64, the way `keygen --addr` prints it.
+pub fn parse_address(addr: &str) -> Result<Address, String> {
+    let decoded = base64::decode(addr.trim()).map_err(|e| e.to_string())?;
+    let bytes: [u8; 32] = decoded[..]
+        .try_into()
+        .map_err(|_| "address must be 32 bytes".to_string())?;
+    Ok(bytes.into())
+}
+
+/// (De)serialize an address in base64.
+mod base64_address {
+    use crate::transaction::Address;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(addr: &Address, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(addr))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        super::parse_address(&encoded).map_err(D::Error::custom)
+    }
+}
+
+/// (De)serialize an optional address in base64.
+mod base64_address_option {
+    use crate::transaction::Address;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        addr: &Option<Address>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match addr {
+            Some(a) => serializer.serialize_some(&base64::encode(a)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Address>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(encoded) => super::parse_address(&encoded)
+                .map(Some)
+                .map_err(D::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BlockchainConfig {
     /// Number of voter chains.
@@ -38,6 +443,12 @@ pub struct BlockchainConfig {
     log_epsilon: f32,
     pub quantile_epsilon_confirm: f32,
     pub quantile_epsilon_deconfirm: f32,
+    /// Number of proposer levels between difficulty retargets, or 0 to keep the difficulty fixed.
+    pub difficulty_interval: u64,
+    /// Block rewards.
+    pub rewards: RewardSchedule,
+    /// Initial coins.
+    pub allocations: Vec<Allocation>,
 }
 
 impl BlockchainConfig {
@@ -51,24 +462,6 @@ impl BlockchainConfig {
         log_epsilon: f32,
     ) -> Self {
         let tx_txs = tx_size / AVG_TX_SIZE;
-        let proposer_genesis: H256 = {
-            let mut raw_hash: [u8; 32] = [0; 32];
-            let bytes = PROPOSER_INDEX.to_be_bytes();
-            raw_hash[30] = bytes[0];
-            raw_hash[31] = bytes[1];
-            raw_hash.into()
-        };
-        let voter_genesis_hashes: Vec<H256> = {
-            let mut v: Vec<H256> = vec![];
-            for chain_num in 0..voter_chains {
-                let mut raw_hash: [u8; 32] = [0; 32];
-                let bytes = (chain_num + FIRST_VOTER_INDEX).to_be_bytes();
-                raw_hash[30] = bytes[0];
-                raw_hash[31] = bytes[1];
-                v.push(raw_hash.into());
-            }
-            v
-        };
         let tx_mining_rate: f32 = {
             let tx_thruput: f32 = tx_throughput as f32;
             let tx_txs: f32 = tx_txs as f32;
@@ -93,7 +486,7 @@ impl BlockchainConfig {
         .sqrt();
         let quantile_deconfirm: f32 =
             (2.0 * log_epsilon - (2.0 * log_epsilon).ln() - (2.0 * 3.141_692_6 as f32).ln()).sqrt();
-        Self {
+        let mut config = Self {
             voter_chains,
             tx_txs,
             proposer_tx_refs: (tx_mining_rate / proposer_rate * PROPOSER_TX_REF_HEADROOM).ceil()
@@ -101,8 +494,8 @@ impl BlockchainConfig {
             proposer_mining_rate: proposer_rate,
             voter_mining_rate: voter_rate,
             tx_mining_rate,
-            proposer_genesis,
-            voter_genesis: voter_genesis_hashes,
+            proposer_genesis: H256::default(),
+            voter_genesis: vec![],
             total_mining_rate,
             total_sortition_width: SORTITION_PRECISION.into(),
             proposer_sortition_width: proposer_width.into(),
@@ -112,9 +505,75 @@ impl BlockchainConfig {
             log_epsilon,
             quantile_epsilon_confirm: quantile_confirm,
             quantile_epsilon_deconfirm: quantile_deconfirm,
+            difficulty_interval: 0,
+            rewards: RewardSchedule::default(),
+            allocations: vec![],
+        };
+        config.set_genesis_hashes();
+        config
+    }
+
+    /// Set the hashes of the genesis blocks, which carry the digest of the parameters. This must
+    /// be called again after changing the parameters.
+    pub fn set_genesis_hashes(&mut self) {
+        let digest = self.hash();
+        self.proposer_genesis = proposer::genesis(digest).hash();
+        self.voter_genesis = (0..self.voter_chains)
+            .map(|chain_num| voter::genesis(chain_num, digest).hash())
+            .collect();
+    }
+
+    /// Get the transaction that creates the initial coins. It has no inputs, and one output for
+    /// each initial coin, in the order of the allocations.
+    pub fn genesis_transaction(&self) -> Transaction {
+        let mut output = vec![];
+        for a in &self.allocations {
+            let coin = Output {
+                value: a.value,
+                recipient: a.address,
+            };
+            output.extend(std::iter::repeat(coin).take(a.coins as usize));
+        }
+        Transaction {
+            input: vec![],
+            output,
+            authorization: vec![],
+            multisig: vec![],
+            hash: RefCell::new(None),
         }
     }
 
+    /// Whether the difficulty is retargeted for the blocks whose proposer parent is at the given
+    /// level minus one. The first retarget waits until the proposer genesis block, whose timestamp
+    /// is meaningless, has left the window.
+    pub fn is_retarget_level(&self, level: u64) -> bool {
+        self.difficulty_interval != 0
+            && level % self.difficulty_interval == 0
+            && level > self.difficulty_interval + 1
+    }
+
+    /// Get the new difficulty given the current one, and the time in milliseconds it took to mine
+    /// the last `difficulty_interval` proposer levels. The difficulty is scaled so that proposer
+    /// blocks come at the proposer mining rate, and the rate of the other blocks follows through
+    /// sortition. It changes by at most `MAX_RETARGET_FACTOR` times at once.
+    pub fn retarget(&self, difficulty: &H256, time_span: u128) -> H256 {
+        let expected = ((self.difficulty_interval as f64 * 1000.0
+            / f64::from(self.proposer_mining_rate)) as u64)
+            .max(1);
+        let actual = time_span
+            .min(u128::from(expected) * u128::from(MAX_RETARGET_FACTOR))
+            .max(u128::from(expected / MAX_RETARGET_FACTOR)) as u64;
+        let difficulty = U256::from_big_endian(difficulty.as_ref());
+        let (expected, actual): (U256, U256) = (expected.into(), actual.into());
+        // divide first to avoid overflowing, and add back what the division truncates
+        let retargeted = (difficulty / expected)
+            .saturating_mul(actual)
+            .saturating_add(difficulty % expected * actual / expected);
+        let mut raw = [0u8; 32];
+        retargeted.max(U256::one()).to_big_endian(&mut raw);
+        raw.into()
+    }
+
     pub fn sortition_hash(&self, hash: &H256, difficulty: &H256) -> Option<u16> {
         let hash = U256::from_big_endian(hash.as_ref());
         let difficulty = U256::from_big_endian(difficulty.as_ref());
@@ -136,9 +595,179 @@ impl BlockchainConfig {
     }
 }
 
+impl Hashable for BlockchainConfig {
+    /// Hash the parameters that nodes in the same network must agree on. This is the digest of the
+    /// genesis, which the genesis blocks carry, so it leaves out the hashes of the genesis blocks.
+    fn hash(&self) -> H256 {
+        let allocations: Vec<(Address, u32, u64)> = self
+            .allocations
+            .iter()
+            .map(|a| (a.address, a.coins, a.value))
+            .collect();
+        let serialized = bincode::serialize(&(
+            self.voter_chains,
+            self.tx_txs,
+            self.proposer_tx_refs,
+            self.proposer_mining_rate.to_bits(),
+            self.voter_mining_rate.to_bits(),
+            self.tx_mining_rate.to_bits(),
+            self.rewards.proposer,
+            self.rewards.voter,
+            self.rewards.transaction,
+            self.rewards.halving_interval,
+            allocations,
+            self.difficulty_interval,
+        ))
+        .unwrap();
+        ring::digest::digest(&ring::digest::SHA256, &serialized).into()
+    }
+}
+
 lazy_static! {
     pub static ref DEFAULT_DIFFICULTY: H256 = {
         let raw: [u8; 32] = [255; 32];
         raw.into()
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reward_halving() {
+        let rewards = RewardSchedule {
+            proposer: 40,
+            voter: 4,
+            transaction: 8,
+            halving_interval: 10,
+        };
+        assert_eq!(rewards.reward(PROPOSER_INDEX, 9), 40);
+        assert_eq!(rewards.reward(PROPOSER_INDEX, 10), 20);
+        assert_eq!(rewards.reward(TRANSACTION_INDEX, 25), 2);
+        assert_eq!(rewards.reward(FIRST_VOTER_INDEX + 3, 20), 1);
+        assert_eq!(rewards.reward(PROPOSER_INDEX, 10 * 64), 0);
+        let never = RewardSchedule::default();
+        assert_eq!(never.reward(PROPOSER_INDEX, std::u64::MAX), never.proposer);
+    }
+
+    #[test]
+    fn genesis_file() {
+        let addr = base64::encode(&[7u8; 32]);
+        let json = format!(
+            r#"{{"voter_chains": 3, "rewards": {{"voter": 1}},
+            "allocations": [{{"address": "{}", "coins": 2, "value": 10}}]}}"#,
+            addr
+        );
+        let genesis: Genesis = serde_json::from_str(&json).unwrap();
+        genesis.check().unwrap();
+        assert_eq!(genesis.tx_throughput, Genesis::default().tx_throughput);
+        assert_eq!(genesis.rewards.voter, 1);
+        assert_eq!(genesis.rewards.proposer, RewardSchedule::default().proposer);
+
+        let config = genesis.config();
+        assert_eq!(config.voter_chains, 3);
+        let transaction = config.genesis_transaction();
+        assert!(transaction.input.is_empty());
+        assert_eq!(transaction.output.len(), 2);
+        assert_eq!(transaction.output[1].recipient, [7u8; 32].into());
+
+        // the allocations are part of the digest
+        let mut other = genesis.clone();
+        other.allocations[0].value = 11;
+        assert_ne!(config.hash(), other.config().hash());
+        // and so are the genesis blocks
+        assert_eq!(
+            config.proposer_genesis,
+            proposer::genesis(config.hash()).hash()
+        );
+        assert_ne!(config.proposer_genesis, other.config().proposer_genesis);
+        assert_ne!(config.voter_genesis[2], other.config().voter_genesis[2]);
+        other.allocations[0].coins = std::u32::MAX;
+        other.allocations[0].value = std::u64::MAX;
+        assert!(other.check().is_err());
+
+        let bad = json.replace("voter_chains", "voter_chain");
+        assert!(serde_json::from_str::<Genesis>(&bad).is_err());
+    }
+
+    #[test]
+    fn difficulty_retarget() {
+        let genesis = Genesis {
+            proposer_mining_rate: 0.5,
+            difficulty_interval: 10,
+            ..Default::default()
+        };
+        let config = genesis.config();
+        assert!(!config.is_retarget_level(9));
+        assert!(!config.is_retarget_level(10));
+        assert!(!config.is_retarget_level(11));
+        assert!(config.is_retarget_level(20));
+        assert!(!config.is_retarget_level(21));
+
+        // 10 levels are expected to take 20 seconds
+        let mut raw = [0u8; 32];
+        raw[1] = 0x80;
+        let difficulty: H256 = raw.into();
+        assert_eq!(config.retarget(&difficulty, 20_000), difficulty);
+        raw[1] = 0x40;
+        assert_eq!(config.retarget(&difficulty, 10_000), raw.into());
+        raw[1] = 0x20;
+        assert_eq!(config.retarget(&difficulty, 0), raw.into());
+        raw[0] = 0x02;
+        raw[1] = 0;
+        assert_eq!(config.retarget(&difficulty, 1_000_000), raw.into());
+        assert_eq!(
+            config.retarget(&DEFAULT_DIFFICULTY, 40_000),
+            *DEFAULT_DIFFICULTY
+        );
+
+        // retargeting is opt-in
+        let fixed = Genesis::default().config();
+        assert!(!fixed.is_retarget_level(100));
+    }
+
+    #[test]
+    fn node_config_file() {
+        let config: NodeConfig = toml::from_str(
+            r#"
+            p2p = "10.0.0.1:6001"
+            connect = ["10.0.0.2:6000"]
+            mempool_size = 1000
+            miner_addr = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc="
+
+            [max_message_size]
+            Blocks = 1024
+
+            [utxodb_tuning]
+            parallelism = 4
+            "#,
+        )
+        .unwrap();
+        config.check().unwrap();
+        assert_eq!(config.p2p, "10.0.0.1:6001".parse().unwrap());
+        assert_eq!(config.api, NodeConfig::default().api);
+        assert_eq!(config.connect.len(), 1);
+        assert_eq!(config.mempool_size, 1000);
+        assert_eq!(config.miner_addr, Some([7u8; 32].into()));
+        assert_eq!(config.utxodb_tuning.parallelism, 4);
+        assert_eq!(config.utxodb_tuning.max_write_buffer_number, 32);
+        let tag = crate::network::message::MAX_SIZES
+            .iter()
+            .position(|(name, _)| *name == "Blocks")
+            .unwrap();
+        assert_eq!(config.limits().unwrap().max_size(tag as u32), Some(1024));
+
+        // errors name the bad field
+        let err = toml::from_str::<NodeConfig>("mempool_size = \"big\"").unwrap_err();
+        assert!(err.to_string().contains("mempool_size"));
+        let err = toml::from_str::<NodeConfig>("mempool = 10").unwrap_err();
+        assert!(err.to_string().contains("mempool"));
+        let mut bad = config.clone();
+        bad.p2p_workers = 0;
+        assert_eq!(bad.check().unwrap_err(), "p2p_workers must be positive");
+        let mut bad = config;
+        bad.max_message_size.insert("Nothing".to_string(), 1);
+        assert!(bad.check().unwrap_err().starts_with("max_message_size"));
+    }
+}
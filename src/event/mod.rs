@@ -0,0 +1,249 @@
+use crate::crypto::hash::H256;
+use crossbeam::channel;
+use serde::Serializer;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of most recent events that clients can read.
+const EVENT_LOG_CAPACITY: usize = 100_000;
+/// Longest that a reader can wait for an event in one read.
+const MAX_READ_WAIT: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    pub static ref EVENTS: EventLog = EventLog::new(EVENT_LOG_CAPACITY);
+}
+
+/// Something that happened to the node. Hashes are in hex.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A block passed validation and was inserted into the blockchain.
+    NewBlock { hash: String, block_type: String },
+    /// The proposer main chain got longer.
+    ProposerMainChain { level: u64, hash: String },
+    /// The leader of a proposer level changed. `leader` is `None` if the level is deconfirmed.
+    NewLeader { level: u64, leader: Option<String> },
+    /// Transaction blocks were added to and removed from the ledger.
+    LedgerDiff {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// A transaction was admitted into the memory pool.
+    MempoolAdmission {
+        #[serde(serialize_with = "hex")]
+        hash: H256,
+    },
+    /// A transaction was evicted from the full memory pool for one with a higher fee rate.
+    MempoolEviction {
+        #[serde(serialize_with = "hex")]
+        hash: H256,
+    },
+}
+
+/// Serialize a hash in hex. The events of the memory pool keep the hash as is, so that publishing
+/// them does not allocate.
+fn hex<S: Serializer>(hash: &H256, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(hash)
+}
+
+/// An event with its sequence number.
+#[derive(Serialize, Clone, Debug)]
+pub struct Record {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// The events after a sequence number.
+#[derive(Serialize, Debug)]
+pub struct Batch {
+    pub events: Vec<Record>,
+    /// The sequence number to read from next time.
+    pub next: u64,
+    /// Whether some events after the requested sequence number are no longer kept, or the
+    /// sequence number is from before a restart of the node.
+    pub missed: bool,
+}
+
+struct Log {
+    records: VecDeque<Record>,
+    /// Sequence number of the last event, starting from 1. 0 if there is no event yet.
+    last_seq: u64,
+}
+
+/// A log of the most recent events, numbered in the order they happen. The log is kept in memory
+/// only, so it starts over when the node restarts.
+pub struct EventLog {
+    shared: Arc<Shared>,
+    sender: channel::Sender<Event>,
+    receiver: channel::Receiver<Event>,
+}
+
+struct Shared {
+    log: Mutex<Log>,
+    published: Condvar,
+    capacity: usize,
+}
+
+impl Shared {
+    /// Number and append events to the log, and wake up the readers waiting for them.
+    fn append(&self, log: &mut Log, events: impl Iterator<Item = Event>) {
+        let before = log.last_seq;
+        for event in events {
+            log.last_seq += 1;
+            let seq = log.last_seq;
+            log.records.push_back(Record { seq, event });
+            if log.records.len() > self.capacity {
+                log.records.pop_front();
+            }
+        }
+        if log.last_seq != before {
+            self.published.notify_all();
+        }
+    }
+}
+
+impl EventLog {
+    fn new(capacity: usize) -> Self {
+        let shared = Arc::new(Shared {
+            log: Mutex::new(Log {
+                records: VecDeque::new(),
+                last_seq: 0,
+            }),
+            published: Condvar::new(),
+            capacity,
+        });
+        let (sender, receiver) = channel::unbounded();
+        // append the events in batches off the threads that publish them. only take them from
+        // the queue under the lock, so that they keep their order when a reader takes them too.
+        // the thread stops when the log is dropped
+        let appender = Arc::clone(&shared);
+        let events: channel::Receiver<Event> = receiver.clone();
+        thread::Builder::new()
+            .name("event_log".to_string())
+            .spawn(move || loop {
+                let mut select = channel::Select::new();
+                select.recv(&events);
+                select.ready();
+                let mut log = appender.log.lock().unwrap();
+                match events.try_recv() {
+                    Ok(event) => {
+                        appender.append(&mut log, std::iter::once(event).chain(events.try_iter()))
+                    }
+                    Err(channel::TryRecvError::Disconnected) => return,
+                    Err(channel::TryRecvError::Empty) => {}
+                }
+            })
+            .unwrap();
+        Self {
+            shared,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Publish an event. This only queues the event, and does not wait for the log.
+    pub fn publish(&self, event: Event) {
+        self.sender.send(event).unwrap();
+    }
+
+    /// Get up to `limit` events after the sequence number `since`, and wait up to `timeout` for
+    /// one (at most `MAX_READ_WAIT`) if there is none yet. Pass 0 as `since` to read from the
+    /// oldest event kept.
+    pub fn read(&self, since: u64, limit: usize, timeout: Duration) -> Batch {
+        let deadline = Instant::now() + timeout.min(MAX_READ_WAIT);
+        let mut log = self.shared.log.lock().unwrap();
+        // take the events still queued, so that readers see everything published before
+        self.shared.append(&mut log, self.receiver.try_iter());
+        // a sequence number we have not reached must be from before a restart
+        let (mut since, mut missed) = if since > log.last_seq {
+            (0, true)
+        } else {
+            (since, false)
+        };
+        loop {
+            let now = Instant::now();
+            if log.last_seq > since || now >= deadline {
+                break;
+            }
+            log = self
+                .shared
+                .published
+                .wait_timeout(log, deadline - now)
+                .unwrap()
+                .0;
+        }
+        if let Some(oldest) = log.records.front() {
+            if since + 1 < oldest.seq {
+                missed |= since != 0;
+                since = oldest.seq - 1;
+            }
+        }
+        let skip = match log.records.front() {
+            Some(oldest) => (since + 1 - oldest.seq) as usize,
+            None => 0,
+        };
+        let events: Vec<Record> = log.records.iter().skip(skip).take(limit).cloned().collect();
+        let next = match events.last() {
+            Some(r) => r.seq,
+            None => since,
+        };
+        Batch {
+            events,
+            next,
+            missed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admission(n: u8) -> Event {
+        Event::MempoolAdmission {
+            hash: [n; 32].into(),
+        }
+    }
+
+    #[test]
+    fn resume_and_miss() {
+        let log = EventLog::new(3);
+        for n in 0..5 {
+            log.publish(admission(n));
+        }
+        // events 1 and 2 are dropped
+        let batch = log.read(1, 10, Duration::from_millis(0));
+        assert!(batch.missed);
+        assert_eq!(batch.events.len(), 3);
+        assert_eq!(batch.next, 5);
+        let batch = log.read(3, 1, Duration::from_millis(0));
+        assert!(!batch.missed);
+        assert_eq!(batch.events[0].seq, 4);
+        assert_eq!(batch.next, 4);
+        // nothing new
+        let batch = log.read(5, 10, Duration::from_millis(10));
+        assert!(batch.events.is_empty());
+        assert_eq!(batch.next, 5);
+        // from before a restart
+        let batch = log.read(100, 10, Duration::from_millis(0));
+        assert!(batch.missed);
+        assert_eq!(batch.events[0].seq, 3);
+    }
+
+    #[test]
+    fn wait_for_event() {
+        let log = Arc::new(EventLog::new(3));
+        let reader = {
+            let log = Arc::clone(&log);
+            thread::spawn(move || log.read(0, 10, Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        log.publish(admission(0));
+        let batch = reader.join().unwrap();
+        assert_eq!(batch.events.len(), 1);
+        assert_eq!(batch.next, 1);
+    }
+}
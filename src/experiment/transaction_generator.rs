@@ -2,8 +2,9 @@ use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::handler::new_transaction;
 use crate::miner::memory_pool::MemoryPool;
 use crate::network::server::Handle as ServerHandle;
+use crate::utxodb::UtxoDatabase;
 
-use crate::wallet::Wallet;
+use crate::wallet::{CoinSelection, Wallet, DEFAULT_ACCOUNT};
 use crossbeam::channel;
 use log::{info, trace};
 use rand::Rng;
@@ -46,6 +47,7 @@ pub struct TransactionGenerator {
     wallet: Arc<Wallet>,
     server: ServerHandle,
     mempool: Arc<Mutex<MemoryPool>>,
+    utxodb: Arc<UtxoDatabase>,
     control_chan: channel::Receiver<ControlSignal>,
     arrival_distribution: ArrivalDistribution,
     value_distribution: ValueDistribution,
@@ -57,12 +59,14 @@ impl TransactionGenerator {
         wallet: &Arc<Wallet>,
         server: &ServerHandle,
         mempool: &Arc<Mutex<MemoryPool>>,
+        utxodb: &Arc<UtxoDatabase>,
     ) -> (Self, channel::Sender<ControlSignal>) {
         let (tx, rx) = channel::unbounded();
         let instance = Self {
             wallet: Arc::clone(wallet),
             server: server.clone(),
             mempool: Arc::clone(mempool),
+            utxodb: Arc::clone(utxodb),
             control_chan: rx,
             arrival_distribution: ArrivalDistribution::Uniform(UniformArrival { interval: 100 }),
             value_distribution: ValueDistribution::Uniform(UniformValue { min: 50, max: 100 }),
@@ -145,12 +149,23 @@ impl TransactionGenerator {
                         }
                     }
                 };
-                let transaction = self.wallet.create_transaction(addr, value, prev_coin);
+                let transaction = self.wallet.create_transaction(
+                    DEFAULT_ACCOUNT,
+                    addr,
+                    value,
+                    0,
+                    CoinSelection::InOrder(prev_coin),
+                );
                 PERFORMANCE_COUNTER.record_generate_transaction(&transaction);
                 match transaction {
                     Ok(t) => {
                         prev_coin = Some(t.input.last().unwrap().coin);
-                        new_transaction(t, &self.mempool, &self.server);
+                        if let Err(e) =
+                            new_transaction(t.clone(), &self.mempool, &self.utxodb, &self.server)
+                        {
+                            trace!("Generated transaction rejected: {}", e);
+                            self.wallet.abandon_transaction(&t).unwrap();
+                        }
                         // if we are in stepping mode, decrease the step count
                         if let State::Step(step_count) = self.state {
                             if step_count - 1 == 0 {
@@ -1,5 +1,5 @@
 mod new_transaction;
 mod new_validated_block;
 
-pub use new_transaction::new_transaction;
+pub use new_transaction::{new_transaction, new_transactions};
 pub use new_validated_block::new_validated_block;
@@ -3,17 +3,43 @@ use crate::miner::memory_pool::MemoryPool;
 
 use crate::network::server::Handle;
 use crate::transaction::Transaction;
+use crate::utxodb::UtxoDatabase;
+use crate::validation::{self, TransactionRejection};
 use std::sync::Mutex;
 
-/// Handler for new transaction
-// We may want to add the result of memory pool check
-pub fn new_transaction(transaction: Transaction, mempool: &Mutex<MemoryPool>, _server: &Handle) {
-    let mut mempool = mempool.lock().unwrap();
-    // memory pool check
-    if !mempool.contains(&transaction.hash()) && !mempool.is_double_spend(&transaction.input) {
-        // if check passes, insert the new transaction into the mempool
-        //server.broadcast(Message::NewTransactionHashes(vec![transaction.hash()]));
-        mempool.insert(transaction);
+/// Handler for new transaction. Check the transaction and insert it into the memory pool, or
+/// return why it is rejected.
+pub fn new_transaction(
+    transaction: Transaction,
+    mempool: &Mutex<MemoryPool>,
+    utxodb: &UtxoDatabase,
+    server: &Handle,
+) -> Result<(), TransactionRejection> {
+    new_transactions(vec![transaction], mempool, utxodb, server)
+        .pop()
+        .unwrap()
+}
+
+/// Handler for a batch of new transactions, whose signatures are verified together. Return the
+/// result for each transaction.
+pub fn new_transactions(
+    transactions: Vec<Transaction>,
+    mempool: &Mutex<MemoryPool>,
+    utxodb: &UtxoDatabase,
+    server: &Handle,
+) -> Vec<Result<(), TransactionRejection>> {
+    let stateless_results = validation::check_transactions_stateless(&transactions);
+    let mut results = vec![];
+    for (transaction, result) in transactions.into_iter().zip(stateless_results) {
+        let result = result
+            .and_then(|()| validation::check_transaction_inputs(&transaction, utxodb))
+            .and_then(|()| {
+                let hash = transaction.hash();
+                mempool.lock().unwrap().insert(transaction)?;
+                server.announce_transaction(hash);
+                Ok(())
+            });
+        results.push(result);
     }
-    drop(mempool);
+    results
 }
@@ -2,6 +2,7 @@ use crate::block::{Block, Content};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::crypto::hash::Hashable;
+use crate::event::{Event, EVENTS};
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::miner::memory_pool::MemoryPool;
 
@@ -34,4 +35,14 @@ pub fn new_validated_block(
 
     // insert the new block into the blockchain
     chain.insert_block(&block).unwrap();
+
+    let block_type = match &block.content {
+        Content::Proposer(_) => "proposer",
+        Content::Voter(_) => "voter",
+        Content::Transaction(_) => "transaction",
+    };
+    EVENTS.publish(Event::NewBlock {
+        hash: block.hash().to_string(),
+        block_type: block_type.to_string(),
+    });
 }
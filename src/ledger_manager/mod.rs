@@ -1,22 +1,137 @@
-use crate::block::Content;
-use crate::blockchain::BlockChain;
+use crate::block::{Block, Content};
+use crate::blockchain::{BlockChain, TransactionStatus};
 use crate::blockdb::BlockDatabase;
+use crate::config::{BlockchainConfig, RewardSchedule};
 use crate::crypto::hash::{Hashable, H256};
+use crate::event::{Event, EVENTS};
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
-
-use crate::transaction::{CoinId, Output, Transaction};
+use crate::miner::memory_pool::MemoryPool;
+use crate::transaction::{Address, CoinId, Output, Transaction};
 use crate::utxodb::UtxoDatabase;
-use crate::wallet::Wallet;
+use crate::wallet::{Wallet, WalletError};
 use crossbeam::channel;
+use log::{debug, info, warn};
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time;
+use std::{error, fmt};
+
+/// Minimum interval between two checkpoints of the UTXO database.
+const CHECKPOINT_INTERVAL: time::Duration = time::Duration::from_secs(30);
+/// Interval between two checks of the transactions the wallet is waiting on. A transaction that
+/// is neither in the memory pool nor in a block at a check is dropped, as long as it is older
+/// than this.
+const SPEND_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(10);
+/// How long a transaction of the wallet can stay out of the ledger before we give up on it and
+/// return its coins, and how long it must stay in the ledger before we stop watching it.
+const SPEND_TIMEOUT: time::Duration = time::Duration::from_secs(600);
+
+/// A transaction in the ledger, with its hash and the miner who gets its fee.
+type LedgerTransaction = (Transaction, H256, Address);
+
+#[derive(Debug)]
+pub enum RecoveryError {
+    ConfigMismatch(String),
+    MissingBlock(H256),
+    CheckpointMismatch(u64),
+    WalletError(WalletError),
+    DBError(rocksdb::Error),
+}
+
+impl fmt::Display for RecoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecoveryError::ConfigMismatch(ref e) => {
+                write!(f, "database does not match the config: {}", e)
+            }
+            RecoveryError::MissingBlock(ref h) => {
+                write!(
+                    f,
+                    "block {} is in the blockchain but not in the block database",
+                    h
+                )
+            }
+            RecoveryError::CheckpointMismatch(l) => write!(
+                f,
+                "UTXO checkpoint at level {} is no longer in the ledger",
+                l
+            ),
+            RecoveryError::WalletError(ref e) => e.fmt(f),
+            RecoveryError::DBError(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for RecoveryError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RecoveryError::WalletError(ref e) => Some(e),
+            RecoveryError::DBError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rocksdb::Error> for RecoveryError {
+    fn from(err: rocksdb::Error) -> RecoveryError {
+        RecoveryError::DBError(err)
+    }
+}
+
+impl From<WalletError> for RecoveryError {
+    fn from(err: WalletError) -> RecoveryError {
+        RecoveryError::WalletError(err)
+    }
+}
 
 pub struct LedgerManager {
     blockdb: Arc<BlockDatabase>,
     chain: Arc<BlockChain>,
     utxodb: Arc<UtxoDatabase>,
     wallet: Arc<Wallet>,
+    mempool: Arc<Mutex<MemoryPool>>,
+    updates: Arc<Updates>,
+    config: BlockchainConfig,
+}
+
+/// Counts the changes to the ledger, so that others can wait for the next one.
+#[derive(Default)]
+struct Updates {
+    count: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl Updates {
+    /// Count a change to the ledger, and wake those waiting for it.
+    fn bump(&self) {
+        *self.count.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    updates: Arc<Updates>,
+}
+
+impl Handle {
+    /// Get the number of changes to the ledger so far.
+    pub fn updates(&self) -> u64 {
+        *self.updates.count.lock().unwrap()
+    }
+
+    /// Wait until the number of changes to the ledger goes past the given one, or until the
+    /// timeout. Returns the number of changes to the ledger so far.
+    pub fn wait_update(&self, since: u64, timeout: time::Duration) -> u64 {
+        let count = self.updates.count.lock().unwrap();
+        let (count, _) = self
+            .updates
+            .changed
+            .wait_timeout_while(count, timeout, |count| *count <= since)
+            .unwrap();
+        *count
+    }
 }
 
 impl LedgerManager {
@@ -25,27 +140,194 @@ impl LedgerManager {
         chain: &Arc<BlockChain>,
         utxodb: &Arc<UtxoDatabase>,
         wallet: &Arc<Wallet>,
+        mempool: &Arc<Mutex<MemoryPool>>,
+        config: BlockchainConfig,
     ) -> Self {
         Self {
             blockdb: Arc::clone(&blockdb),
             chain: Arc::clone(&chain),
             utxodb: Arc::clone(&utxodb),
             wallet: Arc::clone(&wallet),
+            mempool: Arc::clone(&mempool),
+            updates: Arc::new(Updates::default()),
+            config,
         }
     }
 
-    pub fn start(self, buffer_size: usize, num_workers: usize) {
+    /// Create the initial coins of the genesis in the new UTXO set and the wallet, and checkpoint
+    /// them. Must be called before `start` unless recovering.
+    pub fn apply_genesis(&self) -> Result<(), WalletError> {
+        let transaction = self.config.genesis_transaction();
+        let (added, _) =
+            self.utxodb
+                .add_transaction(&transaction, transaction.hash(), &Address::default())?;
+        self.utxodb
+            .set_checkpoint(0, self.config.proposer_genesis, &self.config.voter_genesis)?;
+        self.wallet.apply_diff(&added, &[])?;
+        info!(
+            "Created {} initial coins, wallet has {} of them",
+            added.len(),
+            self.wallet.number_of_coins()
+        );
+        Ok(())
+    }
+
+    /// Check the databases loaded from the disk against each other and the config, and bring the
+    /// UTXO set and the wallet up to the current ledger. Must be called before `start`.
+    ///
+    /// The UTXO set is written without WAL, so `UtxoDatabase::load` restores it to the copy saved
+    /// at its last checkpoint, and we replay the ledger after the checkpoint on top of it.
+    pub fn recover(&self) -> Result<(), RecoveryError> {
+        let config = &self.config;
+        // the databases must have been created with the same genesis
+        if !self.chain.contains_proposer(&config.proposer_genesis)? {
+            return Err(RecoveryError::ConfigMismatch(
+                "proposer genesis block not found".to_string(),
+            ));
+        }
+        let genesis_digest = match self.blockdb.get(&config.proposer_genesis)? {
+            Some(block) => H256::from(block.header.extra_content),
+            None => return Err(RecoveryError::MissingBlock(config.proposer_genesis)),
+        };
+        if genesis_digest != config.hash() {
+            return Err(RecoveryError::ConfigMismatch(
+                "database was created with another genesis".to_string(),
+            ));
+        }
+        let voter_chains = self.chain.num_voter_chains()?;
+        if voter_chains != config.voter_chains {
+            return Err(RecoveryError::ConfigMismatch(format!(
+                "database has {} voter chains, config has {}",
+                voter_chains, config.voter_chains
+            )));
+        }
+
+        // the tips, the leaders and the unreferred blocks of the blockchain must be in the block
+        // database
+        let (proposer_bottom, proposer_tip, _) = self.chain.proposer_bottom_tip()?;
+        let mut to_check = vec![proposer_bottom, proposer_tip];
+        for (bottom, tip, _) in self.chain.voter_bottom_tip()? {
+            to_check.push(bottom);
+            to_check.push(tip);
+        }
+        to_check.extend(self.chain.proposer_leaders()?);
+        to_check.extend(self.chain.unreferred_proposers());
+        to_check.extend(self.chain.unreferred_transactions());
+        for hash in &to_check {
+            if *hash != H256::default() && !self.blockdb.contains(hash)? {
+                return Err(RecoveryError::MissingBlock(*hash));
+            }
+        }
+
+        // replay the ledger after the checkpoint
+        let (tip_level, _) = self.chain.proposer_ledger_tip()?;
+        let leaders = self.chain.proposer_leaders()?;
+        let (checkpoint_level, checkpoint_leader) = match self.utxodb.checkpoint()? {
+            Some(c) => c,
+            None => (0, config.proposer_genesis),
+        };
+        if checkpoint_level > tip_level || leaders[checkpoint_level as usize] != checkpoint_leader {
+            return Err(RecoveryError::CheckpointMismatch(checkpoint_level));
+        }
+        // the rewards of the voter blocks go first, since the ledger may spend them
+        let voter_checkpoint = match self.utxodb.voter_checkpoint()? {
+            Some(tips) => tips,
+            None => config.voter_genesis.clone(),
+        };
+        let voter_tips = self.chain.voter_ledger_tips();
+        if voter_checkpoint != voter_tips {
+            info!("Replaying the rewards of voter blocks");
+            for (from, to) in voter_checkpoint.iter().zip(&voter_tips) {
+                let (added, removed) = self.chain.voter_chain_diff(*from, *to)?;
+                for hash in removed.iter().rev() {
+                    for (t, h, _) in self.block_transactions(hash)?.iter().rev() {
+                        self.utxodb.remove_transaction(t, *h)?;
+                    }
+                }
+                for hash in &added {
+                    for (t, h, miner) in &self.block_transactions(hash)? {
+                        self.utxodb.add_transaction(t, *h, miner)?;
+                    }
+                }
+            }
+        }
+        if checkpoint_level < tip_level {
+            info!(
+                "Replaying the ledger from level {} to {}",
+                checkpoint_level + 1,
+                tip_level
+            );
+            let ledger = self
+                .chain
+                .proposer_transaction_in_ledger(tip_level - checkpoint_level - 1)?;
+            for (proposer, transaction_blocks) in ledger {
+                // a transaction block applies with the proposer block that put it in the ledger,
+                // which may be before the checkpoint
+                let mut blocks = vec![proposer];
+                for hash in transaction_blocks {
+                    if self.chain.transaction_block_ledger_proposer(&hash)? == Some(proposer) {
+                        blocks.push(hash);
+                    }
+                }
+                for hash in &blocks {
+                    for (t, h, miner) in &self.block_transactions(hash)? {
+                        self.utxodb.add_transaction(t, *h, miner)?;
+                    }
+                }
+            }
+        }
+        let (level, leader) = self.chain.proposer_ledger_tip()?;
+        self.utxodb.set_checkpoint(level, leader, &voter_tips)?;
+
+        // the wallet follows the UTXO set
+        self.wallet.rescan(&self.utxodb)?;
+        info!(
+            "Recovered ledger at level {}, wallet has {} coins",
+            tip_level,
+            self.wallet.number_of_coins()
+        );
+        Ok(())
+    }
+
+    /// Get what a block of the ledger applies to the UTXO set, for replaying it.
+    fn block_transactions(&self, hash: &H256) -> Result<Vec<LedgerTransaction>, RecoveryError> {
+        let block = match self.blockdb.get(hash)? {
+            Some(b) => b,
+            None => return Err(RecoveryError::MissingBlock(*hash)),
+        };
+        Ok(ledger_transactions(
+            &block,
+            *hash,
+            &self.chain,
+            &self.config.rewards,
+        )?)
+    }
+
+    pub fn start(self, buffer_size: usize, num_workers: usize) -> Handle {
         // start thread that updates transaction sequence
         let blockdb = Arc::clone(&self.blockdb);
         let chain = Arc::clone(&self.chain);
+        let updates = Arc::clone(&self.updates);
+        let wallet = Arc::clone(&self.wallet);
+        let rewards = self.config.rewards.clone();
         let (tx_diff_tx, tx_diff_rx) = channel::bounded(buffer_size);
         thread::spawn(move || loop {
-            let tx_diff = update_transaction_sequence(&blockdb, &chain);
-            tx_diff_tx.send(tx_diff).unwrap();
+            let tx_diff = update_transaction_sequence(&blockdb, &chain, &rewards);
+            if !tx_diff.0.is_empty() || !tx_diff.1.is_empty() {
+                updates.bump();
+                // settle the transactions of the wallet
+                let removed: Vec<H256> = tx_diff.1.iter().map(|(_, h, _)| *h).collect();
+                let added: Vec<H256> = tx_diff.0.iter().map(|(_, h, _)| *h).collect();
+                wallet.deconfirm_spends(&removed).unwrap();
+                wallet.confirm_spends(&added).unwrap();
+            }
+            let ledger_tip = chain.proposer_ledger_tip().unwrap();
+            let voter_tips = chain.voter_ledger_tips();
+            tx_diff_tx.send((tx_diff, ledger_tip, voter_tips)).unwrap();
         });
 
         // start thread that dispatches jobs to utxo manager
-        let _utxodb = Arc::clone(&self.utxodb);
+        let utxodb = Arc::clone(&self.utxodb);
         // Scoreboard notes the transaction ID of the coins that is being looked up, may be added,
         // or may be deleted. Before dispatching a transaction, we first check whether the input
         // and output are used by transactions being processed. If no, we will dispatch this
@@ -62,12 +344,14 @@ impl LedgerManager {
         let (coin_diff_tx, coin_diff_rx) = channel::unbounded();
 
         thread::spawn(move || {
+            let mut last_checkpoint = time::Instant::now();
             loop {
                 // get the diff
-                let (mut added_tx, mut removed_tx) = tx_diff_rx.recv().unwrap();
+                let ((mut added_tx, mut removed_tx), (tip_level, tip_leader), voter_tips) =
+                    tx_diff_rx.recv().unwrap();
 
                 // dispatch transactions
-                for (t, h) in removed_tx.drain(..).rev() {
+                for (t, h, miner) in removed_tx.drain(..).rev() {
                     // drain the notification channel so that we mark all finished transaction as
                     // finished
                     for processed in notification_rx.try_iter() {
@@ -100,9 +384,9 @@ impl LedgerManager {
                         scoreboard.insert(hash);
                     }
                     transaction_coins.insert(h, touched);
-                    transaction_tx.send((false, t, h)).unwrap();
+                    transaction_tx.send((false, t, h, miner)).unwrap();
                 }
-                for (t, h) in added_tx.drain(..) {
+                for (t, h, miner) in added_tx.drain(..) {
                     // drain the notification channel so that we mark all finished transaction as
                     // finished
                     for processed in notification_rx.try_iter() {
@@ -135,7 +419,24 @@ impl LedgerManager {
                         scoreboard.insert(hash);
                     }
                     transaction_coins.insert(h, touched);
-                    transaction_tx.send((true, t, h)).unwrap();
+                    transaction_tx.send((true, t, h, miner)).unwrap();
+                }
+
+                // checkpoint the utxo database once all transactions up to the ledger tip are
+                // applied
+                if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                    while !transaction_coins.is_empty() {
+                        let processed = notification_rx.recv().unwrap();
+                        let finished_coins = transaction_coins.remove(&processed).unwrap();
+                        for hash in &finished_coins {
+                            scoreboard.remove(&hash);
+                        }
+                    }
+                    utxodb
+                        .set_checkpoint(tip_level, tip_leader, &voter_tips)
+                        .unwrap();
+                    debug!("Checkpointed UTXO database at level {}", tip_level);
+                    last_checkpoint = time::Instant::now();
                 }
             }
         });
@@ -146,27 +447,50 @@ impl LedgerManager {
             transaction_chan: transaction_rx,
             coin_chan: coin_diff_tx,
             notification_chan: notification_tx,
+            updates: Arc::clone(&self.updates),
         };
         utxo_manager.start(num_workers);
 
-        // start thread that writes to wallet
+        // start thread that writes to wallet, and checks on the transactions of the wallet
         let wallet = Arc::clone(&self.wallet);
-        thread::spawn(move || loop {
-            let coin_diff = coin_diff_rx.recv().unwrap();
-            wallet.apply_diff(&coin_diff.0, &coin_diff.1).unwrap();
+        let chain = Arc::clone(&self.chain);
+        let utxodb = Arc::clone(&self.utxodb);
+        let mempool = Arc::clone(&self.mempool);
+        thread::spawn(move || {
+            let mut last_check = time::Instant::now();
+            loop {
+                match coin_diff_rx.recv_timeout(SPEND_CHECK_INTERVAL) {
+                    Ok(coin_diff) => wallet.apply_diff(&coin_diff.0, &coin_diff.1).unwrap(),
+                    Err(channel::RecvTimeoutError::Timeout) => {}
+                    Err(channel::RecvTimeoutError::Disconnected) => {
+                        panic!("Wallet coin diff channel detached")
+                    }
+                }
+                if last_check.elapsed() >= SPEND_CHECK_INTERVAL {
+                    check_spends(&wallet, &chain, &utxodb, &mempool).unwrap();
+                    last_check = time::Instant::now();
+                }
+            }
         });
+
+        Handle {
+            updates: self.updates,
+        }
     }
 }
 
 #[derive(Clone)]
 struct UtxoManager {
     utxodb: Arc<UtxoDatabase>,
-    /// Channel for dispatching jobs (add/delete, transaction, hash of transaction).
-    transaction_chan: channel::Receiver<(bool, Transaction, H256)>,
+    /// Channel for dispatching jobs (add/delete, transaction, hash of transaction, miner that gets
+    /// the fee).
+    transaction_chan: channel::Receiver<(bool, Transaction, H256, Address)>,
     /// Channel for returning added and removed coins.
     coin_chan: channel::Sender<(Vec<(CoinId, Output)>, Vec<CoinId>)>,
     /// Channel for notifying the dispatcher about the completion of processing this transaction.
     notification_chan: channel::Sender<H256>,
+    /// Changes to the ledger, which include whether a transaction applies.
+    updates: Arc<Updates>,
 }
 
 impl UtxoManager {
@@ -181,59 +505,337 @@ impl UtxoManager {
 
     fn worker_loop(&self) {
         loop {
-            let (add, transaction, hash) = self.transaction_chan.recv().unwrap();
+            let (add, transaction, hash, miner) = self.transaction_chan.recv().unwrap();
             if add {
-                let diff = self.utxodb.add_transaction(&transaction, hash).unwrap();
+                let diff = self
+                    .utxodb
+                    .add_transaction(&transaction, hash, &miner)
+                    .unwrap();
                 self.coin_chan.send(diff).unwrap();
             } else {
                 let diff = self.utxodb.remove_transaction(&transaction, hash).unwrap();
                 self.coin_chan.send(diff).unwrap();
             }
+            self.updates.bump();
             self.notification_chan.send(hash).unwrap();
         }
     }
 }
 
+/// Settle the transactions of the wallet that stayed in the ledger for long enough, and give up on
+/// those that are dropped, do not apply, or stayed out of the ledger for too long.
+fn check_spends(
+    wallet: &Wallet,
+    chain: &BlockChain,
+    utxodb: &UtxoDatabase,
+    mempool: &Mutex<MemoryPool>,
+) -> Result<(), WalletError> {
+    let now = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    for (hash, spend) in wallet.spends()? {
+        // a transaction of the ledger that does not apply will never spend its coins
+        if let Some(TransactionStatus::Rejected { .. }) = chain.transaction_status(&hash, utxodb)? {
+            warn!("Transaction {} of the wallet does not apply", hash);
+            wallet.settle_spend(&hash)?;
+            continue;
+        }
+        match spend.confirmed {
+            Some(confirmed) => {
+                if now >= confirmed + SPEND_TIMEOUT.as_secs() {
+                    wallet.settle_spend(&hash)?;
+                }
+            }
+            None => {
+                if now < spend.created + SPEND_CHECK_INTERVAL.as_secs() {
+                    continue;
+                }
+                // check the memory pool first, since a transaction leaves the memory pool before
+                // its block enters the blockchain
+                let dropped = !mempool.lock().unwrap().contains(&hash)
+                    && chain.transaction_status(&hash, utxodb)?.is_none();
+                if dropped {
+                    warn!("Transaction {} of the wallet is dropped", hash);
+                    wallet.settle_spend(&hash)?;
+                } else if now >= spend.created + SPEND_TIMEOUT.as_secs() {
+                    warn!("Transaction {} of the wallet expired", hash);
+                    wallet.settle_spend(&hash)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get what a block of the ledger applies to the UTXO set, in order: the coinbase that pays the
+/// block reward, which goes by the hash of the block, and then the transactions of a transaction
+/// block, whose fees go to the miner of the block.
+fn ledger_transactions(
+    block: &Block,
+    hash: H256,
+    chain: &BlockChain,
+    rewards: &RewardSchedule,
+) -> Result<Vec<LedgerTransaction>, rocksdb::Error> {
+    let miner = block.header.miner;
+    let mut transactions = vec![];
+    // the genesis blocks have no parent, and get no reward
+    if chain.contains_proposer(&block.header.parent)? {
+        let level = chain.proposer_level(&block.header.parent)?;
+        let reward = rewards.reward(block.content.sortition_id(), level);
+        if reward > 0 {
+            transactions.push((Transaction::coinbase(miner, reward), hash, miner));
+        }
+    }
+    if let Content::Transaction(content) = &block.content {
+        // TODO: precompute the hash here. Note that although lazy-eval for tx hash, and we could
+        // have just called hash() here without storing the results (the results will be cached in
+        // the struct), such function call will be optimized away by LLVM. As a result, we have
+        // to manually pass the hash here. This is a very ugly hack.
+        transactions.extend(
+            content
+                .transactions
+                .iter()
+                .map(|t| (t.clone(), t.hash(), miner)),
+        );
+    }
+    Ok(transactions)
+}
+
+/// Update the ledger, and get the transactions to apply to the UTXO set and to revert, each in the
+/// order of the ledger.
 fn update_transaction_sequence(
     blockdb: &BlockDatabase,
     chain: &BlockChain,
-) -> (Vec<(Transaction, H256)>, Vec<(Transaction, H256)>) {
+    rewards: &RewardSchedule,
+) -> (Vec<LedgerTransaction>, Vec<LedgerTransaction>) {
     let diff = chain.update_ledger().unwrap();
-    PERFORMANCE_COUNTER.record_deconfirm_transaction_blocks(diff.1.len());
-
-    // gather the transaction diff
-    let mut add: Vec<(Transaction, H256)> = vec![];
-    let mut remove: Vec<(Transaction, H256)> = vec![];
-    for hash in diff.0 {
-        let block = blockdb.get(&hash).unwrap().unwrap();
-        PERFORMANCE_COUNTER.record_confirm_transaction_block(&block);
-        let content = match block.content {
-            Content::Transaction(data) => data,
-            _ => unreachable!(),
-        };
-        let mut transactions = content
-            .transactions
+    let added_transaction_blocks = diff.added_transaction_blocks();
+    let removed_transaction_blocks = diff.removed_transaction_blocks();
+    PERFORMANCE_COUNTER.record_deconfirm_transaction_blocks(removed_transaction_blocks.len());
+    if !added_transaction_blocks.is_empty() || !removed_transaction_blocks.is_empty() {
+        EVENTS.publish(Event::LedgerDiff {
+            added: added_transaction_blocks
+                .iter()
+                .map(|h| h.to_string())
+                .collect(),
+            removed: removed_transaction_blocks
+                .iter()
+                .map(|h| h.to_string())
+                .collect(),
+        });
+    }
+
+    // gather the transaction diff. the rewards of the voter blocks go first, since the ledger may
+    // spend them
+    let mut add: Vec<LedgerTransaction> = vec![];
+    let mut remove: Vec<LedgerTransaction> = vec![];
+    let added_blocks = diff.added_voters.iter().chain(
+        diff.added_proposers
             .iter()
-            .map(|t| (t.clone(), t.hash()))
-            .collect();
-        // TODO: precompute the hash here. Note that although lazy-eval for tx hash, and we could have
-        // just called hash() here without storing the results (the results will be cached in the struct),
-        // such function call will be optimized away by LLVM. As a result, we have to manually pass the hash
-        // here. The same for added transactions below. This is a very ugly hack.
-        add.append(&mut transactions);
-    }
-    for hash in diff.1 {
-        let block = blockdb.get(&hash).unwrap().unwrap();
-        let content = match block.content {
-            Content::Transaction(data) => data,
-            _ => unreachable!(),
-        };
-        let mut transactions = content
-            .transactions
+            .flat_map(|(p, t)| std::iter::once(p).chain(t)),
+    );
+    for hash in added_blocks {
+        let block = blockdb.get(hash).unwrap().unwrap();
+        if let Content::Transaction(_) = block.content {
+            PERFORMANCE_COUNTER.record_confirm_transaction_block(&block);
+        }
+        add.extend(ledger_transactions(&block, *hash, chain, rewards).unwrap());
+    }
+    let removed_blocks = diff.removed_voters.iter().chain(
+        diff.removed_proposers
             .iter()
-            .map(|t| (t.clone(), t.hash()))
-            .collect();
-        remove.append(&mut transactions);
+            .flat_map(|(p, t)| std::iter::once(p).chain(t)),
+    );
+    for hash in removed_blocks {
+        let block = blockdb.get(hash).unwrap().unwrap();
+        remove.extend(ledger_transactions(&block, *hash, chain, rewards).unwrap());
     }
     (add, remove)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::{proposer_block, transaction_block, voter_block};
+    use crate::config::Genesis;
+    use crate::transaction::Input;
+    use crate::utxodb::Tuning;
+
+    /// A blockchain with one voter chain, and the databases that follow its ledger.
+    struct Ledger {
+        blockdb: BlockDatabase,
+        chain: BlockChain,
+        utxodb: UtxoDatabase,
+        config: BlockchainConfig,
+    }
+
+    impl Ledger {
+        fn new(name: &str) -> Self {
+            let genesis = Genesis {
+                voter_chains: 1,
+                adversary_ratio: 0.1,
+                confirm_confidence: 2.0,
+                ..Default::default()
+            };
+            let config = genesis.config();
+            let path = |db| format!("/tmp/prism_test_ledger_manager_{}_{}.rocksdb", name, db);
+            Self {
+                blockdb: BlockDatabase::new(path("blockdb"), config.clone()).unwrap(),
+                chain: BlockChain::new(path("blockchain"), config.clone()).unwrap(),
+                utxodb: UtxoDatabase::new(path("utxodb"), false, &Tuning::default()).unwrap(),
+                config,
+            }
+        }
+
+        fn insert(&self, block: Block) -> H256 {
+            self.blockdb.insert(&block).unwrap();
+            self.chain.insert_block(&block).unwrap();
+            block.hash()
+        }
+
+        /// Update the ledger and apply it to the UTXO set the way the UTXO manager does, and get
+        /// the coins added and removed.
+        fn update(&self) -> (Vec<CoinId>, Vec<CoinId>) {
+            let (added, removed) =
+                update_transaction_sequence(&self.blockdb, &self.chain, &self.config.rewards);
+            let mut added_coins = vec![];
+            let mut removed_coins = vec![];
+            for (t, h, _) in removed.iter().rev() {
+                let (a, r) = self.utxodb.remove_transaction(t, *h).unwrap();
+                added_coins.extend(a.into_iter().map(|(c, _)| c));
+                removed_coins.extend(r);
+            }
+            for (t, h, miner) in &added {
+                let (a, r) = self.utxodb.add_transaction(t, *h, miner).unwrap();
+                added_coins.extend(a.into_iter().map(|(c, _)| c));
+                removed_coins.extend(r);
+            }
+            (added_coins, removed_coins)
+        }
+    }
+
+    #[test]
+    fn shared_transaction_block() {
+        let ledger = Ledger::new("shared_transaction_block");
+        let genesis = ledger.config.proposer_genesis;
+        let voter_genesis = ledger.config.voter_genesis[0];
+        let t = ledger.insert(transaction_block(genesis, 1, vec![]));
+        let reward = CoinId { hash: t, index: 0 };
+        let p1 = ledger.insert(proposer_block(genesis, 2, vec![], vec![t]));
+        let p2 = ledger.insert(proposer_block(p1, 3, vec![], vec![t]));
+        let other_p2 = ledger.insert(proposer_block(p1, 4, vec![], vec![]));
+        let v1 = ledger.insert(voter_block(p1, 5, 0, voter_genesis, vec![p1]));
+        let (added, _) = ledger.update();
+        assert!(added.contains(&reward));
+
+        // the second proposer block that refers to the transaction block does not pay it again
+        ledger.insert(voter_block(p2, 6, 0, v1, vec![p2]));
+        let (added, removed) = ledger.update();
+        assert_eq!(ledger.chain.proposer_ledger_tip().unwrap(), (2, p2));
+        assert!(!added.contains(&reward));
+        assert!(removed.is_empty());
+
+        // and when it leaves the ledger, the transaction block stays through the first one
+        let v2 = ledger.insert(voter_block(other_p2, 7, 0, v1, vec![other_p2]));
+        ledger.insert(voter_block(other_p2, 8, 0, v2, vec![]));
+        let (added, removed) = ledger.update();
+        assert_eq!(ledger.chain.proposer_ledger_tip().unwrap(), (2, other_p2));
+        assert!(!added.contains(&reward));
+        assert!(!removed.contains(&reward));
+        assert!(ledger.utxodb.contains(&reward).unwrap());
+
+        // until the last proposer block that refers to it leaves the ledger too
+        let other_p1 = ledger.insert(proposer_block(genesis, 9, vec![], vec![]));
+        let mut voter = ledger.insert(voter_block(other_p1, 10, 0, voter_genesis, vec![other_p1]));
+        for timestamp in 11..14 {
+            voter = ledger.insert(voter_block(other_p1, timestamp, 0, voter, vec![]));
+        }
+        let (added, removed) = ledger.update();
+        assert_eq!(ledger.chain.proposer_ledger_tip().unwrap(), (1, other_p1));
+        assert!(!added.contains(&reward));
+        assert_eq!(removed.iter().filter(|c| **c == reward).count(), 1);
+        assert!(!ledger.utxodb.contains(&reward).unwrap());
+    }
+
+    #[test]
+    fn rejected_transaction() {
+        let ledger = Ledger::new("rejected_transaction");
+        let genesis = ledger.config.proposer_genesis;
+        let voter_genesis = ledger.config.voter_genesis[0];
+        let miner = Address::default();
+        let coinbase = Transaction::coinbase(miner, 1);
+        // spends a coin that does not exist
+        let spend = Transaction {
+            input: vec![Input {
+                coin: CoinId {
+                    hash: [7u8; 32].into(),
+                    index: 0,
+                },
+                value: 1,
+                owner: miner,
+            }],
+            output: vec![Output {
+                value: 1,
+                recipient: miner,
+            }],
+            authorization: vec![],
+            multisig: vec![],
+            hash: Default::default(),
+        };
+        let status = |t: &Transaction| {
+            ledger
+                .chain
+                .transaction_status(&t.hash(), &ledger.utxodb)
+                .unwrap()
+        };
+        let block = ledger.insert(transaction_block(
+            genesis,
+            1,
+            vec![coinbase.clone(), spend.clone()],
+        ));
+        assert_eq!(status(&spend), Some(TransactionStatus::Included { block }));
+
+        // the transaction block is referred to, but not in the ledger yet
+        let proposer = ledger.insert(proposer_block(genesis, 2, vec![], vec![block]));
+        assert_eq!(
+            status(&spend),
+            Some(TransactionStatus::Referred { block, proposer })
+        );
+
+        ledger.insert(voter_block(proposer, 3, 0, voter_genesis, vec![proposer]));
+        ledger.update();
+        assert_eq!(
+            status(&coinbase),
+            Some(TransactionStatus::Confirmed {
+                block,
+                proposer,
+                level: 1
+            })
+        );
+        assert_eq!(
+            status(&spend),
+            Some(TransactionStatus::Rejected {
+                block,
+                proposer,
+                level: 1
+            })
+        );
+
+        // a transaction that leaves the ledger is tried again when it comes back
+        let other = ledger.insert(proposer_block(genesis, 4, vec![], vec![]));
+        let mut voter = ledger.insert(voter_block(other, 5, 0, voter_genesis, vec![other]));
+        voter = ledger.insert(voter_block(other, 6, 0, voter, vec![]));
+        ledger.insert(voter_block(other, 7, 0, voter, vec![]));
+        ledger.update();
+        assert_eq!(
+            status(&spend),
+            Some(TransactionStatus::Deconfirmed {
+                block,
+                proposer,
+                level: 1
+            })
+        );
+        assert_eq!(ledger.utxodb.applied(&spend.hash()).unwrap(), None);
+    }
+}
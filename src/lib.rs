@@ -12,6 +12,7 @@ pub mod blockchain;
 pub mod blockdb;
 pub mod config;
 pub mod crypto;
+pub mod event;
 pub mod experiment;
 pub mod handler;
 pub mod ledger_manager;
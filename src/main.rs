@@ -8,22 +8,24 @@ use piper;
 use prism::api::Server as ApiServer;
 use prism::blockchain::BlockChain;
 use prism::blockdb::BlockDatabase;
-use prism::config::BlockchainConfig;
-use prism::crypto::hash::H256;
+use prism::config::{parse_address, Genesis, NodeConfig};
+use prism::crypto::hash::Hashable;
 use prism::experiment::transaction_generator::TransactionGenerator;
 use prism::ledger_manager::LedgerManager;
 use prism::miner;
 use prism::miner::memory_pool::MemoryPool;
+use prism::network::address_book::AddressBook;
 use prism::network::server;
 use prism::network::worker;
 use prism::transaction::Address;
 use prism::utxodb::UtxoDatabase;
 use prism::visualization::Server as VisualizationServer;
-use prism::wallet::Wallet;
+use prism::wallet::{self, EncryptedKeypair, Wallet, DEFAULT_ACCOUNT};
 use rand::rngs::OsRng;
-use std::convert::TryInto;
-use std::net;
+use std::fmt;
+use std::path::Path;
 use std::process;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::thread;
 use std::time;
@@ -34,33 +36,42 @@ fn main() {
      (version: "0.1")
      (about: "Prism blockchain full client")
      (@arg verbose: -v ... "Increases the verbosity of logging")
-     (@arg peer_addr: --p2p [ADDR] default_value("127.0.0.1:6000") "Sets the IP address and the port of the P2P server")
-     (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
-     (@arg visualization: --visual [ADDR] "Enables the visualization server and sets its address and port")
-     (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
-     (@arg block_db: --blockdb [PATH] default_value("/tmp/prism-blocks.rocksdb") "Sets the path to the block database")
-     (@arg utxo_db: --utxodb [PATH] default_value("/tmp/prism-utxo.rocksdb") "Sets the path to the UTXO database")
-     (@arg blockchain_db: --blockchaindb [PATH] default_value("/tmp/prism-blockchain.rocksdb") "Sets the path to the blockchain database")
-     (@arg wallet_db: --walletdb [PATH] default_value("/tmp/prism-wallet.rocksdb") "Sets the path to the wallet database")
-     (@arg init_fund_addr: --("fund-addr") ... [ADDR] "Endows the given address an initial fund in the genesis block")
-     (@arg init_fund_coins: --("fund-coins") [INT] default_value("50000") "Sets the number of initial coins for each address")
-     (@arg init_fund_value: --("fund-value") [INT] default_value("100") "Sets the value of each initial coin")
-     (@arg load_key_path: --("load-key") ... [PATH] "Loads a key pair into the wallet from the given path")
-     (@arg mempool_size: --("mempool-size") [INT] default_value("500000") "Sets the maximum number of transactions for the memory pool")
-     (@arg execution_workers: --("execution-workers") [INT] default_value("8") "Sets the number of worker threads for transaction execution")
-     (@arg execution_buffer: --("execution-buffer") [INT] default_value("3") "Sets the size of the buffer between pipeline stages in transaction execution")
-     (@arg p2p_workers: --("p2p-workers") [INT] default_value("16") "Sets the number of worker threads for P2P server")
-     (@arg voter_chains: --("voter-chains") [INT] default_value("1000") "Sets the number of voter chains")
-     (@arg tx_throughput: --("tx-throughput") [INT] default_value("80000") "Sets the target transaction throughput")
-     (@arg tx_block_size: --("tx-block-size") [INT] default_value("64000") "Sets the maximum size of the transaction block in Bytes")
-     (@arg proposer_mining_rate: --("proposer-mining-rate") [FLOAT] default_value("0.1") "Sets the proposer chain mining rate")
-     (@arg voter_mining_rate: --("voter-mining-rate") [FLOAT] default_value("0.1") "Sets the voter chain mining rate")
-     (@arg adv_ratio: --("adversary-ratio") [FLOAT] default_value("0.4") "Sets the ratio of adversary hashing power")
-     (@arg log_epsilon: --("confirm-confidence") [FLOAT] default_value("20.0") "Sets -log(epsilon) for confirmation")
+     (@arg config: --config [PATH] "Loads the node parameters from the given TOML or JSON file, which the other flags override")
+     (@arg p2p: --p2p [ADDR] "Sets the IP address and the port of the P2P server")
+     (@arg api: --api [ADDR] "Sets the IP address and the port of the API server")
+     (@arg visual: --visual [ADDR] "Enables the visualization server and sets its address and port")
+     (@arg connect: -c --connect ... [PEER] "Sets the peers to connect to at start")
+     (@arg outgoing_peers: --("outgoing-peers") [INT] "Sets the number of outgoing peers to keep by connecting to known addresses")
+     (@arg peerdb: --peerdb [PATH] "Sets the path to the address book of peers")
+     (@arg blockdb: --blockdb [PATH] "Sets the path to the block database")
+     (@arg utxodb: --utxodb [PATH] "Sets the path to the UTXO database")
+     (@arg blockchaindb: --blockchaindb [PATH] "Sets the path to the blockchain database")
+     (@arg walletdb: --walletdb [PATH] "Sets the path to the wallet database")
+     (@arg address_index: --("address-index") "Indexes the UTXO set by address to serve coins and balances of any address")
+     (@arg resume: --resume "Reopens the existing databases instead of creating new ones")
+     (@arg genesis: --genesis [PATH] "Sets the genesis file, which holds the blockchain parameters and the initial coins")
+     (@arg load_key: --("load-key") ... [PATH] "Loads a key pair into the wallet from the given path")
+     (@arg wallet_seed_file: --("wallet-seed-file") [PATH] "Restores the wallet from the seed in the given file and scans the UTXO set for its coins")
+     (@arg wallet_gap_limit: --("wallet-gap-limit") [INT] "Sets the number of addresses in a row without coins after which restoring a wallet stops looking")
+     (@arg wallet_passphrase_file: --("wallet-passphrase-file") [PATH] "Encrypts the wallet keys with the passphrase in the given file if they are not, and unlocks the wallet with it")
+     (@arg mempool_size: --("mempool-size") [INT] "Sets the maximum number of transactions for the memory pool")
+     (@arg execution_workers: --("execution-workers") [INT] "Sets the number of worker threads for transaction execution")
+     (@arg execution_buffer: --("execution-buffer") [INT] "Sets the size of the buffer between pipeline stages in transaction execution")
+     (@arg p2p_workers: --("p2p-workers") [INT] "Sets the number of worker threads for P2P server")
+     (@arg p2p_queue_size: --("p2p-queue-size") [INT] "Sets the number of received messages to buffer for the P2P workers")
+     (@arg p2p_control_queue_size: --("p2p-control-queue-size") [INT] "Sets the number of control signals, e.g. messages to send, to buffer for the P2P server")
+     (@arg max_message_size: --("max-message-size") ... [TYPE_BYTES] "Sets the maximum size of a message type in Bytes, e.g. Blocks=33554432")
+     (@arg peer_byte_rate: --("peer-byte-rate") [INT] "Sets the maximum number of Bytes per second to read from each peer")
+     (@arg peer_message_rate: --("peer-message-rate") [INT] "Sets the maximum number of messages per second to read from each peer")
+     (@arg miner_addr: --("miner-addr") [ADDR] "Sets the address to receive the rewards of the blocks we mine, instead of an address of the wallet")
 
      (@subcommand keygen =>
       (about: "Generates Prism wallet key pair")
       (@arg display_address: --addr "Prints the address of the key pair to STDERR")
+      (@arg passphrase_file: --("passphrase-file") [PATH] "Encrypts the key pair with the passphrase in the given file and prints it as JSON")
+      (@arg seed: --seed conflicts_with[from_seed passphrase_file] "Generates a wallet seed in hex instead of a key pair")
+      (@arg from_seed: --("from-seed") [PATH] "Derives the key pair from the wallet seed in the given file instead of generating it")
+      (@arg index: --index [INT] default_value("0") "Sets the index of the receiving address to derive the key pair of")
      )
     )
     .get_matches();
@@ -68,94 +79,139 @@ fn main() {
     // match subcommands
     match matches.subcommand() {
         ("keygen", Some(m)) => {
-            let mut csprng = OsRng;
-            let keypair: Keypair = Keypair::generate(&mut csprng);
-            let base64_encoded = base64::encode(&keypair.to_bytes().to_vec());
-            println!("{}", base64_encoded);
+            if m.is_present("seed") {
+                let seed = wallet::generate_seed();
+                println!("{}", hex::encode(&seed));
+                if m.is_present("display_address") {
+                    let keypair = wallet::address_keypair(&seed, false, 0);
+                    let addr: Address = ring::digest::digest(
+                        &ring::digest::SHA256,
+                        keypair.public.as_bytes().as_ref(),
+                    )
+                    .into();
+                    eprintln!("{}", base64::encode(&addr));
+                }
+                return;
+            }
+            let keypair: Keypair = match m.value_of("from_seed") {
+                Some(path) => {
+                    let seed = read_seed(path).unwrap_or_else(|e| {
+                        eprintln!("Error reading seed at {}: {}", path, e);
+                        process::exit(1);
+                    });
+                    let index = m
+                        .value_of("index")
+                        .unwrap()
+                        .parse::<u32>()
+                        .unwrap_or_else(|e| {
+                            eprintln!("Error parsing address index: {}", e);
+                            process::exit(1);
+                        });
+                    wallet::address_keypair(&seed, false, index)
+                }
+                None => {
+                    let mut csprng = OsRng;
+                    Keypair::generate(&mut csprng)
+                }
+            };
+            let addr: Address =
+                ring::digest::digest(&ring::digest::SHA256, &keypair.public.as_bytes().as_ref())
+                    .into();
+            if let Some(path) = m.value_of("passphrase_file") {
+                let passphrase = read_passphrase(path).unwrap_or_else(|e| {
+                    eprintln!("Error reading passphrase at {}: {}", path, e);
+                    process::exit(1);
+                });
+                let encrypted = EncryptedKeypair::seal(&keypair, &addr.to_string(), &passphrase);
+                println!("{}", serde_json::to_string(&encrypted).unwrap());
+            } else {
+                let base64_encoded = base64::encode(&keypair.to_bytes().to_vec());
+                println!("{}", base64_encoded);
+            }
             if m.is_present("display_address") {
-                let addr: Address = ring::digest::digest(
-                    &ring::digest::SHA256,
-                    &keypair.public.as_bytes().as_ref(),
-                )
-                .into();
-                let base64_encoded = base64::encode(&addr);
-                eprintln!("{}", base64_encoded);
+                eprintln!("{}", base64::encode(&addr));
             }
             return;
         }
         _ => {}
     }
 
-    // init logger
-    let verbosity = matches.occurrences_of("verbose") as usize;
-    stderrlog::new().verbosity(verbosity).init().unwrap();
-
-    // init config struct
-    let voter_chains: u16 = matches
-        .value_of("voter_chains")
-        .unwrap()
-        .parse()
-        .unwrap_or_else(|e| {
-            error!("Error parsing voter chain number: {}", e);
-            process::exit(1);
-        });
-    let tx_throughput = matches
-        .value_of("tx_throughput")
-        .unwrap()
-        .parse::<u32>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing target transaction throughput: {}", e);
-            process::exit(1);
-        });
-    let tx_blk_size = matches
-        .value_of("tx_block_size")
-        .unwrap()
-        .parse::<u32>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing transaction block size: {}", e);
-            process::exit(1);
-        });
-    let proposer_mining_rate = matches
-        .value_of("proposer_mining_rate")
-        .unwrap()
-        .parse::<f32>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing proposer chain mining rate: {}", e);
-            process::exit(1);
-        });
-    let voter_mining_rate = matches
-        .value_of("voter_mining_rate")
-        .unwrap()
-        .parse::<f32>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing voter chain mining rate: {}", e);
+    // load the node config, and override it with the flags
+    let mut node = match matches.value_of("config") {
+        Some(path) => NodeConfig::load(path).unwrap_or_else(|e| {
+            eprintln!("Error loading config file at {}: {}", path, e);
             process::exit(1);
-        });
-    let adv_ratio = matches
-        .value_of("adv_ratio")
-        .unwrap()
-        .parse::<f32>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing adversary power ratio: {}", e);
+        }),
+        None => NodeConfig::default(),
+    };
+    if matches.occurrences_of("verbose") > 0 {
+        node.verbose = matches.occurrences_of("verbose") as usize;
+    }
+    set_flag(&matches, "p2p", &mut node.p2p);
+    set_flag(&matches, "api", &mut node.api);
+    set_optional_flag(&matches, "visual", &mut node.visual);
+    set_list_flag(&matches, "connect", &mut node.connect);
+    set_flag(&matches, "outgoing_peers", &mut node.outgoing_peers);
+    set_flag(&matches, "peerdb", &mut node.peerdb);
+    set_flag(&matches, "blockdb", &mut node.blockdb);
+    set_flag(&matches, "utxodb", &mut node.utxodb);
+    set_flag(&matches, "blockchaindb", &mut node.blockchaindb);
+    set_flag(&matches, "walletdb", &mut node.walletdb);
+    node.address_index |= matches.is_present("address_index");
+    node.resume |= matches.is_present("resume");
+    set_optional_flag(&matches, "genesis", &mut node.genesis);
+    set_list_flag(&matches, "load_key", &mut node.load_key);
+    set_optional_flag(&matches, "wallet_seed_file", &mut node.wallet_seed_file);
+    set_flag(&matches, "wallet_gap_limit", &mut node.wallet_gap_limit);
+    set_optional_flag(
+        &matches,
+        "wallet_passphrase_file",
+        &mut node.wallet_passphrase_file,
+    );
+    set_flag(&matches, "mempool_size", &mut node.mempool_size);
+    set_flag(&matches, "execution_workers", &mut node.execution_workers);
+    set_flag(&matches, "execution_buffer", &mut node.execution_buffer);
+    set_flag(&matches, "p2p_workers", &mut node.p2p_workers);
+    set_flag(&matches, "p2p_queue_size", &mut node.p2p_queue_size);
+    set_flag(
+        &matches,
+        "p2p_control_queue_size",
+        &mut node.p2p_control_queue_size,
+    );
+    if let Some(sizes) = matches.values_of("max_message_size") {
+        for size in sizes {
+            let mut split = size.splitn(2, '=');
+            let variant = split.next().unwrap().to_string();
+            let bytes = parse_flag("max_message_size", split.next().unwrap_or(""));
+            node.max_message_size.insert(variant, bytes);
+        }
+    }
+    set_flag(&matches, "peer_byte_rate", &mut node.peer_byte_rate);
+    set_flag(&matches, "peer_message_rate", &mut node.peer_message_rate);
+    if let Some(addr) = matches.value_of("miner_addr") {
+        node.miner_addr = Some(parse_address(addr).unwrap_or_else(|e| {
+            eprintln!("Error parsing miner_addr: {}", e);
             process::exit(1);
-        });
-    let log_epsilon = matches
-        .value_of("log_epsilon")
-        .unwrap()
-        .parse::<f32>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing confirm confidence: {}", e);
+        }));
+    }
+    node.check().unwrap_or_else(|e| {
+        eprintln!("Error in node config: {}", e);
+        process::exit(1);
+    });
+
+    // init logger
+    stderrlog::new().verbosity(node.verbose).init().unwrap();
+
+    // load the genesis, which sets the blockchain parameters
+    let genesis = match &node.genesis {
+        Some(path) => Genesis::load(path).unwrap_or_else(|e| {
+            error!("Error loading genesis file at {}: {}", path.display(), e);
             process::exit(1);
-        });
-    let config = BlockchainConfig::new(
-        voter_chains,
-        tx_blk_size,
-        tx_throughput,
-        proposer_mining_rate,
-        voter_mining_rate,
-        adv_ratio,
-        log_epsilon,
-    );
+        }),
+        None => Genesis::default(),
+    };
+    let config = genesis.config();
+    info!("Genesis digest is {}", config.hash());
     info!(
         "Proposer block mining rate set to {} blks/s",
         config.proposer_mining_rate
@@ -170,132 +226,221 @@ fn main() {
     );
 
     // init mempool
-    let mempool_size = matches
-        .value_of("mempool_size")
-        .unwrap()
-        .parse::<u64>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing memory pool size limit: {}", e);
-            process::exit(1);
-        });
-    let mempool = MemoryPool::new(mempool_size);
+    let mempool = MemoryPool::new(node.mempool_size);
     let mempool = Arc::new(std::sync::Mutex::new(mempool));
-    debug!("Initialized mempool, maximum size set to {}", mempool_size);
+    debug!(
+        "Initialized mempool, maximum size set to {}",
+        node.mempool_size
+    );
+
+    // whether to reopen the databases of a previous run
+    let resume = node.resume;
 
     // init block database
-    let blockdb =
-        BlockDatabase::new(&matches.value_of("block_db").unwrap(), config.clone()).unwrap();
+    let blockdb = if resume {
+        BlockDatabase::load(&node.blockdb, config.clone())
+    } else {
+        BlockDatabase::new(&node.blockdb, config.clone())
+    }
+    .unwrap_or_else(|e| {
+        error!("Error opening block database: {}", e);
+        process::exit(1);
+    });
     let blockdb = Arc::new(blockdb);
     debug!("Initialized block database");
 
     // init utxo database
-    let utxodb = UtxoDatabase::new(&matches.value_of("utxo_db").unwrap()).unwrap();
+    let utxodb = if resume {
+        UtxoDatabase::load(&node.utxodb, node.address_index, &node.utxodb_tuning)
+    } else {
+        UtxoDatabase::new(&node.utxodb, node.address_index, &node.utxodb_tuning)
+    }
+    .unwrap_or_else(|e| {
+        error!("Error opening UTXO database: {}", e);
+        process::exit(1);
+    });
     let utxodb = Arc::new(utxodb);
     debug!("Initialized UTXO database");
 
     // init blockchain database
-    let blockchain =
-        BlockChain::new(&matches.value_of("blockchain_db").unwrap(), config.clone()).unwrap();
+    let blockchain = if resume {
+        BlockChain::load(&node.blockchaindb, config.clone())
+    } else {
+        BlockChain::new(&node.blockchaindb, config.clone())
+    }
+    .unwrap_or_else(|e| {
+        error!("Error opening blockchain database: {}", e);
+        process::exit(1);
+    });
     let blockchain = Arc::new(blockchain);
     debug!("Initialized blockchain database");
 
     // init wallet database
-    let wallet = Wallet::new(&matches.value_of("wallet_db").unwrap()).unwrap();
+    let wallet_seed = node.wallet_seed_file.as_ref().map(|path| {
+        read_seed(path).unwrap_or_else(|e| {
+            error!("Error reading wallet seed at {}: {}", path.display(), e);
+            process::exit(1);
+        })
+    });
+    let wallet = if let Some(seed) = &wallet_seed {
+        Wallet::from_seed(&node.walletdb, seed)
+    } else if resume {
+        Wallet::load(&node.walletdb)
+    } else {
+        Wallet::new(&node.walletdb)
+    }
+    .unwrap_or_else(|e| {
+        error!("Error opening wallet database: {}", e);
+        process::exit(1);
+    });
     let wallet = Arc::new(wallet);
     debug!("Initialized wallet");
 
+    // unlock the wallet, and encrypt it first if it is not
+    let wallet_passphrase = node.wallet_passphrase_file.as_ref().map(|path| {
+        read_passphrase(path).unwrap_or_else(|e| {
+            error!(
+                "Error reading wallet passphrase at {}: {}",
+                path.display(),
+                e
+            );
+            process::exit(1);
+        })
+    });
+    if let Some(passphrase) = &wallet_passphrase {
+        if !wallet.is_encrypted() {
+            wallet.encrypt(passphrase).unwrap_or_else(|e| {
+                error!("Error encrypting wallet: {}", e);
+                process::exit(1);
+            });
+            info!("Encrypted wallet");
+        }
+        wallet.unlock(passphrase, None).unwrap_or_else(|e| {
+            error!("Error unlocking wallet: {}", e);
+            process::exit(1);
+        });
+    } else if wallet.is_encrypted() {
+        info!("Wallet is locked");
+    }
+
     // load wallet keys
-    if let Some(wallet_keys) = matches.values_of("load_key_path") {
-        for key_path in wallet_keys {
-            let content = match std::fs::read_to_string(&key_path) {
-                Ok(c) => c,
+    for key_path in &node.load_key {
+        let content = match std::fs::read_to_string(key_path) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Error loading key pair at {}: {}", key_path.display(), &e);
+                process::exit(1);
+            }
+        };
+        let loaded = if content.trim_start().starts_with('{') {
+            // an encrypted key pair from keygen or the export API
+            let key: EncryptedKeypair = match serde_json::from_str(&content) {
+                Ok(k) => k,
                 Err(e) => {
-                    error!("Error loading key pair at {}: {}", &key_path, &e);
+                    error!("Error decoding key pair at {}: {}", key_path.display(), &e);
                     process::exit(1);
                 }
             };
+            let passphrase = wallet_passphrase.as_ref().unwrap_or_else(|| {
+                error!(
+                    "Key pair at {} is encrypted, but no wallet passphrase is given",
+                    key_path.display()
+                );
+                process::exit(1);
+            });
+            wallet.import_keypair(&key, passphrase, DEFAULT_ACCOUNT)
+        } else {
             let decoded = match base64::decode(&content.trim()) {
                 Ok(d) => d,
                 Err(e) => {
-                    error!("Error decoding key pair at {}: {}", &key_path, &e);
+                    error!("Error decoding key pair at {}: {}", key_path.display(), &e);
                     process::exit(1);
                 }
             };
             let keypair = Keypair::from_bytes(&decoded).unwrap();
-            match wallet.load_keypair(keypair) {
-                Ok(a) => info!("Loaded key pair for address {}", &a),
-                Err(e) => {
-                    error!("Error loading key pair into wallet: {}", &e);
-                    process::exit(1);
-                }
+            wallet.load_keypair(keypair)
+        };
+        match loaded {
+            Ok(a) => info!("Loaded key pair for address {}", &a),
+            Err(e) => {
+                error!("Error loading key pair into wallet: {}", &e);
+                process::exit(1);
             }
         }
     }
 
     // start thread to update ledger
-    let tx_workers = matches
-        .value_of("execution_workers")
-        .unwrap()
-        .parse::<usize>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing transaction execution workers: {}", e);
+    let tx_workers = node.execution_workers;
+    let tx_buffer = node.execution_buffer;
+    let ledger_manager = LedgerManager::new(
+        &blockdb,
+        &blockchain,
+        &utxodb,
+        &wallet,
+        &mempool,
+        config.clone(),
+    );
+    if resume {
+        ledger_manager.recover().unwrap_or_else(|e| {
+            error!("Error recovering from the existing databases: {}", e);
             process::exit(1);
         });
-    let tx_buffer = matches
-        .value_of("execution_buffer")
-        .unwrap()
-        .parse::<usize>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing transaction execution buffer size: {}", e);
+    } else {
+        ledger_manager.apply_genesis().unwrap_or_else(|e| {
+            error!("Error creating the initial coins: {}", e);
             process::exit(1);
         });
-    let ledger_manager = LedgerManager::new(&blockdb, &blockchain, &utxodb, &wallet);
-    ledger_manager.start(tx_buffer, tx_workers);
+    }
+    // look for the key pairs and coins of a restored wallet
+    if wallet_seed.is_some() {
+        wallet
+            .discover(&utxodb, node.wallet_gap_limit)
+            .unwrap_or_else(|e| {
+                error!("Error restoring wallet from seed: {}", e);
+                process::exit(1);
+            });
+        info!(
+            "Restored wallet from seed, it has {} addresses and {} coins",
+            wallet.addresses().unwrap().len(),
+            wallet.number_of_coins()
+        );
+    }
+    let ledger = ledger_manager.start(tx_buffer, tx_workers);
     debug!(
         "Initialized ledger manager with buffer size {} and {} workers",
         tx_buffer, tx_workers
     );
 
-    // parse p2p server address
-    let p2p_addr = matches
-        .value_of("peer_addr")
-        .unwrap()
-        .parse::<net::SocketAddr>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing P2P server address: {}", e);
-            process::exit(1);
-        });
-
-    // parse api server address
-    let api_addr = matches
-        .value_of("api_addr")
-        .unwrap()
-        .parse::<net::SocketAddr>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing API server address: {}", e);
-            process::exit(1);
-        });
-
     // create channels between server and worker, worker and miner, miner and worker
-    let (msg_tx, msg_rx) = piper::chan(100); // TODO: make this buffer adjustable
+    let (msg_tx, msg_rx) = piper::chan(node.p2p_queue_size);
     let (ctx_tx, ctx_rx) = channel::unbounded();
     let ctx_tx_miner = ctx_tx.clone();
 
+    // init address book
+    let address_book = AddressBook::open(&node.peerdb).unwrap_or_else(|e| {
+        error!("Error opening address book: {}", e);
+        process::exit(1);
+    });
+    let address_book = Arc::new(address_book);
+    debug!("Initialized address book");
+
     // start the p2p server
-    let (server_ctx, server) = server::new(p2p_addr, msg_tx).unwrap();
+    let limits = node.limits().unwrap();
+    let (server_ctx, server) = server::new(
+        node.p2p,
+        msg_tx,
+        &config,
+        &address_book,
+        node.outgoing_peers,
+        limits,
+        node.p2p_control_queue_size,
+    )
+    .unwrap();
     server_ctx.start().unwrap();
 
     // start the worker
-    let p2p_workers = matches
-        .value_of("p2p_workers")
-        .unwrap()
-        .parse::<usize>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing P2P workers: {}", e);
-            process::exit(1);
-        });
     let worker_ctx = worker::new(
-        p2p_workers,
+        node.p2p_workers,
         msg_rx,
         &blockchain,
         &blockdb,
@@ -304,11 +449,28 @@ fn main() {
         &mempool,
         ctx_tx,
         &server,
+        &address_book,
         config.clone(),
     );
+    if resume {
+        worker_ctx.process_stored_blocks();
+    }
     worker_ctx.start();
 
+    // create wallet key pair if there is none
+    if wallet.addresses().unwrap().is_empty() {
+        wallet.generate_keypair().unwrap_or_else(|e| {
+            error!("Error generating wallet key pair: {}", e);
+            process::exit(1);
+        });
+    }
+
     // start the miner
+    let miner_addr = match node.miner_addr {
+        Some(addr) => addr,
+        None => wallet.addresses().unwrap()[0],
+    };
+    info!("Block rewards go to {}", miner_addr);
     let (miner_ctx, miner) = miner::new(
         &mempool,
         &blockchain,
@@ -316,24 +478,20 @@ fn main() {
         ctx_rx,
         &ctx_tx_miner,
         &server,
+        miner_addr,
         config.clone(),
     );
     miner_ctx.start();
 
     // connect to known peers
-    if let Some(known_peers) = matches.values_of("known_peer") {
-        let known_peers: Vec<String> = known_peers.map(|x| x.to_owned()).collect();
+    if !node.connect.is_empty() {
+        let known_peers = node.connect.clone();
         let server = server.clone();
+        let address_book = Arc::clone(&address_book);
         thread::spawn(move || {
-            for peer in known_peers {
+            for addr in known_peers {
                 loop {
-                    let addr = match peer.parse::<net::SocketAddr>() {
-                        Ok(x) => x,
-                        Err(e) => {
-                            error!("Error parsing peer address {}: {}", &peer, e);
-                            break;
-                        }
-                    };
+                    address_book.add(&addr).unwrap();
                     match server.connect(addr) {
                         Ok(_) => {
                             info!("Connected to outgoing peer {}", &addr);
@@ -353,73 +511,27 @@ fn main() {
         });
     }
 
-    // fund the given addresses
-    if let Some(fund_addrs) = matches.values_of("init_fund_addr") {
-        let num_coins = matches
-            .value_of("init_fund_coins")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap_or_else(|e| {
-                error!("Error parsing number of initial fund coins: {}", e);
-                process::exit(1);
-            });
-        let coin_value = matches
-            .value_of("init_fund_value")
-            .unwrap()
-            .parse::<u64>()
-            .unwrap_or_else(|e| {
-                error!("Error parsing value of initial fund coins: {}", e);
-                process::exit(1);
-            });
-        let mut addrs = vec![];
-        for addr in fund_addrs {
-            let decoded = match base64::decode(&addr.trim()) {
-                Ok(d) => d,
-                Err(e) => {
-                    error!("Error decoding address {}: {}", &addr.trim(), e);
-                    process::exit(1);
-                }
-            };
-            let addr_bytes: [u8; 32] = (&decoded[0..32]).try_into().unwrap();
-            let hash: H256 = addr_bytes.into();
-            addrs.push(hash);
-        }
-        info!(
-            "Funding {} addresses with {} initial coins of {}",
-            addrs.len(),
-            num_coins,
-            coin_value
-        );
-        prism::experiment::ico(&addrs, &utxodb, &wallet, num_coins, coin_value).unwrap();
-    }
-
-    // create wallet key pair if there is none
-    if wallet.addresses().unwrap().is_empty() {
-        wallet.generate_keypair().unwrap();
-    }
-
     // start the transaction generator
-    let (txgen_ctx, txgen_control_chan) = TransactionGenerator::new(&wallet, &server, &mempool);
+    let (txgen_ctx, txgen_control_chan) =
+        TransactionGenerator::new(&wallet, &server, &mempool, &utxodb);
     txgen_ctx.start();
 
     // start the API server
     ApiServer::start(
-        api_addr,
+        node.api,
         &wallet,
+        &blockdb,
         &blockchain,
         &utxodb,
         &server,
         &miner,
         &mempool,
+        &ledger,
         txgen_control_chan,
     );
 
     // start the visualization server
-    if let Some(addr) = matches.value_of("visualization") {
-        let addr = addr.parse::<net::SocketAddr>().unwrap_or_else(|e| {
-            error!("Error parsing visualization server socket address: {}", e);
-            process::exit(1);
-        });
+    if let Some(addr) = node.visual {
         info!("Starting visualization server at {}", &addr);
         VisualizationServer::start(addr, &blockchain, &blockdb, &utxodb);
     }
@@ -428,3 +540,56 @@ fn main() {
         std::thread::park();
     }
 }
+
+/// Parse the value of a flag, or exit with an error that names the flag.
+fn parse_flag<T: FromStr>(name: &str, value: &str) -> T
+where
+    T::Err: fmt::Display,
+{
+    value.parse().unwrap_or_else(|e| {
+        eprintln!("Error parsing {}: {}", name, e);
+        process::exit(1);
+    })
+}
+
+/// Override a node parameter with the flag of the same name, if it is given.
+fn set_flag<T: FromStr>(matches: &clap::ArgMatches, name: &str, param: &mut T)
+where
+    T::Err: fmt::Display,
+{
+    if let Some(value) = matches.value_of(name) {
+        *param = parse_flag(name, value);
+    }
+}
+
+/// Override an optional node parameter with the flag of the same name, if it is given.
+fn set_optional_flag<T: FromStr>(matches: &clap::ArgMatches, name: &str, param: &mut Option<T>)
+where
+    T::Err: fmt::Display,
+{
+    if let Some(value) = matches.value_of(name) {
+        *param = Some(parse_flag(name, value));
+    }
+}
+
+/// Override a list of node parameters with the flags of the same name, if any is given.
+fn set_list_flag<T: FromStr>(matches: &clap::ArgMatches, name: &str, param: &mut Vec<T>)
+where
+    T::Err: fmt::Display,
+{
+    if let Some(values) = matches.values_of(name) {
+        *param = values.map(|v| parse_flag(name, v)).collect();
+    }
+}
+
+/// Read a wallet seed in hex from a file.
+fn read_seed<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    wallet::parse_seed(&content)
+}
+
+/// Read a passphrase from a file, without the trailing newline.
+fn read_passphrase<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
@@ -1,7 +1,11 @@
 use crate::crypto::hash::{Hashable, H256};
+use crate::event::{Event, EVENTS};
 use crate::transaction::{CoinId, Input, Transaction};
+use crate::validation::TransactionRejection;
+use std::cmp::Reverse;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 
 /// transactions storage
@@ -17,8 +21,9 @@ pub struct MemoryPool {
     by_hash: HashMap<H256, Entry>,
     /// Transactions by previous output, formatted as Input
     by_input: HashMap<Input, H256>,
-    /// Storage for order by storage index, it is equivalent to FIFO
-    by_storage_index: BTreeMap<u64, H256>,
+    /// Storage for order by fee rate from the highest, and by storage index (i.e. FIFO) among
+    /// the same fee rate
+    by_fee_rate: BTreeMap<(Reverse<u64>, u64), H256>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +32,14 @@ pub struct Entry {
     pub transaction: Transaction,
     /// counter of the tx
     storage_index: u64,
+    /// fee per 1000 bytes of the tx
+    pub fee_rate: u64,
+}
+
+impl Entry {
+    fn key(&self) -> (Reverse<u64>, u64) {
+        (Reverse(self.fee_rate), self.storage_index)
+    }
 }
 
 impl MemoryPool {
@@ -37,20 +50,34 @@ impl MemoryPool {
             counter: 0,
             by_hash: HashMap::new(),
             by_input: HashMap::new(),
-            by_storage_index: BTreeMap::new(),
+            by_fee_rate: BTreeMap::new(),
         }
     }
 
-    /// Insert a tx into memory pool. The input of it will also be recorded.
-    pub fn insert(&mut self, tx: Transaction) {
-        if self.num_transactions > self.max_transactions {
-            return;
-        }
-        // assumes no duplicates nor double spends
+    /// Insert a tx into memory pool. The input of it will also be recorded. Duplicates and double
+    /// spends are rejected. When the memory pool is full, the tx with the lowest fee rate is
+    /// evicted for it, unless the new tx does not pay a higher fee rate.
+    pub fn insert(&mut self, tx: Transaction) -> Result<(), TransactionRejection> {
         let hash = tx.hash();
+        if self.contains(&hash) {
+            return Err(TransactionRejection::Duplicate);
+        }
+        if let Some(input) = tx.input.iter().find(|i| self.by_input.contains_key(i)) {
+            return Err(TransactionRejection::DoubleSpend(input.coin));
+        }
+        let fee_rate = tx.fee_rate();
+        while self.num_transactions >= self.max_transactions {
+            match self.by_fee_rate.iter().next_back() {
+                Some((&(Reverse(lowest), _), &lowest_hash)) if lowest < fee_rate => {
+                    self.evict(&lowest_hash)
+                }
+                _ => return Err(TransactionRejection::MempoolFull),
+            }
+        }
         let entry = Entry {
             transaction: tx,
             storage_index: self.counter,
+            fee_rate,
         };
         self.counter += 1;
 
@@ -60,12 +87,14 @@ impl MemoryPool {
         }
 
         // add to btree
-        self.by_storage_index.insert(entry.storage_index, hash);
+        self.by_fee_rate.insert(entry.key(), hash);
 
         // add to hashmap
         self.by_hash.insert(hash, entry);
 
         self.num_transactions += 1;
+        EVENTS.publish(Event::MempoolAdmission { hash });
+        Ok(())
     }
 
     pub fn get(&self, h: &H256) -> Option<&Entry> {
@@ -90,11 +119,31 @@ impl MemoryPool {
         for input in &entry.transaction.input {
             self.by_input.remove(&input);
         }
-        self.by_storage_index.remove(&entry.storage_index);
+        self.by_fee_rate.remove(&entry.key());
         self.num_transactions -= 1;
         Some(entry)
     }
 
+    /// Evict a tx and the txs that spend its outputs, since they can't go into the ledger without
+    /// it.
+    fn evict(&mut self, hash: &H256) {
+        let entry = match self.remove_and_get(hash) {
+            Some(e) => e,
+            None => return,
+        };
+        for (index, output) in entry.transaction.output.iter().enumerate() {
+            self.remove_by_input(&Input {
+                coin: CoinId {
+                    hash: *hash,
+                    index: index as u32,
+                },
+                value: output.value,
+                owner: output.recipient,
+            });
+        }
+        EVENTS.publish(Event::MempoolEviction { hash: *hash });
+    }
+
     /// Remove a tx by its hash, also remove its recorded inputs
     pub fn remove_by_hash(&mut self, hash: &H256) {
         self.remove_and_get(hash);
@@ -125,13 +174,28 @@ impl MemoryPool {
         }
     }
 
-    /// get n transaction by fifo
+    /// get n transactions, highest fee rate first. A tx that spends the output of another tx in
+    /// the memory pool only comes after it, so that it does not go into a block before its input
+    /// exists.
     pub fn get_transactions(&self, n: u32) -> Vec<Transaction> {
-        self.by_storage_index
-            .values()
-            .take(n as usize)
-            .map(|hash| self.get(hash).unwrap().transaction.clone())
-            .collect()
+        let mut taken: HashSet<H256> = HashSet::new();
+        let mut transactions = vec![];
+        for hash in self.by_fee_rate.values() {
+            if transactions.len() >= n as usize {
+                break;
+            }
+            let transaction = &self.get(hash).unwrap().transaction;
+            let waiting = transaction
+                .input
+                .iter()
+                .any(|i| self.contains(&i.coin.hash) && !taken.contains(&i.coin.hash));
+            if waiting {
+                continue;
+            }
+            taken.insert(*hash);
+            transactions.push(transaction.clone());
+        }
+        transactions
     }
 
     /// get size/length
@@ -141,4 +205,75 @@ impl MemoryPool {
 }
 
 #[cfg(test)]
-pub mod tests {}
+pub mod tests {
+    use super::*;
+    use crate::transaction::Output;
+
+    /// A tx spending `value` out of coin `n` and paying `fee`.
+    fn tx(n: u8, value: u64, fee: u64) -> Transaction {
+        Transaction {
+            input: vec![Input {
+                coin: CoinId {
+                    hash: [n; 32].into(),
+                    index: 0,
+                },
+                value,
+                owner: H256::default(),
+            }],
+            output: vec![Output {
+                value: value - fee,
+                recipient: H256::default(),
+            }],
+            authorization: vec![],
+            multisig: vec![],
+            hash: Default::default(),
+        }
+    }
+
+    /// A tx spending the output of `parent` and paying `fee`.
+    fn child(parent: &Transaction, fee: u64) -> Transaction {
+        let value = parent.output[0].value;
+        let mut t = tx(0, value, fee);
+        t.input[0].coin.hash = parent.hash();
+        t
+    }
+
+    fn hashes(transactions: &[Transaction]) -> Vec<H256> {
+        transactions.iter().map(|t| t.hash()).collect()
+    }
+
+    #[test]
+    fn fee_rate_order_and_eviction() {
+        let mut pool = MemoryPool::new(3);
+        let cheap = tx(1, 100, 0);
+        let medium = tx(2, 100, 10);
+        let rich = tx(3, 100, 50);
+        let rich_child = child(&rich, 1);
+        pool.insert(cheap.clone()).unwrap();
+        pool.insert(medium.clone()).unwrap();
+        pool.insert(rich.clone()).unwrap();
+        assert_eq!(
+            hashes(&pool.get_transactions(3)),
+            hashes(&[rich.clone(), medium.clone(), cheap.clone()])
+        );
+        // the child does not pay more than the cheapest tx, so it does not get in
+        assert!(match pool.insert(child(&rich, 0)) {
+            Err(TransactionRejection::MempoolFull) => true,
+            _ => false,
+        });
+        pool.insert(rich_child.clone()).unwrap();
+        assert!(!pool.contains(&cheap.hash()));
+        // the child pays a lower rate than its parent, and only comes after it
+        assert_eq!(
+            hashes(&pool.get_transactions(3)),
+            hashes(&[rich.clone(), medium.clone(), rich_child.clone()])
+        );
+        assert_eq!(hashes(&pool.get_transactions(1)), hashes(&[rich.clone()]));
+        // evicting a tx also evicts its child
+        pool.insert(tx(4, 100, 20)).unwrap();
+        pool.insert(tx(5, 100, 30)).unwrap();
+        assert!(!pool.contains(&medium.hash()));
+        assert!(!pool.contains(&rich_child.hash()));
+        assert_eq!(pool.len(), 3);
+    }
+}
@@ -12,8 +12,10 @@ use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::handler::new_validated_block;
 use crate::network::message::Message;
 use crate::network::server::Handle as ServerHandle;
+use crate::transaction::Address;
+use crate::validation;
 
-use log::info;
+use log::{info, warn};
 
 use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
 use memory_pool::MemoryPool;
@@ -82,6 +84,7 @@ pub fn new(
     ctx_update_source: Receiver<ContextUpdateSignal>,
     ctx_update_tx: &Sender<ContextUpdateSignal>,
     server: &ServerHandle,
+    address: Address,
     config: BlockchainConfig,
 ) -> (Context, Handle) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
@@ -125,6 +128,7 @@ pub fn new(
             content_merkle_root: H256::default(),
             extra_content: [0; 32],
             difficulty: *DEFAULT_DIFFICULTY,
+            miner: address,
         },
         contents,
         content_merkle_tree,
@@ -372,8 +376,18 @@ impl Context {
                 }
             }
 
-            // update the difficulty
-            self.header.difficulty = self.get_difficulty(&self.header.parent);
+            // update the difficulty, which only changes with the proposer parent
+            if new_proposer_block {
+                match validation::expected_difficulty(
+                    &self.header.parent,
+                    &self.blockchain,
+                    &self.blockdb,
+                    &self.config,
+                ) {
+                    Ok(difficulty) => self.header.difficulty = difficulty,
+                    Err(e) => warn!("Keeping the old difficulty: {}", e),
+                }
+            }
 
             // update or rebuild the merkle tree according to what we did in the last stage
             if new_proposer_block || voter_shift {
@@ -502,17 +516,6 @@ impl Context {
             sortition_proof,
         )
     }
-
-    /// Calculate the difficulty for the block to be mined
-    // TODO: shall we make a dedicated type for difficulty?
-    fn get_difficulty(&self, block_hash: &H256) -> H256 {
-        // Get the header of the block corresponding to block_hash
-        match self.blockdb.get(block_hash).unwrap() {
-            // extract difficulty
-            Some(b) => b.header.difficulty,
-            None => *DEFAULT_DIFFICULTY,
-        }
-    }
 }
 
 /// Get the current UNIX timestamp
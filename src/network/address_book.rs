@@ -0,0 +1,253 @@
+use bincode::{deserialize, serialize};
+use rand::seq::SliceRandom;
+use rocksdb::{Options, DB};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of failed connection attempts in a row before we forget an address.
+const MAX_FAILURES: u32 = 3;
+/// Maximum number of addresses we keep. Once the address book is full, we forget the addresses
+/// that we heard of the earliest and never connected to.
+const MAX_ADDRESSES: usize = 4096;
+
+/// What we know about an address.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct Entry {
+    /// The last time (in UNIX seconds) we completed a handshake with the node, or 0 if never.
+    last_seen: u64,
+    /// Number of failed connection attempts in a row.
+    failures: u32,
+    /// The time (in UNIX seconds) we first heard of the address.
+    heard: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Database of the P2P addresses of other nodes.
+pub struct AddressBook {
+    /// The underlying RocksDB handle.
+    db: rocksdb::DB,
+    /// Number of addresses in the database, which also serializes the changes to it.
+    len: Mutex<usize>,
+    /// Maximum number of addresses we keep.
+    capacity: usize,
+}
+
+impl AddressBook {
+    /// Open the address book at the given path, and create a new one if missing. Unlike the other
+    /// databases, the address book is always kept across runs.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, rocksdb::Error> {
+        Self::open_with_capacity(path, MAX_ADDRESSES)
+    }
+
+    fn open_with_capacity<P: AsRef<std::path::Path>>(
+        path: P,
+        capacity: usize,
+    ) -> Result<Self, rocksdb::Error> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, path)?;
+        // forget the entries that an older version wrote
+        let mut len = 0;
+        for (k, v) in db.iterator(rocksdb::IteratorMode::Start) {
+            if deserialize::<Entry>(v.as_ref()).is_ok() {
+                len += 1;
+            } else {
+                db.delete(k)?;
+            }
+        }
+        Ok(Self {
+            db,
+            len: Mutex::new(len),
+            capacity,
+        })
+    }
+
+    fn get(&self, addr: &SocketAddr) -> Result<Option<Entry>, rocksdb::Error> {
+        match self.db.get_pinned(serialize(addr).unwrap())? {
+            Some(d) => Ok(Some(deserialize(&d).unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, addr: &SocketAddr, entry: &Entry) -> Result<(), rocksdb::Error> {
+        self.db
+            .put(serialize(addr).unwrap(), serialize(entry).unwrap())
+    }
+
+    /// Add an address that we heard of. Do nothing if we already know it, or if the address book
+    /// is full of addresses that we have connected to.
+    pub fn add(&self, addr: &SocketAddr) -> Result<(), rocksdb::Error> {
+        let mut len = self.len.lock().unwrap();
+        if self.get(addr)?.is_some() {
+            return Ok(());
+        }
+        if *len >= self.capacity {
+            *len -= self.evict()?;
+            if *len >= self.capacity {
+                return Ok(());
+            }
+        }
+        self.put(
+            addr,
+            &Entry {
+                last_seen: 0,
+                failures: 0,
+                heard: now(),
+            },
+        )?;
+        *len += 1;
+        Ok(())
+    }
+
+    /// Forget the addresses that we heard of the earliest and never connected to, a sixteenth of
+    /// the capacity at once so that we don't look for them on every new address. Returns the
+    /// number of addresses forgotten.
+    fn evict(&self) -> Result<usize, rocksdb::Error> {
+        let mut untried: Vec<(u64, Box<[u8]>)> = vec![];
+        for (k, v) in self.db.iterator(rocksdb::IteratorMode::Start) {
+            let entry: Entry = deserialize(v.as_ref()).unwrap();
+            if entry.last_seen == 0 {
+                untried.push((entry.heard, k));
+            }
+        }
+        untried.sort_unstable_by_key(|(heard, _)| *heard);
+        untried.truncate(std::cmp::max(self.capacity / 16, 1));
+        for (_, k) in &untried {
+            self.db.delete(k)?;
+        }
+        Ok(untried.len())
+    }
+
+    /// Record that we completed a handshake with the node at the given address, which must be an
+    /// address that we dialed, since others can claim any address.
+    pub fn mark_connected(&self, addr: &SocketAddr) -> Result<(), rocksdb::Error> {
+        let mut len = self.len.lock().unwrap();
+        let heard = match self.get(addr)? {
+            Some(e) => e.heard,
+            None => {
+                *len += 1;
+                now()
+            }
+        };
+        self.put(
+            addr,
+            &Entry {
+                last_seen: now(),
+                failures: 0,
+                heard,
+            },
+        )
+    }
+
+    /// Record that we failed to connect to the given address, and forget it after too many
+    /// failures in a row.
+    pub fn mark_failed(&self, addr: &SocketAddr) -> Result<(), rocksdb::Error> {
+        let mut len = self.len.lock().unwrap();
+        let mut entry = match self.get(addr)? {
+            Some(e) => e,
+            None => return Ok(()),
+        };
+        entry.failures += 1;
+        if entry.failures >= MAX_FAILURES {
+            *len -= 1;
+            self.db.delete(serialize(addr).unwrap())
+        } else {
+            self.put(addr, &entry)
+        }
+    }
+
+    /// Get up to `limit` addresses that we have connected to, the most recently seen first.
+    pub fn verified(&self, limit: usize) -> Result<Vec<SocketAddr>, rocksdb::Error> {
+        let mut addrs: Vec<(u64, SocketAddr)> = vec![];
+        for (k, v) in self.db.iterator(rocksdb::IteratorMode::Start) {
+            let entry: Entry = deserialize(v.as_ref()).unwrap();
+            if entry.last_seen > 0 {
+                addrs.push((entry.last_seen, deserialize(k.as_ref()).unwrap()));
+            }
+        }
+        addrs.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        Ok(addrs.into_iter().take(limit).map(|(_, a)| a).collect())
+    }
+
+    /// Get all known addresses in random order.
+    pub fn shuffled(&self) -> Result<Vec<SocketAddr>, rocksdb::Error> {
+        let mut addrs: Vec<SocketAddr> = self
+            .db
+            .iterator(rocksdb::IteratorMode::Start)
+            .map(|(k, _)| deserialize(k.as_ref()).unwrap())
+            .collect();
+        addrs.shuffle(&mut rand::thread_rng());
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn add_and_verify() {
+        let path = "/tmp/prism_test_address_book_add_and_verify.rocksdb";
+        DB::destroy(&Options::default(), path).unwrap();
+        let book = AddressBook::open_with_capacity(path, 16).unwrap();
+        for port in 1..=3 {
+            book.add(&addr(port)).unwrap();
+        }
+        book.add(&addr(1)).unwrap();
+        assert_eq!(book.shuffled().unwrap().len(), 3);
+        assert!(book.verified(10).unwrap().is_empty());
+
+        // only the addresses we connected to are handed out
+        book.mark_connected(&addr(2)).unwrap();
+        assert_eq!(book.verified(10).unwrap(), vec![addr(2)]);
+
+        // we forget an address after failing to connect too many times
+        for _ in 0..MAX_FAILURES {
+            book.mark_failed(&addr(3)).unwrap();
+        }
+        let mut known = book.shuffled().unwrap();
+        known.sort();
+        assert_eq!(known, vec![addr(1), addr(2)]);
+        assert_eq!(*book.len.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn eviction() {
+        let path = "/tmp/prism_test_address_book_eviction.rocksdb";
+        DB::destroy(&Options::default(), path).unwrap();
+        let book = AddressBook::open_with_capacity(path, 16).unwrap();
+        for port in 1..=16 {
+            book.add(&addr(port)).unwrap();
+            let mut entry = book.get(&addr(port)).unwrap().unwrap();
+            entry.heard = port as u64;
+            book.put(&addr(port), &entry).unwrap();
+        }
+        book.mark_connected(&addr(1)).unwrap();
+
+        // the earliest address we never connected to makes room for the new one
+        book.add(&addr(17)).unwrap();
+        assert_eq!(*book.len.lock().unwrap(), 16);
+        assert!(book.get(&addr(1)).unwrap().is_some());
+        assert!(book.get(&addr(2)).unwrap().is_none());
+        assert!(book.get(&addr(17)).unwrap().is_some());
+
+        // new addresses don't push out those we connected to
+        for a in book.shuffled().unwrap() {
+            book.mark_connected(&a).unwrap();
+        }
+        book.add(&addr(18)).unwrap();
+        assert!(book.get(&addr(18)).unwrap().is_none());
+        assert_eq!(book.verified(100).unwrap().len(), 16);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::crypto::hash::H256;
+use std::collections::{HashSet, VecDeque};
+
+/// The hashes that a peer is known to have, so that we don't announce them to it again. Only the
+/// most recent hashes are kept, and the oldest ones are forgotten when the filter is full.
+pub struct KnownFilter {
+    hashes: HashSet<H256>,
+    order: VecDeque<H256>,
+    capacity: usize,
+}
+
+impl KnownFilter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            hashes: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Record that the peer has the given hash, and return whether it was new to the filter.
+    pub fn insert(&mut self, hash: H256) -> bool {
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.hashes.remove(&oldest);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forget_oldest() {
+        let hash = |n: u8| -> H256 { [n; 32].into() };
+        let mut filter = KnownFilter::new(2);
+        assert!(filter.insert(hash(1)));
+        assert!(!filter.insert(hash(1)));
+        assert!(filter.insert(hash(2)));
+        // the filter is full, so the oldest hash is forgotten
+        assert!(filter.insert(hash(3)));
+        assert!(!filter.insert(hash(2)));
+        assert!(!filter.insert(hash(3)));
+        assert!(filter.insert(hash(1)));
+        assert_eq!(filter.hashes.len(), 2);
+        assert_eq!(filter.order.len(), 2);
+    }
+}
@@ -0,0 +1,99 @@
+use super::message::MAX_SIZES;
+use std::time::{Duration, Instant};
+
+/// Limits on what a single peer can send us.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Maximum size of each message variant in bytes, indexed by the tag of the variant.
+    max_sizes: Vec<u32>,
+    /// Maximum number of bytes per second that we read from a peer.
+    pub bytes_per_sec: u64,
+    /// Maximum number of messages per second that we read from a peer.
+    pub messages_per_sec: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_sizes: MAX_SIZES.iter().map(|(_, size)| *size).collect(),
+            bytes_per_sec: 64 << 20,
+            messages_per_sec: 10000,
+        }
+    }
+}
+
+impl Limits {
+    /// Set the maximum size of the message variant with the given name.
+    pub fn set_max_size(&mut self, variant: &str, size: u32) -> Result<(), String> {
+        match MAX_SIZES.iter().position(|(name, _)| *name == variant) {
+            Some(tag) => {
+                self.max_sizes[tag] = size;
+                Ok(())
+            }
+            None => Err(format!("unknown message type {}", variant)),
+        }
+    }
+
+    /// Get the maximum size of the message variant with the given tag, or `None` if there is no
+    /// such variant.
+    pub fn max_size(&self, tag: u32) -> Option<u32> {
+        self.max_sizes.get(tag as usize).copied()
+    }
+}
+
+/// A token bucket that refills at a fixed rate and holds at most one second worth of tokens.
+/// Taking more tokens than available puts the bucket into debt, which the caller pays off by
+/// waiting.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take the given number of tokens, and return how long to wait until the bucket is out of
+    /// debt.
+    fn take(&mut self, amount: u64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Limits the rate of bytes and messages that we read from a peer.
+pub struct RateLimiter {
+    bytes: TokenBucket,
+    messages: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            bytes: TokenBucket::new(limits.bytes_per_sec),
+            messages: TokenBucket::new(limits.messages_per_sec),
+        }
+    }
+
+    /// Record a message of the given size, and return how long to wait before reading the next
+    /// one.
+    pub fn record(&mut self, size: u64) -> Duration {
+        let bytes_delay = self.bytes.take(size);
+        let messages_delay = self.messages.take(1);
+        bytes_delay.max(messages_delay)
+    }
+}
@@ -1,8 +1,45 @@
+use crate::block::HeaderProof;
 use crate::crypto::hash::H256;
 use crate::transaction::Transaction;
+use crate::validation::TransactionRejection;
+use std::net::SocketAddr;
+
+/// Version of the P2P protocol. Peers with different versions can't talk to each other.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// The first message that two peers exchange on a new connection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Handshake {
+    /// Version of the P2P protocol.
+    pub version: u32,
+    /// Hash of the blockchain config.
+    pub config: H256,
+    /// Address where the P2P server of the node listens.
+    pub listen_addr: SocketAddr,
+}
+
+/// Names of the message variants and the default maximum size of their encoding in bytes. Must be
+/// in the same order as the variants of `Message`, so that the index is the tag that bincode uses.
+pub const MAX_SIZES: [(&str, u32); 14] = [
+    ("Handshake", 1024),
+    ("Ping", 1024),
+    ("Pong", 1024),
+    ("NewBlockHashes", 1 << 20),
+    ("GetBlocks", 1 << 20),
+    ("Blocks", 32 << 20),
+    ("NewTransactionHashes", 1 << 20),
+    ("GetTransactions", 1 << 20),
+    ("Transactions", 16 << 20),
+    ("GetHeaders", 1024),
+    ("Headers", 8 << 20),
+    ("GetPeers", 1024),
+    ("Peers", 64 << 10),
+    ("RejectedTransactions", 1 << 20),
+];
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
+    Handshake(Handshake),
     Ping(String),
     Pong(String),
     NewBlockHashes(Vec<H256>),
@@ -11,5 +48,65 @@ pub enum Message {
     NewTransactionHashes(Vec<H256>),
     GetTransactions(Vec<H256>),
     Transactions(Vec<Transaction>),
-    Bootstrap(H256),
+    GetHeaders(H256),
+    Headers(Vec<HeaderProof>),
+    GetPeers,
+    Peers(Vec<SocketAddr>),
+    RejectedTransactions(Vec<(H256, TransactionRejection)>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get the name of a message variant. The match is exhaustive, so that adding a variant fails
+    /// to compile until it is added to the test below.
+    fn name(message: &Message) -> &'static str {
+        match message {
+            Message::Handshake(_) => "Handshake",
+            Message::Ping(_) => "Ping",
+            Message::Pong(_) => "Pong",
+            Message::NewBlockHashes(_) => "NewBlockHashes",
+            Message::GetBlocks(_) => "GetBlocks",
+            Message::Blocks(_) => "Blocks",
+            Message::NewTransactionHashes(_) => "NewTransactionHashes",
+            Message::GetTransactions(_) => "GetTransactions",
+            Message::Transactions(_) => "Transactions",
+            Message::GetHeaders(_) => "GetHeaders",
+            Message::Headers(_) => "Headers",
+            Message::GetPeers => "GetPeers",
+            Message::Peers(_) => "Peers",
+            Message::RejectedTransactions(_) => "RejectedTransactions",
+        }
+    }
+
+    #[test]
+    fn max_sizes_in_variant_order() {
+        let messages = vec![
+            Message::Handshake(Handshake {
+                version: PROTOCOL_VERSION,
+                config: H256::default(),
+                listen_addr: "127.0.0.1:6000".parse().unwrap(),
+            }),
+            Message::Ping(String::new()),
+            Message::Pong(String::new()),
+            Message::NewBlockHashes(vec![]),
+            Message::GetBlocks(vec![]),
+            Message::Blocks(vec![]),
+            Message::NewTransactionHashes(vec![]),
+            Message::GetTransactions(vec![]),
+            Message::Transactions(vec![]),
+            Message::GetHeaders(H256::default()),
+            Message::Headers(vec![]),
+            Message::GetPeers,
+            Message::Peers(vec![]),
+            Message::RejectedTransactions(vec![]),
+        ];
+        assert_eq!(messages.len(), MAX_SIZES.len());
+        for message in &messages {
+            let raw = bincode::serialize(message).unwrap();
+            let tag = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+            assert_eq!(MAX_SIZES[tag as usize].0, name(message));
+        }
+    }
 }
@@ -1,5 +1,9 @@
+pub mod address_book;
 mod buffer;
+mod filter;
+pub mod limits;
 pub mod message;
 pub mod peer;
+pub mod reputation;
 pub mod server;
 pub mod worker;
@@ -5,17 +5,19 @@ use smol::Async;
 
 pub fn new(
     stream: &Async<std::net::TcpStream>,
+    direction: Direction,
 ) -> std::io::Result<(mpsc::UnboundedReceiver<Vec<u8>>, Handle)> {
     let (write_sender, write_receiver) = mpsc::unbounded(); // TODO: think about the buffer size here
     let addr = stream.get_ref().peer_addr()?;
     let handle = Handle {
         write_queue: write_sender,
         addr,
+        direction,
     };
     Ok((write_receiver, handle))
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Direction {
     Incoming,
     Outgoing,
@@ -25,9 +27,18 @@ pub enum Direction {
 pub struct Handle {
     addr: std::net::SocketAddr,
     write_queue: mpsc::UnboundedSender<Vec<u8>>,
+    direction: Direction,
 }
 
 impl Handle {
+    pub fn addr(&self) -> std::net::SocketAddr {
+        self.addr
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
     pub fn write(&mut self, msg: message::Message) {
         // TODO: return result
         let buffer = bincode::serialize(&msg).unwrap();
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Score at which a peer gets disconnected and banned.
+const BAN_THRESHOLD: u32 = 100;
+/// How long (in seconds) a peer stays banned.
+const BAN_DURATION: u64 = 3600;
+
+/// Things a peer can do wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// Sent a frame that we could not decode.
+    MalformedMessage,
+    /// Sent a frame that is too large for its message type, or of an unknown type.
+    InvalidFrame,
+    /// Sent a block or header with invalid PoW or sortition.
+    InvalidPow,
+    /// Sent a block with invalid sortition proof or content.
+    InvalidBlock,
+    /// Sent a transaction that is malformed or has invalid signatures.
+    InvalidTransaction,
+    /// Sent a block that we did not ask for.
+    UnrequestedPayload,
+}
+
+impl Misbehavior {
+    /// The score that the peer gets for this misbehavior.
+    fn penalty(self) -> u32 {
+        match self {
+            Misbehavior::MalformedMessage => 50,
+            Misbehavior::InvalidFrame => 50,
+            Misbehavior::InvalidPow => 100,
+            Misbehavior::InvalidBlock => 50,
+            Misbehavior::InvalidTransaction => 50,
+            Misbehavior::UnrequestedPayload => 10,
+        }
+    }
+}
+
+impl std::fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Misbehavior::MalformedMessage => write!(f, "malformed message"),
+            Misbehavior::InvalidFrame => write!(f, "invalid frame"),
+            Misbehavior::InvalidPow => write!(f, "invalid PoW or sortition"),
+            Misbehavior::InvalidBlock => write!(f, "invalid block"),
+            Misbehavior::InvalidTransaction => write!(f, "invalid transaction"),
+            Misbehavior::UnrequestedPayload => write!(f, "unrequested payload"),
+        }
+    }
+}
+
+/// A peer that we refuse to talk to.
+#[derive(Serialize, Clone, Debug)]
+pub struct BannedPeer {
+    /// The IP address that the peer connects from.
+    pub ip: IpAddr,
+    /// The time (in UNIX seconds) when the ban is lifted.
+    pub until: u64,
+}
+
+/// Misbehavior scores and bans of peers, keyed by the IP address that we see the connection come
+/// from. Unlike the listen address in the handshake, the peer can't choose it, so it can't dodge a
+/// ban or get another node banned by claiming a different address.
+#[derive(Default)]
+pub struct Reputation {
+    scores: HashMap<IpAddr, u32>,
+    bans: HashMap<IpAddr, u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Reputation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a misbehavior of the given peer, and return whether the peer is banned as a result.
+    pub fn penalize(&mut self, ip: IpAddr, misbehavior: Misbehavior) -> bool {
+        let score = self.scores.entry(ip).or_insert(0);
+        *score += misbehavior.penalty();
+        if *score < BAN_THRESHOLD {
+            return false;
+        }
+        self.scores.remove(&ip);
+        self.bans.insert(ip, now() + BAN_DURATION);
+        true
+    }
+
+    /// Check whether the given peer is banned, and lift the ban if it has expired.
+    pub fn is_banned(&mut self, ip: &IpAddr) -> bool {
+        match self.bans.get(ip) {
+            Some(until) if *until > now() => true,
+            Some(_) => {
+                self.bans.remove(ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Get the peers that are currently banned.
+    pub fn banned(&mut self) -> Vec<BannedPeer> {
+        let now = now();
+        self.bans.retain(|_, until| *until > now);
+        self.bans
+            .iter()
+            .map(|(ip, until)| BannedPeer {
+                ip: *ip,
+                until: *until,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban_past_threshold() {
+        let mut reputation = Reputation::new();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..9 {
+            assert!(!reputation.penalize(ip, Misbehavior::UnrequestedPayload));
+        }
+        assert!(!reputation.is_banned(&ip));
+        assert!(reputation.penalize(ip, Misbehavior::UnrequestedPayload));
+        assert!(reputation.is_banned(&ip));
+        assert_eq!(reputation.banned().len(), 1);
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(!reputation.is_banned(&other));
+    }
+}
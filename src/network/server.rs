@@ -1,41 +1,83 @@
-use super::message;
+use super::address_book::AddressBook;
+use super::filter::KnownFilter;
+use super::limits::{Limits, RateLimiter};
+use super::message::{self, Handshake, Message, PROTOCOL_VERSION};
 use super::peer;
+use super::reputation::{BannedPeer, Misbehavior, Reputation};
+use crate::config::BlockchainConfig;
+use crate::crypto::hash::{Hashable, H256};
 
-use futures::{channel::oneshot, stream::StreamExt};
-use log::{debug, info, trace};
+use futures::{channel::oneshot, future, stream::StreamExt};
+use log::{debug, info, trace, warn};
 use piper;
 use piper::Arc;
 use std::net;
 
-use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use futures::io::{BufReader, BufWriter};
-use smol::{Async, Task};
+use smol::{Async, Task, Timer};
+use std::collections::{HashMap, HashSet};
 use std::thread;
+use std::time;
+
+/// Interval between two checks of the number of outgoing peers.
+const PEER_MAINTENANCE_INTERVAL: time::Duration = time::Duration::from_secs(10);
+/// Interval between two batches of transaction announcements.
+const TX_ANNOUNCE_INTERVAL: time::Duration = time::Duration::from_millis(100);
+/// Maximum number of transaction hashes in one announcement.
+const TX_ANNOUNCE_BATCH_SIZE: usize = 10000;
+/// Number of transaction hashes to remember for each peer.
+const KNOWN_TX_FILTER_SIZE: usize = 100_000;
+/// Time a new peer has to send its handshake before we drop it.
+const HANDSHAKE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
 
 pub fn new(
     addr: std::net::SocketAddr,
     msg_sink: piper::Sender<(Vec<u8>, peer::Handle)>,
+    config: &BlockchainConfig,
+    address_book: &std::sync::Arc<AddressBook>,
+    outgoing_target: usize,
+    limits: Limits,
+    control_queue_size: usize,
 ) -> std::io::Result<(Context, Handle)> {
-    let (control_signal_sender, control_signal_receiver) = piper::chan(10000); // TODO: think about the buffer size
+    let (control_signal_sender, control_signal_receiver) = piper::chan(control_queue_size);
     let handle = Handle {
         control_chan: control_signal_sender.clone(),
     };
     let ctx = Context {
-        peers: std::collections::HashMap::new(),
+        peers: HashMap::new(),
+        listen_addrs: HashMap::new(),
+        streams: HashMap::new(),
+        reputation: Reputation::new(),
+        tx_announcements: vec![],
+        known_txs: HashMap::new(),
         addr,
         control_chan: control_signal_receiver,
         control_sender: control_signal_sender,
         new_msg_chan: msg_sink,
+        config_hash: config.hash(),
+        address_book: std::sync::Arc::clone(address_book),
+        outgoing_target,
+        limits,
     };
     Ok((ctx, handle))
 }
 
 pub struct Context {
-    peers: std::collections::HashMap<std::net::SocketAddr, peer::Handle>,
+    peers: HashMap<std::net::SocketAddr, peer::Handle>,
+    listen_addrs: HashMap<std::net::SocketAddr, std::net::SocketAddr>, // peer address to the address its server listens at
+    streams: HashMap<std::net::SocketAddr, Arc<Async<net::TcpStream>>>, // connections, so that we can close them
+    reputation: Reputation,
+    tx_announcements: Vec<H256>, // new transactions to announce in the next batch
+    known_txs: HashMap<std::net::SocketAddr, KnownFilter>, // transactions that each peer has
     addr: std::net::SocketAddr,
     control_chan: piper::Receiver<ControlSignal>,
     control_sender: piper::Sender<ControlSignal>,
     new_msg_chan: piper::Sender<(Vec<u8>, peer::Handle)>,
+    config_hash: H256,
+    address_book: std::sync::Arc<AddressBook>,
+    outgoing_target: usize, // number of outgoing peers that we try to keep
+    limits: Limits,
 }
 
 impl Context {
@@ -61,10 +103,34 @@ impl Context {
         })
         .detach();
 
+        // start the task that periodically tops up outgoing peers
+        let maintenance_chan = control_chan.clone();
+        Task::local(async move {
+            loop {
+                maintenance_chan.send(ControlSignal::MaintainPeers).await;
+                Timer::after(PEER_MAINTENANCE_INTERVAL).await;
+            }
+        })
+        .detach();
+
+        // start the task that periodically announces new transactions
+        let announcement_chan = control_chan.clone();
+        Task::local(async move {
+            loop {
+                Timer::after(TX_ANNOUNCE_INTERVAL).await;
+                announcement_chan
+                    .send(ControlSignal::AnnounceTransactions)
+                    .await;
+            }
+        })
+        .detach();
+
         // finally, enter the loop that endlessly accept incoming peers
         loop {
             let (stream, addr) = listener.accept().await?;
-            control_chan.send(ControlSignal::GetNewPeer(stream)).await;
+            control_chan
+                .send(ControlSignal::GetNewPeer(stream, peer::Direction::Incoming))
+                .await;
             info!("Incoming peer from {}", addr);
         }
     }
@@ -84,13 +150,90 @@ impl Context {
                         hd.write(msg.clone());
                     }
                 }
-                ControlSignal::GetNewPeer(stream) => {
+                ControlSignal::GetNewPeer(stream, direction) => {
                     trace!("Processing GetNewPeer command");
-                    self.accept(stream).await?;
+                    self.accept(stream, direction).await?;
+                }
+                ControlSignal::AcceptedPeer(addr, mut handle, listen_addr) => {
+                    trace!("Processing AcceptedPeer({})", addr);
+                    // the address we dialed is known to work, otherwise trust what the peer says
+                    let listen_addr = match handle.direction() {
+                        peer::Direction::Outgoing => addr,
+                        peer::Direction::Incoming => {
+                            if listen_addr.ip().is_unspecified() {
+                                std::net::SocketAddr::new(addr.ip(), listen_addr.port())
+                            } else {
+                                listen_addr
+                            }
+                        }
+                    };
+                    if self.reputation.is_banned(&addr.ip()) {
+                        info!("Disconnecting banned peer {}", addr);
+                        self.disconnect(&addr);
+                        continue;
+                    }
+                    // an incoming peer can claim any address, so we only vouch for it once we
+                    // have dialed it ourselves
+                    match handle.direction() {
+                        peer::Direction::Outgoing => {
+                            self.address_book.mark_connected(&listen_addr).unwrap();
+                            handle.write(Message::GetPeers);
+                        }
+                        peer::Direction::Incoming => {
+                            self.address_book.add(&listen_addr).unwrap();
+                        }
+                    }
+                    self.listen_addrs.insert(addr, listen_addr);
+                    self.known_txs
+                        .insert(addr, KnownFilter::new(KNOWN_TX_FILTER_SIZE));
+                    self.peers.insert(addr, handle);
+                }
+                ControlSignal::MaintainPeers => {
+                    trace!("Processing MaintainPeers command");
+                    self.maintain_peers();
+                }
+                ControlSignal::NewTransaction(hash) => {
+                    self.tx_announcements.push(hash);
+                    if self.tx_announcements.len() >= TX_ANNOUNCE_BATCH_SIZE {
+                        self.announce_transactions();
+                    }
+                }
+                ControlSignal::KnownTransactions(addr, hashes) => {
+                    if let Some(filter) = self.known_txs.get_mut(&addr) {
+                        for hash in hashes {
+                            filter.insert(hash);
+                        }
+                    }
+                }
+                ControlSignal::AnnounceTransactions => {
+                    self.announce_transactions();
+                }
+                ControlSignal::Misbehaving(addr, misbehavior) => {
+                    trace!("Processing Misbehaving({}, {})", addr, misbehavior);
+                    warn!("Peer {} misbehaved: {}", addr, misbehavior);
+                    if self.reputation.penalize(addr.ip(), misbehavior) {
+                        warn!("Banning peers from {}", addr.ip());
+                        let banned: Vec<std::net::SocketAddr> = self
+                            .streams
+                            .keys()
+                            .filter(|a| a.ip() == addr.ip())
+                            .copied()
+                            .collect();
+                        for a in banned {
+                            self.disconnect(&a);
+                        }
+                    }
+                }
+                ControlSignal::GetBannedPeers(result_chan) => {
+                    trace!("Processing GetBannedPeers command");
+                    result_chan.send(self.reputation.banned()).unwrap_or(());
                 }
                 ControlSignal::DroppedPeer(addr) => {
                     trace!("Processing DroppedPeer({})", addr);
                     self.peers.remove(&addr);
+                    self.listen_addrs.remove(&addr);
+                    self.streams.remove(&addr);
+                    self.known_txs.remove(&addr);
                     info!("Peer {} disconnected", addr);
                 }
             }
@@ -107,56 +250,204 @@ impl Context {
         self.register(stream, peer::Direction::Outgoing).await
     }
 
-    async fn accept(&mut self, stream: Async<net::TcpStream>) -> std::io::Result<()> {
-        self.register(stream, peer::Direction::Incoming).await?;
+    async fn accept(
+        &mut self,
+        stream: Async<net::TcpStream>,
+        direction: peer::Direction,
+    ) -> std::io::Result<()> {
+        self.register(stream, direction).await?;
         Ok(())
     }
 
+    /// Start connecting to known addresses if we have fewer outgoing peers than the target, or
+    /// ask our peers for more addresses if we don't know enough.
+    fn maintain_peers(&mut self) {
+        let outgoing = self
+            .peers
+            .values()
+            .filter(|p| p.direction() == peer::Direction::Outgoing)
+            .count();
+        if outgoing >= self.outgoing_target {
+            return;
+        }
+        let connected: HashSet<std::net::SocketAddr> =
+            self.listen_addrs.values().copied().collect();
+        let own_addr = self.addr;
+        let reputation = &mut self.reputation;
+        let candidates: Vec<std::net::SocketAddr> = self
+            .address_book
+            .shuffled()
+            .unwrap()
+            .into_iter()
+            .filter(|a| *a != own_addr && !connected.contains(a) && !reputation.is_banned(&a.ip()))
+            .take(self.outgoing_target - outgoing)
+            .collect();
+        if outgoing + candidates.len() < self.outgoing_target {
+            for (_, hd) in self.peers.iter_mut() {
+                hd.write(Message::GetPeers);
+            }
+        }
+        // connect in the background, so that an unreachable address doesn't hold up the server
+        for addr in candidates {
+            let control_chan = self.control_sender.clone();
+            let address_book = std::sync::Arc::clone(&self.address_book);
+            Task::local(async move {
+                debug!("Establishing connection to known address {}", addr);
+                match Async::<std::net::TcpStream>::connect(&addr).await {
+                    Ok(stream) => {
+                        control_chan
+                            .send(ControlSignal::GetNewPeer(stream, peer::Direction::Outgoing))
+                            .await;
+                    }
+                    Err(e) => {
+                        debug!("Error connecting to known address {}: {}", addr, e);
+                        address_book.mark_failed(&addr).unwrap();
+                    }
+                }
+            })
+            .detach();
+        }
+    }
+
+    /// Announce the new transactions of the current batch to each peer that doesn't have them.
+    fn announce_transactions(&mut self) {
+        if self.tx_announcements.is_empty() {
+            return;
+        }
+        let hashes = std::mem::replace(&mut self.tx_announcements, vec![]);
+        for (addr, hd) in self.peers.iter_mut() {
+            let filter = match self.known_txs.get_mut(addr) {
+                Some(f) => f,
+                None => continue,
+            };
+            let new_hashes: Vec<H256> = hashes
+                .iter()
+                .copied()
+                .filter(|h| filter.insert(*h))
+                .collect();
+            if !new_hashes.is_empty() {
+                hd.write(Message::NewTransactionHashes(new_hashes));
+            }
+        }
+    }
+
+    /// Close the connection to the given peer and forget about it.
+    fn disconnect(&mut self, addr: &std::net::SocketAddr) {
+        self.peers.remove(addr);
+        self.listen_addrs.remove(addr);
+        self.known_txs.remove(addr);
+        if let Some(stream) = self.streams.remove(addr) {
+            stream.get_ref().shutdown(net::Shutdown::Both).unwrap_or(());
+        }
+    }
+
     async fn register(
         &mut self,
         stream: Async<net::TcpStream>,
-        _direction: peer::Direction,
+        direction: peer::Direction,
     ) -> std::io::Result<peer::Handle> {
         // create a handle so that we can write to this peer TODO
-        let (mut write_queue, handle) = peer::new(&stream)?;
+        let (mut write_queue, handle) = peer::new(&stream, direction)?;
 
         let stream = Arc::new(stream);
         let new_msg_chan = self.new_msg_chan.clone();
         let handle_copy = handle.clone();
         let control_chan = self.control_sender.clone();
         let addr = stream.get_ref().peer_addr()?;
+        self.streams.insert(addr, stream.clone());
+
+        // introduce ourselves, this is the first message the peer gets from us
+        let mut handle_for_handshake = handle.clone();
+        handle_for_handshake.write(Message::Handshake(Handshake {
+            version: PROTOCOL_VERSION,
+            config: self.config_hash,
+            listen_addr: self.addr,
+        }));
 
         // start the reactor for this peer
         // first, start a task that keeps reading from this guy
         let mut reader = BufReader::new(stream.clone());
+        let stream_for_reader = stream.clone();
+        let control_chan_for_reader = self.control_sender.clone();
+        let config_hash = self.config_hash;
+        let limits = self.limits.clone();
         Task::local(async move {
-            // the buffer to store the frame header, which contains the length of the frame
-            let mut size_buffer: [u8; 4] = [0; 4];
             // the buffer to store the message content
             let mut msg_buffer: Vec<u8> = vec![];
+
+            // the first frame must be the handshake of the peer, and we only start to talk to it
+            // after checking that it runs the same protocol and the same chain
+            let (handshake, msg_size) = match read_handshake(
+                &mut reader,
+                &limits,
+                &mut msg_buffer,
+                HANDSHAKE_TIMEOUT,
+            )
+            .await
+            {
+                Ok(msg_size) => (
+                    check_handshake(&msg_buffer[0..msg_size], config_hash),
+                    msg_size,
+                ),
+                Err(FrameError::Closed) => return,
+                Err(FrameError::Invalid(reason)) => (Err(reason), 0),
+            };
+            match handshake {
+                Ok(listen_addr) => {
+                    info!(
+                        "Peer {} listening at {} completed handshake",
+                        addr, listen_addr
+                    );
+                    control_chan_for_reader
+                        .send(ControlSignal::AcceptedPeer(
+                            addr,
+                            handle_copy.clone(),
+                            listen_addr,
+                        ))
+                        .await;
+                    // the workers start to bootstrap from the peers that we dialed once they get
+                    // the handshake
+                    if direction == peer::Direction::Outgoing {
+                        new_msg_chan
+                            .send((msg_buffer[0..msg_size].to_vec(), handle_copy.clone()))
+                            .await;
+                    }
+                }
+                Err(reason) => {
+                    warn!("Rejecting peer {}: {}", addr, reason);
+                    // closing the stream also stops the writer, which reports the dropped peer
+                    stream_for_reader
+                        .get_ref()
+                        .shutdown(net::Shutdown::Both)
+                        .unwrap_or(());
+                    return;
+                }
+            }
+
+            let mut rate_limiter = RateLimiter::new(&limits);
             loop {
-                // first, read exactly 4 bytes to get the frame header
-                let msg_size = match reader.read_exact(&mut size_buffer).await {
-                    Ok(_) => u32::from_be_bytes(size_buffer),
-                    Err(_) => {
+                let msg_size = match read_frame(&mut reader, &limits, &mut msg_buffer).await {
+                    Ok(msg_size) => msg_size,
+                    Err(FrameError::Closed) => break,
+                    Err(FrameError::Invalid(reason)) => {
+                        warn!("Disconnecting peer {}: {}", addr, reason);
+                        control_chan_for_reader
+                            .send(ControlSignal::Misbehaving(addr, Misbehavior::InvalidFrame))
+                            .await;
+                        stream_for_reader
+                            .get_ref()
+                            .shutdown(net::Shutdown::Both)
+                            .unwrap_or(());
                         break;
                     }
                 };
-                // then, read exactly msg_size bytes to get the whole message
-                if msg_buffer.len() < msg_size as usize {
-                    msg_buffer.resize(msg_size as usize, 0);
-                }
-                match reader
-                    .read_exact(&mut msg_buffer[0..msg_size as usize])
-                    .await
-                {
-                    Ok(_) => {
-                        let new_payload: Vec<u8> = msg_buffer[0..msg_size as usize].to_vec();
-                        new_msg_chan.send((new_payload, handle_copy.clone())).await;
-                    }
-                    Err(_) => {
-                        break;
-                    }
+                // this waits when the workers are busy, so that we stop reading from the peer
+                let new_payload: Vec<u8> = msg_buffer[0..msg_size].to_vec();
+                new_msg_chan.send((new_payload, handle_copy.clone())).await;
+                // slow down if the peer sends too much
+                let delay = rate_limiter.record(msg_size as u64 + 4);
+                if delay > time::Duration::from_secs(0) {
+                    Timer::after(delay).await;
                 }
             }
             // the peer is disconnected
@@ -167,8 +458,12 @@ impl Context {
         let mut writer = BufWriter::new(stream.clone());
         Task::local(async move {
             loop {
-                // first, get a message to write from the queue
-                let new_msg = write_queue.next().await.unwrap();
+                // first, get a message to write from the queue, which ends when all the handles
+                // of this peer are gone
+                let new_msg = match write_queue.next().await {
+                    Some(msg) => msg,
+                    None => break,
+                };
 
                 // second, encode the length of the message
                 let size_buffer = (new_msg.len() as u32).to_be_bytes();
@@ -198,12 +493,114 @@ impl Context {
         })
         .detach();
 
-        // insert the peer handle so that we can broadcast to this guy later
-        self.peers.insert(addr, handle.clone());
+        // the peer handle is inserted after the handshake, so that we can broadcast to this guy
         Ok(handle)
     }
 }
 
+/// Why we could not read a frame from a peer.
+enum FrameError {
+    /// The connection is closed.
+    Closed,
+    /// The frame is too large for its message type, or is not a message at all.
+    Invalid(String),
+}
+
+/// Read a frame into the given buffer, and return the size of the message. The size is checked
+/// against the limit of the message type before we read the rest of the message.
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+    msg_buffer: &mut Vec<u8>,
+) -> Result<usize, FrameError> {
+    // first, read exactly 4 bytes to get the frame header
+    let mut size_buffer: [u8; 4] = [0; 4];
+    if reader.read_exact(&mut size_buffer).await.is_err() {
+        return Err(FrameError::Closed);
+    }
+    let msg_size = u32::from_be_bytes(size_buffer);
+    // then, read the tag of the message type, which bincode puts at the beginning
+    if msg_size < 4 {
+        return Err(FrameError::Invalid(format!(
+            "frame of {} bytes is too short",
+            msg_size
+        )));
+    }
+    if msg_buffer.len() < 4 {
+        msg_buffer.resize(4, 0);
+    }
+    if reader.read_exact(&mut msg_buffer[0..4]).await.is_err() {
+        return Err(FrameError::Closed);
+    }
+    let tag = u32::from_le_bytes([msg_buffer[0], msg_buffer[1], msg_buffer[2], msg_buffer[3]]);
+    match limits.max_size(tag) {
+        Some(max_size) if msg_size <= max_size => {}
+        Some(max_size) => {
+            return Err(FrameError::Invalid(format!(
+                "{} message of {} bytes exceeds the limit of {} bytes",
+                message::MAX_SIZES[tag as usize].0,
+                msg_size,
+                max_size
+            )))
+        }
+        None => return Err(FrameError::Invalid(format!("unknown message type {}", tag))),
+    }
+    // finally, read the rest of the message
+    let msg_size = msg_size as usize;
+    if msg_buffer.len() < msg_size {
+        msg_buffer.resize(msg_size, 0);
+    }
+    if reader
+        .read_exact(&mut msg_buffer[4..msg_size])
+        .await
+        .is_err()
+    {
+        return Err(FrameError::Closed);
+    }
+    Ok(msg_size)
+}
+
+/// Read the first frame from a peer, which should be its handshake, and give up if it does not
+/// arrive within `timeout`.
+async fn read_handshake<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+    msg_buffer: &mut Vec<u8>,
+    timeout: time::Duration,
+) -> Result<usize, FrameError> {
+    let read = read_frame(reader, limits, msg_buffer);
+    futures::pin_mut!(read);
+    match future::select(read, Timer::after(timeout)).await {
+        future::Either::Left((result, _)) => result,
+        future::Either::Right(_) => Err(FrameError::Invalid(format!(
+            "no handshake within {} seconds",
+            timeout.as_secs()
+        ))),
+    }
+}
+
+/// Check the handshake of a peer against ours, and return the listen address of the peer.
+fn check_handshake(raw: &[u8], config_hash: H256) -> Result<std::net::SocketAddr, String> {
+    let handshake = match bincode::deserialize(raw) {
+        Ok(Message::Handshake(h)) => h,
+        Ok(_) => return Err("the first message is not a handshake".to_string()),
+        Err(e) => return Err(format!("malformed handshake: {}", e)),
+    };
+    if handshake.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "protocol version {} does not match ours ({})",
+            handshake.version, PROTOCOL_VERSION
+        ));
+    }
+    if handshake.config != config_hash {
+        return Err(
+            "blockchain config (voter chains, mining rates or genesis) does not match ours"
+                .to_string(),
+        );
+    }
+    Ok(handshake.listen_addr)
+}
+
 #[derive(Clone)]
 pub struct Handle {
     control_chan: piper::Sender<ControlSignal>,
@@ -222,6 +619,36 @@ impl Handle {
     pub fn broadcast(&self, msg: message::Message) {
         futures::executor::block_on(self.control_chan.send(ControlSignal::BroadcastMessage(msg)));
     }
+
+    /// Announce a new transaction in the memory pool to the peers in the next batch.
+    pub fn announce_transaction(&self, hash: H256) {
+        futures::executor::block_on(self.control_chan.send(ControlSignal::NewTransaction(hash)));
+    }
+
+    /// Record that the given peer has the given transactions, so that we don't announce them to it.
+    pub fn mark_known_transactions(&self, addr: std::net::SocketAddr, hashes: Vec<H256>) {
+        futures::executor::block_on(
+            self.control_chan
+                .send(ControlSignal::KnownTransactions(addr, hashes)),
+        );
+    }
+
+    /// Lower the reputation of the given peer, and ban it if it has misbehaved too much.
+    pub fn report(&self, addr: std::net::SocketAddr, misbehavior: Misbehavior) {
+        futures::executor::block_on(
+            self.control_chan
+                .send(ControlSignal::Misbehaving(addr, misbehavior)),
+        );
+    }
+
+    pub fn banned_peers(&self) -> Vec<BannedPeer> {
+        let (sender, receiver) = oneshot::channel();
+        futures::executor::block_on(
+            self.control_chan
+                .send(ControlSignal::GetBannedPeers(sender)),
+        );
+        futures::executor::block_on(receiver).unwrap()
+    }
 }
 
 enum ControlSignal {
@@ -230,6 +657,81 @@ enum ControlSignal {
         oneshot::Sender<std::io::Result<peer::Handle>>,
     ),
     BroadcastMessage(message::Message),
-    GetNewPeer(Async<net::TcpStream>),
+    GetNewPeer(Async<net::TcpStream>, peer::Direction),
+    AcceptedPeer(std::net::SocketAddr, peer::Handle, std::net::SocketAddr),
+    MaintainPeers,
+    NewTransaction(H256),
+    KnownTransactions(std::net::SocketAddr, Vec<H256>),
+    AnnounceTransactions,
+    Misbehaving(std::net::SocketAddr, Misbehavior),
+    GetBannedPeers(oneshot::Sender<Vec<BannedPeer>>),
     DroppedPeer(std::net::SocketAddr),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A peer that never sends anything.
+    struct Silent;
+
+    impl AsyncRead for Silent {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context,
+            _buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    fn handshake(version: u32, config: H256) -> Vec<u8> {
+        bincode::serialize(&Message::Handshake(Handshake {
+            version,
+            config,
+            listen_addr: "127.0.0.1:6000".parse().unwrap(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reject_mismatched_handshake() {
+        let config: H256 = [1u8; 32].into();
+        assert_eq!(
+            check_handshake(&handshake(PROTOCOL_VERSION, config), config),
+            Ok("127.0.0.1:6000".parse().unwrap())
+        );
+        assert!(check_handshake(&handshake(PROTOCOL_VERSION + 1, config), config).is_err());
+        assert!(check_handshake(&handshake(PROTOCOL_VERSION, [2u8; 32].into()), config).is_err());
+        let ping = bincode::serialize(&Message::Ping("hello".to_string())).unwrap();
+        assert!(check_handshake(&ping, config).is_err());
+        assert!(check_handshake(&[0xff; 3], config).is_err());
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let limits = Limits::default();
+        let mut msg_buffer = vec![];
+        let result = smol::run(read_handshake(
+            &mut Silent,
+            &limits,
+            &mut msg_buffer,
+            time::Duration::from_millis(50),
+        ));
+        assert!(matches!(result, Err(FrameError::Invalid(_))));
+
+        // a handshake that arrives in time is read as usual
+        let raw = handshake(PROTOCOL_VERSION, H256::default());
+        let mut frame = (raw.len() as u32).to_be_bytes().to_vec();
+        frame.extend(&raw);
+        let result = smol::run(read_handshake(
+            &mut &frame[..],
+            &limits,
+            &mut msg_buffer,
+            time::Duration::from_secs(10),
+        ));
+        assert!(matches!(result, Ok(size) if msg_buffer[..size] == raw[..]));
+    }
+}
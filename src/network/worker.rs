@@ -1,27 +1,38 @@
+use super::address_book::AddressBook;
 use super::buffer::BlockBuffer;
 use super::message::Message;
 use super::peer;
-use crate::block::{Block, Content};
+use super::reputation::Misbehavior;
+use crate::block::{Block, Content, HeaderProof};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::config::*;
 use crate::crypto::hash::{Hashable, H256};
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
-use crate::handler::new_transaction;
+use crate::handler::new_transactions;
 use crate::handler::new_validated_block;
 use crate::miner::memory_pool::MemoryPool;
 use crate::miner::ContextUpdateSignal;
 use crate::network::server::Handle as ServerHandle;
 use crate::utxodb::UtxoDatabase;
-use crate::validation::{self, BlockResult};
+use crate::validation::{self, BlockResult, TransactionRejection};
 use crate::wallet::Wallet;
 use crossbeam::channel;
-use log::{debug, warn};
-use std::collections::HashSet;
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+/// Number of headers to send in one batch when a peer is bootstrapping from us.
+const HEADER_BATCH_SIZE: u64 = 2000;
+/// Maximum number of addresses to exchange in one message.
+const MAX_PEER_ADDRESSES: usize = 1000;
+/// Maximum total size in bytes of the blocks in one `Blocks` message. Larger replies are split, so
+/// that they stay under the size limit of the peer.
+const MAX_BLOCKS_MESSAGE_SIZE: usize = 8 << 20;
+
 #[derive(Clone)]
 pub struct Context {
     msg_chan: piper::Receiver<(Vec<u8>, peer::Handle)>,
@@ -33,9 +44,12 @@ pub struct Context {
     mempool: Arc<Mutex<MemoryPool>>,
     context_update_chan: channel::Sender<ContextUpdateSignal>,
     server: ServerHandle,
+    address_book: Arc<AddressBook>,
     buffer: Arc<Mutex<BlockBuffer>>,
     recent_blocks: Arc<Mutex<HashSet<H256>>>, // blocks that we have received but not yet inserted
     requested_blocks: Arc<Mutex<HashSet<H256>>>, // blocks that we have requested but not yet received
+    synced_blocks: Arc<Mutex<HashSet<H256>>>, // blocks among those that we requested after syncing their headers
+    bootstrap_cursors: Arc<Mutex<HashMap<SocketAddr, H256>>>, // address we dialed to the last block we bootstrapped from it
     config: BlockchainConfig,
 }
 
@@ -49,6 +63,7 @@ pub fn new(
     mempool: &Arc<Mutex<MemoryPool>>,
     ctx_update_sink: channel::Sender<ContextUpdateSignal>,
     server: &ServerHandle,
+    address_book: &Arc<AddressBook>,
     config: BlockchainConfig,
 ) -> Context {
     Context {
@@ -61,9 +76,12 @@ pub fn new(
         mempool: Arc::clone(mempool),
         context_update_chan: ctx_update_sink,
         server: server.clone(),
+        address_book: Arc::clone(address_book),
         buffer: Arc::new(Mutex::new(BlockBuffer::new())),
         recent_blocks: Arc::new(Mutex::new(HashSet::new())),
         requested_blocks: Arc::new(Mutex::new(HashSet::new())),
+        synced_blocks: Arc::new(Mutex::new(HashSet::new())),
+        bootstrap_cursors: Arc::new(Mutex::new(HashMap::new())),
         config,
     }
 }
@@ -85,8 +103,22 @@ impl Context {
             let msg = futures::executor::block_on(self.msg_chan.recv()).unwrap();
             PERFORMANCE_COUNTER.record_process_message();
             let (msg, mut peer) = msg;
-            let msg: Message = bincode::deserialize(&msg).unwrap();
+            let msg: Message = match bincode::deserialize(&msg) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    debug!("Malformed message from peer {}: {}", peer.addr(), e);
+                    self.server
+                        .report(peer.addr(), Misbehavior::MalformedMessage);
+                    continue;
+                }
+            };
             match msg {
+                Message::Handshake(_) => {
+                    // the server passes on the handshake of each peer once it checks out
+                    if peer.direction() == peer::Direction::Outgoing {
+                        self.bootstrap(&mut peer);
+                    }
+                }
                 Message::Ping(nonce) => {
                     debug!("Ping: {}", nonce);
                     peer.write(Message::Pong(nonce.to_string()));
@@ -97,11 +129,12 @@ impl Context {
                 Message::NewTransactionHashes(hashes) => {
                     debug!("Got {} new transaction hashes", hashes.len());
                     let mut hashes_to_request = vec![];
-                    for hash in hashes {
-                        if !self.mempool.lock().unwrap().contains(&hash) {
-                            hashes_to_request.push(hash);
+                    for hash in &hashes {
+                        if !self.mempool.lock().unwrap().contains(hash) {
+                            hashes_to_request.push(*hash);
                         }
                     }
+                    self.server.mark_known_transactions(peer.addr(), hashes);
                     if !hashes_to_request.is_empty() {
                         peer.write(Message::GetTransactions(hashes_to_request));
                     }
@@ -121,8 +154,44 @@ impl Context {
                 }
                 Message::Transactions(transactions) => {
                     debug!("Got {} transactions", transactions.len());
-                    for transaction in transactions {
-                        new_transaction(transaction, &self.mempool, &self.server);
+                    self.server.mark_known_transactions(
+                        peer.addr(),
+                        transactions.iter().map(|t| t.hash()).collect(),
+                    );
+                    let hashes: Vec<H256> = transactions.iter().map(|t| t.hash()).collect();
+                    let results =
+                        new_transactions(transactions, &self.mempool, &self.utxodb, &self.server);
+                    let mut rejections = vec![];
+                    for (hash, result) in hashes.into_iter().zip(results) {
+                        let rejection = match result {
+                            Ok(()) => continue,
+                            Err(r) => r,
+                        };
+                        if rejection.is_invalid() {
+                            warn!("Ignoring invalid transaction {:.8}: {}", hash, rejection);
+                            self.server
+                                .report(peer.addr(), Misbehavior::InvalidTransaction);
+                        } else {
+                            debug!("Rejected transaction {:.8}: {}", hash, rejection);
+                        }
+                        // duplicates are common, since several peers may relay the same
+                        // transaction to us at the same time
+                        if rejection != TransactionRejection::Duplicate {
+                            rejections.push((hash, rejection));
+                        }
+                    }
+                    if !rejections.is_empty() {
+                        peer.write(Message::RejectedTransactions(rejections));
+                    }
+                }
+                Message::RejectedTransactions(rejections) => {
+                    for (hash, rejection) in rejections {
+                        debug!(
+                            "Peer {} rejected transaction {:.8}: {}",
+                            peer.addr(),
+                            hash,
+                            rejection
+                        );
                     }
                 }
                 Message::NewBlockHashes(hashes) => {
@@ -149,10 +218,19 @@ impl Context {
                 Message::GetBlocks(hashes) => {
                     debug!("Asked for {} blocks", hashes.len());
                     let mut blocks = vec![];
+                    let mut size = 0;
                     for hash in hashes {
                         match self.blockdb.get_encoded(&hash).unwrap() {
                             None => {}
                             Some(encoded_block) => {
+                                if size + encoded_block.len() > MAX_BLOCKS_MESSAGE_SIZE
+                                    && !blocks.is_empty()
+                                {
+                                    peer.write(Message::Blocks(blocks));
+                                    blocks = vec![];
+                                    size = 0;
+                                }
+                                size += encoded_block.len();
                                 blocks.push(encoded_block.to_vec());
                             }
                         }
@@ -161,180 +239,409 @@ impl Context {
                 }
                 Message::Blocks(encoded_blocks) => {
                     debug!("Got {} blocks", encoded_blocks.len());
-
-                    // decode the blocks
-                    let mut blocks: Vec<Block> = vec![];
-                    let mut hashes: Vec<H256> = vec![];
-                    for encoded_block in &encoded_blocks {
-                        let block: Block = bincode::deserialize(&encoded_block).unwrap();
-                        let hash = block.hash();
-
-                        // now that the block that we request has arrived, remove it from the set
-                        // of requested blocks. removing it at this stage causes a race condition,
-                        // where the block could have been removed from requested_blocks but not
-                        // yet inserted into the database. but this does not cause correctness
-                        // problem and hardly incurs a performance issue (I hope)
-                        let mut requested_blocks = self.requested_blocks.lock().unwrap();
-                        requested_blocks.remove(&hash);
-                        drop(requested_blocks);
-
-                        // check POW here. If POW does not pass, discard the block at this
-                        // stage
-                        let pow_check = validation::check_pow_sortition_id(&block, &self.config);
-                        match pow_check {
-                            BlockResult::Pass => {}
-                            _ => continue,
-                        }
-
-                        // check whether the block is being processed. note that here we use lock
-                        // to make sure that the hash either in recent_blocks, or blockdb, so we
-                        // don't have a single duplicate
-                        let mut recent_blocks = self.recent_blocks.lock().unwrap();
-                        if recent_blocks.contains(&hash) {
-                            drop(recent_blocks);
-                            continue;
-                        }
-                        // register this block as being processed
-                        recent_blocks.insert(hash);
-                        drop(recent_blocks);
-
-                        // TODO: consider the ordering here. I'd expect a lot of duplicate blocks
-                        // to proceed to this step, which means a lot of useless database lookups
-                        // and lock/unlocks
-                        // detect duplicates
-                        if self.blockdb.contains(&hash).unwrap() {
-                            let mut recent_blocks = self.recent_blocks.lock().unwrap();
-                            recent_blocks.remove(&hash);
-                            drop(recent_blocks);
-                            continue;
-                        }
-
-                        // store the block into database
-                        self.blockdb.insert_encoded(&hash, &encoded_block).unwrap();
-
-                        // now that this block is store, remove the reference
-                        let mut recent_blocks = self.recent_blocks.lock().unwrap();
-                        recent_blocks.remove(&hash);
-                        drop(recent_blocks);
-
-                        blocks.push(block);
-                        hashes.push(hash);
-                    }
-
-                    for block in &blocks {
-                        PERFORMANCE_COUNTER.record_receive_block(&block);
+                    self.handle_blocks(&encoded_blocks, &mut peer);
+                }
+                Message::GetHeaders(after) => {
+                    debug!("Asked for headers after {:.8}", &after);
+                    let headers: Vec<HeaderProof> =
+                        match self.blockdb.blocks_after(&after, HEADER_BATCH_SIZE).next() {
+                            Some(blocks) => blocks.iter().map(|b| b.header_proof()).collect(),
+                            None => vec![],
+                        };
+                    peer.write(Message::Headers(headers));
+                }
+                Message::Headers(headers) => {
+                    debug!("Got {} headers", headers.len());
+                    if peer.direction() != peer::Direction::Outgoing {
+                        self.server
+                            .report(peer.addr(), Misbehavior::UnrequestedPayload);
+                        continue;
                     }
-
-                    // tell peers about the new blocks
-                    // TODO: we will do this only in a reasonable network topology
-                    if hashes.is_empty() {
-                        continue; // end processing this message
+                    if headers.is_empty() {
+                        info!("Finished bootstrapping from peer {}", peer.addr());
+                        continue;
                     }
-                    self.server
-                        .broadcast(Message::NewBlockHashes(hashes.clone()));
-
-                    // process each block
-                    let mut to_process: Vec<Block> = blocks;
-                    let mut to_request: Vec<H256> = vec![];
-                    let mut context_update_sig = vec![];
-                    while let Some(block) = to_process.pop() {
-                        // check data availability
-                        // make sure checking data availability and buffering are one atomic
-                        // operation. see the comments in buffer.rs
-                        let mut buffer = self.buffer.lock().unwrap();
-                        let data_availability =
-                            validation::check_data_availability(&block, &self.chain, &self.blockdb);
-                        match data_availability {
-                            BlockResult::Pass => drop(buffer),
-                            BlockResult::MissingReferences(r) => {
-                                debug!(
-                                    "Missing {} referred blocks for block {:.8}",
-                                    r.len(),
-                                    block.hash()
-                                );
-                                buffer.insert(block, &r);
-                                to_request.extend_from_slice(&r);
-                                drop(buffer);
-                                continue;
+                    // check PoW and sortition before we pull the content of any block, and pull
+                    // proposer and voter blocks first. note that a proposer block still waits in
+                    // the buffer for the transaction blocks it refers to, so the tips only move
+                    // once those have arrived too
+                    let mut main_blocks_to_request = vec![];
+                    let mut transaction_blocks_to_request = vec![];
+                    // the last header that passed the checks
+                    let mut cursor = None;
+                    let mut all_valid = true;
+                    for header in &headers {
+                        let hash = header.hash();
+                        let pow_check = validation::check_pow_sortition_id(header, &self.config);
+                        match pow_check {
+                            BlockResult::Pass => {}
+                            _ => {
+                                warn!("Ignoring invalid header {:.8}: {}", hash, pow_check);
+                                self.server.report(peer.addr(), Misbehavior::InvalidPow);
+                                all_valid = false;
+                                break;
                             }
-                            _ => unreachable!(),
                         }
-
-                        // check sortition proof and content semantics
                         let sortition_proof =
-                            validation::check_sortition_proof(&block, &self.config);
+                            validation::check_sortition_proof(header, &self.config);
                         match sortition_proof {
                             BlockResult::Pass => {}
                             _ => {
-                                warn!(
-                                    "Ignoring invalid block {:.8}: {}",
-                                    block.hash(),
-                                    sortition_proof
-                                );
-                                continue;
+                                warn!("Ignoring invalid header {:.8}: {}", hash, sortition_proof);
+                                self.server.report(peer.addr(), Misbehavior::InvalidBlock);
+                                all_valid = false;
+                                break;
                             }
                         }
-                        let content_semantic =
-                            validation::check_content_semantic(&block, &self.chain, &self.blockdb);
-                        match content_semantic {
-                            BlockResult::Pass => {}
-                            _ => {
-                                warn!(
-                                    "Ignoring invalid block {:.8}: {}",
-                                    block.hash(),
-                                    content_semantic
-                                );
-                                continue;
-                            }
+                        cursor = Some(hash);
+                        if self.blockdb.contains(&hash).unwrap() {
+                            continue;
                         }
-
-                        debug!("Processing block {:.8}", block.hash());
-                        new_validated_block(
-                            &block,
-                            &self.mempool,
-                            &self.blockdb,
-                            &self.chain,
-                            &self.server,
-                        );
-                        context_update_sig.push(match &block.content {
-                            Content::Proposer(_) => ContextUpdateSignal::NewProposerBlock,
-                            Content::Voter(c) => ContextUpdateSignal::NewVoterBlock(c.chain_number),
-                            Content::Transaction(_) => ContextUpdateSignal::NewTransactionBlock,
-                        });
-                        let mut buffer = self.buffer.lock().unwrap();
-                        let mut resolved_by_current = buffer.satisfy(block.hash());
-                        drop(buffer);
-                        if !resolved_by_current.is_empty() {
-                            debug!(
-                                "Resolved dependency for {} buffered blocks",
-                                resolved_by_current.len()
-                            );
+                        let mut requested_blocks = self.requested_blocks.lock().unwrap();
+                        if !requested_blocks.insert(hash) {
+                            continue;
                         }
-                        for b in resolved_by_current.drain(..) {
-                            to_process.push(b);
+                        drop(requested_blocks);
+                        self.synced_blocks.lock().unwrap().insert(hash);
+                        if header.sortition_id == TRANSACTION_INDEX {
+                            transaction_blocks_to_request.push(hash);
+                        } else {
+                            main_blocks_to_request.push(hash);
                         }
                     }
-                    // tell the miner to update the context
-                    for sig in context_update_sig {
-                        self.context_update_chan.send(sig).unwrap();
+                    if !main_blocks_to_request.is_empty() {
+                        peer.write(Message::GetBlocks(main_blocks_to_request));
                     }
-
-                    if !to_request.is_empty() {
-                        to_request.sort();
-                        to_request.dedup();
-                        peer.write(Message::GetBlocks(to_request));
+                    if !transaction_blocks_to_request.is_empty() {
+                        peer.write(Message::GetBlocks(transaction_blocks_to_request));
+                    }
+                    // remember where we are, so that we can resume from here should the
+                    // connection drop. the headers after an invalid one are never skipped, since
+                    // we stop syncing from a peer that sends an invalid header
+                    if let Some(cursor) = cursor {
+                        self.bootstrap_cursors
+                            .lock()
+                            .unwrap()
+                            .insert(peer.addr(), cursor);
+                    }
+                    match cursor {
+                        Some(cursor) if all_valid => peer.write(Message::GetHeaders(cursor)),
+                        _ => info!("Stopped bootstrapping from peer {}", peer.addr()),
+                    }
+                }
+                Message::GetPeers => {
+                    debug!("Asked for peer addresses");
+                    let addrs = self.address_book.verified(MAX_PEER_ADDRESSES).unwrap();
+                    peer.write(Message::Peers(addrs));
+                }
+                Message::Peers(addrs) => {
+                    debug!("Got {} peer addresses", addrs.len());
+                    for addr in addrs.iter().take(MAX_PEER_ADDRESSES) {
+                        self.address_book.add(addr).unwrap();
                     }
                 }
-                Message::Bootstrap(after) => {
-                    debug!("Asked for all blocks after {}", &after);
-                    /*
-                     * TODO: recover this message
-                    for batch in self.blockdb.blocks_after(&after, 500) {
-                        peer.write(Message::Blocks(batch));
+            }
+        }
+    }
+
+    /// Ask the given peer for the blocks we don't have yet, starting from where we stopped last
+    /// time we bootstrapped from it, or from our latest block. We first download the headers,
+    /// and only pull the content of the blocks whose PoW and sortition are valid. We only
+    /// bootstrap from peers that we dialed, so the address of the peer is where it listens, and
+    /// stays the same when we reconnect.
+    fn bootstrap(&self, peer: &mut peer::Handle) {
+        let cursor = match self.bootstrap_cursors.lock().unwrap().get(&peer.addr()) {
+            Some(cursor) => *cursor,
+            None => self.blockdb.latest_block_hash().unwrap(),
+        };
+        info!(
+            "Bootstrapping from peer {} after block {:.8}",
+            peer.addr(),
+            cursor
+        );
+        peer.write(Message::GetHeaders(cursor));
+    }
+
+    /// Store the given blocks, which are answers to our `GetBlocks` requests, and process them.
+    /// Announce the new blocks to our peers, except those we pulled after syncing their headers,
+    /// since our peers are likely catching up on them too. Ask the given peer for the blocks they
+    /// refer to that we miss, and report it if it sends blocks that we did not ask for.
+    fn handle_blocks(&self, encoded_blocks: &[Vec<u8>], peer: &mut peer::Handle) {
+        // decode the blocks
+        let mut blocks: Vec<Block> = vec![];
+        let mut hashes: Vec<H256> = vec![];
+        let mut to_announce: Vec<H256> = vec![];
+        for encoded_block in encoded_blocks {
+            // skip the bad entry, the blocks before it are already stored and must be processed
+            let block: Block = match bincode::deserialize(&encoded_block) {
+                Ok(b) => b,
+                Err(_) => {
+                    self.server
+                        .report(peer.addr(), Misbehavior::MalformedMessage);
+                    continue;
+                }
+            };
+            let hash = block.hash();
+
+            // now that the block that we request has arrived, remove it from the set
+            // of requested blocks. removing it at this stage causes a race condition,
+            // where the block could have been removed from requested_blocks but not
+            // yet inserted into the database. but this does not cause correctness
+            // problem and hardly incurs a performance issue (I hope)
+            let mut requested_blocks = self.requested_blocks.lock().unwrap();
+            let requested = requested_blocks.remove(&hash);
+            drop(requested_blocks);
+            if !requested {
+                self.server
+                    .report(peer.addr(), Misbehavior::UnrequestedPayload);
+            }
+            let synced = self.synced_blocks.lock().unwrap().remove(&hash);
+
+            // check POW here. If POW does not pass, discard the block at this
+            // stage
+            let pow_check = validation::check_pow_sortition_id(&block, &self.config);
+            match pow_check {
+                BlockResult::Pass => {}
+                _ => {
+                    warn!("Ignoring invalid block {:.8}: {}", hash, pow_check);
+                    self.server.report(peer.addr(), Misbehavior::InvalidPow);
+                    continue;
+                }
+            }
+
+            // the sortition proof only depends on the block itself, so check it before storing
+            // the block
+            let sortition_proof = validation::check_sortition_proof(&block, &self.config);
+            match sortition_proof {
+                BlockResult::Pass => {}
+                _ => {
+                    warn!("Ignoring invalid block {:.8}: {}", hash, sortition_proof);
+                    self.server.report(peer.addr(), Misbehavior::InvalidBlock);
+                    continue;
+                }
+            }
+
+            // don't store a block from the future, so that we fetch it again once it is due.
+            // honest clocks may be off a bit, so the peer is not at fault
+            let future_timestamp = validation::check_future_timestamp(&block, &self.config);
+            match future_timestamp {
+                BlockResult::Pass => {}
+                _ => {
+                    debug!("Ignoring block {:.8}: {}", hash, future_timestamp);
+                    continue;
+                }
+            }
+
+            // check whether the block is being processed. note that here we use lock
+            // to make sure that the hash either in recent_blocks, or blockdb, so we
+            // don't have a single duplicate
+            let mut recent_blocks = self.recent_blocks.lock().unwrap();
+            if recent_blocks.contains(&hash) {
+                drop(recent_blocks);
+                continue;
+            }
+            // register this block as being processed
+            recent_blocks.insert(hash);
+            drop(recent_blocks);
+
+            // TODO: consider the ordering here. I'd expect a lot of duplicate blocks
+            // to proceed to this step, which means a lot of useless database lookups
+            // and lock/unlocks
+            // detect duplicates
+            if self.blockdb.contains(&hash).unwrap() {
+                let mut recent_blocks = self.recent_blocks.lock().unwrap();
+                recent_blocks.remove(&hash);
+                drop(recent_blocks);
+                continue;
+            }
+
+            // store the block into database
+            self.blockdb.insert_encoded(&hash, &encoded_block).unwrap();
+
+            // now that this block is store, remove the reference
+            let mut recent_blocks = self.recent_blocks.lock().unwrap();
+            recent_blocks.remove(&hash);
+            drop(recent_blocks);
+
+            blocks.push(block);
+            hashes.push(hash);
+            if !synced {
+                to_announce.push(hash);
+            }
+        }
+
+        for block in &blocks {
+            PERFORMANCE_COUNTER.record_receive_block(&block);
+        }
+
+        // tell peers about the new blocks
+        // TODO: we will do this only in a reasonable network topology
+        if hashes.is_empty() {
+            return; // end processing this message
+        }
+        if !to_announce.is_empty() {
+            self.server.broadcast(Message::NewBlockHashes(to_announce));
+        }
+
+        // process each block
+        let (mut to_request, invalid) = self.process_blocks(blocks);
+        // blocks resolved from the buffer may come from other peers
+        if invalid.iter().any(|h| hashes.contains(h)) {
+            self.server.report(peer.addr(), Misbehavior::InvalidBlock);
+        }
+        // don't ask for the blocks that we are already waiting for
+        let mut requested_blocks = self.requested_blocks.lock().unwrap();
+        to_request.retain(|h| requested_blocks.insert(*h));
+        drop(requested_blocks);
+        if !to_request.is_empty() {
+            to_request.sort();
+            peer.write(Message::GetBlocks(to_request));
+        }
+    }
+
+    /// Validate the given blocks that are already in the block database, and insert them into
+    /// the blockchain. Blocks with missing references are buffered. Return the hashes of the
+    /// missing blocks, and the hashes of the blocks that turned out to be invalid.
+    fn process_blocks(&self, blocks: Vec<Block>) -> (Vec<H256>, Vec<H256>) {
+        let mut to_process: Vec<Block> = blocks;
+        let mut to_request: Vec<H256> = vec![];
+        let mut invalid: Vec<H256> = vec![];
+        let mut context_update_sig = vec![];
+        while let Some(block) = to_process.pop() {
+            // check data availability
+            // make sure checking data availability and buffering are one atomic
+            // operation. see the comments in buffer.rs
+            let mut buffer = self.buffer.lock().unwrap();
+            let data_availability =
+                validation::check_data_availability(&block, &self.chain, &self.blockdb);
+            match data_availability {
+                BlockResult::Pass => drop(buffer),
+                BlockResult::MissingReferences(r) => {
+                    debug!(
+                        "Missing {} referred blocks for block {:.8}",
+                        r.len(),
+                        block.hash()
+                    );
+                    buffer.insert(block, &r);
+                    to_request.extend_from_slice(&r);
+                    drop(buffer);
+                    continue;
+                }
+                _ => unreachable!(),
+            }
+
+            // check the timestamp and the difficulty against the proposer chain of the parent
+            let mut retarget = validation::check_timestamp(&block, &self.blockdb, &self.config);
+            if let BlockResult::Pass = retarget {
+                retarget =
+                    validation::check_difficulty(&block, &self.chain, &self.blockdb, &self.config);
+            }
+            match retarget {
+                BlockResult::Pass => {}
+                BlockResult::MissingReferences(r) => {
+                    // the proposer ancestors of a block in the chain should all be stored, but
+                    // wait for them rather than give up on the block should one be missing
+                    warn!(
+                        "Missing {} proposer ancestors of block {:.8}",
+                        r.len(),
+                        block.hash()
+                    );
+                    self.buffer.lock().unwrap().insert(block, &r);
+                    to_request.extend_from_slice(&r);
+                    continue;
+                }
+                _ => {
+                    warn!("Ignoring invalid block {:.8}: {}", block.hash(), retarget);
+                    invalid.push(block.hash());
+                    continue;
+                }
+            }
+
+            // check content semantics
+            let content_semantic =
+                validation::check_content_semantic(&block, &self.chain, &self.blockdb);
+            match content_semantic {
+                BlockResult::Pass => {}
+                _ => {
+                    warn!(
+                        "Ignoring invalid block {:.8}: {}",
+                        block.hash(),
+                        content_semantic
+                    );
+                    invalid.push(block.hash());
+                    continue;
+                }
+            }
+
+            debug!("Processing block {:.8}", block.hash());
+            new_validated_block(
+                &block,
+                &self.mempool,
+                &self.blockdb,
+                &self.chain,
+                &self.server,
+            );
+            context_update_sig.push(match &block.content {
+                Content::Proposer(_) => ContextUpdateSignal::NewProposerBlock,
+                Content::Voter(c) => ContextUpdateSignal::NewVoterBlock(c.chain_number),
+                Content::Transaction(_) => ContextUpdateSignal::NewTransactionBlock,
+            });
+            let mut buffer = self.buffer.lock().unwrap();
+            let mut resolved_by_current = buffer.satisfy(block.hash());
+            drop(buffer);
+            if !resolved_by_current.is_empty() {
+                debug!(
+                    "Resolved dependency for {} buffered blocks",
+                    resolved_by_current.len()
+                );
+            }
+            for b in resolved_by_current.drain(..) {
+                to_process.push(b);
+            }
+        }
+        // tell the miner to update the context
+        for sig in context_update_sig {
+            self.context_update_chan.send(sig).unwrap();
+        }
+        (to_request, invalid)
+    }
+
+    /// Process the blocks left in the block database but not in the blockchain, e.g. when the
+    /// node was stopped in the middle of processing them. Must be called before `start`.
+    pub fn process_stored_blocks(&self) {
+        let mut blocks: Vec<Block> = vec![];
+        for batch in self
+            .blockdb
+            .blocks_after(&self.config.proposer_genesis, 500)
+        {
+            for block in batch {
+                let hash = block.hash();
+                let in_chain = match &block.content {
+                    Content::Proposer(_) => self.chain.contains_proposer(&hash).unwrap(),
+                    Content::Voter(_) => self.chain.contains_voter(&hash).unwrap(),
+                    Content::Transaction(_) => self.chain.contains_transaction(&hash).unwrap(),
+                };
+                if !in_chain {
+                    // blocks stored by older versions were not checked before being stored
+                    match validation::check_sortition_proof(&block, &self.config) {
+                        BlockResult::Pass => blocks.push(block),
+                        r => warn!("Ignoring invalid stored block {:.8}: {}", hash, r),
                     }
-                    */
                 }
             }
         }
+        if blocks.is_empty() {
+            return;
+        }
+        info!("Processing {} stored blocks", blocks.len());
+        // blocks are popped from the end, so put the earliest arrival there
+        blocks.reverse();
+        let (missing, _) = self.process_blocks(blocks);
+        if !missing.is_empty() {
+            warn!(
+                "Buffered stored blocks with {} missing references",
+                missing.len()
+            );
+        }
     }
 }
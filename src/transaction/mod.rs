@@ -3,6 +3,7 @@ use crate::experiment::performance_counter::PayloadSize;
 use bincode::serialize;
 
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::hash::Hash;
 
 /// A unique identifier of a transaction output, a.k.a. a coin.
@@ -33,7 +34,6 @@ pub struct Input {
 }
 
 /// An output of a transaction.
-// TODO: coinbase output (transaction fee). Maybe we don't need that in this case.
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Output {
     /// The amount of this output.
@@ -52,6 +52,9 @@ pub struct Transaction {
     pub output: Vec<Output>,
     /// Authorization of this transaction by the owners of the inputs.
     pub authorization: Vec<Authorization>,
+    /// The multisig locks of the inputs that are locked to one.
+    #[serde(default)]
+    pub multisig: Vec<Multisig>,
     #[serde(skip)]
     pub hash: RefCell<Option<H256>>,
 }
@@ -62,6 +65,124 @@ impl PayloadSize for Transaction {
         self.input.len() * std::mem::size_of::<Input>()
             + self.output.len() * std::mem::size_of::<Output>()
             + self.authorization.len() * std::mem::size_of::<Authorization>()
+            + self.multisig.len() * std::mem::size_of::<Multisig>()
+    }
+}
+
+impl Transaction {
+    /// Create the coinbase of a block, which has no inputs and pays the block reward to the miner.
+    /// It enters the UTXO set under the hash of the block, not its own.
+    pub fn coinbase(miner: Address, reward: u64) -> Self {
+        Self {
+            input: vec![],
+            output: vec![Output {
+                value: reward,
+                recipient: miner,
+            }],
+            authorization: vec![],
+            multisig: vec![],
+            hash: RefCell::new(None),
+        }
+    }
+
+    /// Get the total value of the inputs, or `None` if it overflows.
+    pub fn input_value(&self) -> Option<u64> {
+        self.input
+            .iter()
+            .try_fold(0u64, |sum, i| sum.checked_add(i.value))
+    }
+
+    /// Get the total value of the outputs, or `None` if it overflows.
+    pub fn output_value(&self) -> Option<u64> {
+        self.output
+            .iter()
+            .try_fold(0u64, |sum, o| sum.checked_add(o.value))
+    }
+
+    /// Get the fee, which is what the inputs are worth more than the outputs, or `None` if the
+    /// value of the inputs or the outputs overflows.
+    pub fn fee(&self) -> Option<u64> {
+        Some(self.input_value()?.saturating_sub(self.output_value()?))
+    }
+
+    /// Get the coin that pays the fee to the miner of the block that carries the transaction into
+    /// the ledger, which comes after the outputs. `hash` is the hash of the transaction.
+    pub fn fee_coin(&self, hash: H256) -> CoinId {
+        CoinId {
+            hash,
+            index: self.output.len() as u32,
+        }
+    }
+
+    /// Get the fee per 1000 bytes of the serialized transaction, which is what miners favor.
+    pub fn fee_rate(&self) -> u64 {
+        let size = bincode::serialized_size(self).unwrap().max(1);
+        self.fee().unwrap_or(0).saturating_mul(1000) / size
+    }
+
+    /// Get the addresses of the public keys that sign the transaction.
+    pub fn signers(&self) -> HashSet<Address> {
+        self.authorization
+            .iter()
+            .map(|a| ring::digest::digest(&ring::digest::SHA256, &a.pubkey).into())
+            .collect()
+    }
+
+    /// Get the addresses whose coins the authorization can spend: the signers, and the multisig
+    /// locks that enough of the signers sign.
+    pub fn authorized_addresses(&self) -> HashSet<Address> {
+        let signers = self.signers();
+        let mut authorized = signers.clone();
+        for lock in &self.multisig {
+            if lock.is_satisfied_by(&signers) {
+                authorized.insert(lock.address());
+            }
+        }
+        authorized
+    }
+
+    /// Check that the authorization is exactly what it takes to spend the coins of `owners`, with
+    /// no signatures or multisig locks for anything else.
+    pub fn is_authorized_exactly(&self, owners: &HashSet<Address>) -> bool {
+        let signers = self.signers();
+        let mut needed: HashSet<Address> = HashSet::new();
+        for owner in owners {
+            if signers.contains(owner) {
+                needed.insert(*owner);
+                continue;
+            }
+            match self.multisig.iter().find(|m| m.address() == *owner) {
+                Some(lock) if lock.is_satisfied_by(&signers) => {
+                    needed.extend(lock.members().intersection(&signers));
+                }
+                _ => return false,
+            }
+        }
+        needed == signers && self.multisig.iter().all(|m| owners.contains(&m.address()))
+    }
+
+    /// Merge the signatures of two copies of a transaction that different owners signed, or
+    /// return `None` if they are not copies of the same transaction.
+    pub fn combine(&self, other: &Transaction) -> Option<Transaction> {
+        if self.input != other.input
+            || self.output != other.output
+            || self.multisig != other.multisig
+        {
+            return None;
+        }
+        let mut authorization = self.authorization.clone();
+        for a in &other.authorization {
+            if !authorization.iter().any(|b| b.pubkey == a.pubkey) {
+                authorization.push(a.clone());
+            }
+        }
+        Some(Transaction {
+            input: self.input.clone(),
+            output: self.output.clone(),
+            authorization,
+            multisig: self.multisig.clone(),
+            hash: RefCell::new(None),
+        })
     }
 }
 
@@ -89,5 +210,108 @@ pub struct Authorization {
     pub signature: Vec<u8>,
 }
 
+const MULTISIG_ADDRESS_PREFIX: &[u8] = b"prism multisig";
+
+/// A lock that takes signatures by `threshold` of `pubkeys` to spend coins. Coins are locked to it
+/// by sending them to its address, and the transactions that spend them reveal the lock.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Multisig {
+    /// The number of signatures it takes.
+    pub threshold: u32,
+    /// The public keys that may sign.
+    pub pubkeys: Vec<Vec<u8>>,
+}
+
+impl Multisig {
+    /// Create a lock, with the public keys sorted so that their order doesn't change the address.
+    pub fn new(threshold: u32, mut pubkeys: Vec<Vec<u8>>) -> Self {
+        pubkeys.sort();
+        pubkeys.dedup();
+        Self { threshold, pubkeys }
+    }
+
+    /// Get the address to send coins to to lock them. It can't collide with the address of a
+    /// public key because of the prefix.
+    pub fn address(&self) -> Address {
+        let raw = [MULTISIG_ADDRESS_PREFIX, &serialize(self).unwrap()[..]].concat();
+        ring::digest::digest(&ring::digest::SHA256, &raw).into()
+    }
+
+    /// Get the addresses of the public keys that may sign.
+    pub fn members(&self) -> HashSet<Address> {
+        self.pubkeys
+            .iter()
+            .map(|k| ring::digest::digest(&ring::digest::SHA256, k).into())
+            .collect()
+    }
+
+    /// Check whether signatures by `signers` are enough to spend the coins locked to this. A lock
+    /// with a threshold of zero or above the number of public keys can never be spent.
+    pub fn is_satisfied_by(&self, signers: &HashSet<Address>) -> bool {
+        let members = self.members();
+        let threshold = self.threshold as usize;
+        threshold > 0
+            && threshold <= members.len()
+            && members.intersection(signers).count() >= threshold
+    }
+}
+
 #[cfg(any(test))]
-pub mod tests {}
+pub mod tests {
+    use super::*;
+
+    fn key(i: u8) -> (Vec<u8>, Address) {
+        let pubkey = vec![i; 32];
+        let addr = ring::digest::digest(&ring::digest::SHA256, &pubkey).into();
+        (pubkey, addr)
+    }
+
+    fn signed_by(keys: &[u8], multisig: Vec<Multisig>) -> Transaction {
+        Transaction {
+            input: vec![],
+            output: vec![],
+            authorization: keys
+                .iter()
+                .map(|i| Authorization {
+                    pubkey: key(*i).0,
+                    signature: vec![],
+                })
+                .collect(),
+            multisig,
+            hash: RefCell::new(None),
+        }
+    }
+
+    #[test]
+    fn multisig_authorization() {
+        let lock = Multisig::new(2, vec![key(3).0, key(1).0, key(2).0]);
+        assert_eq!(
+            lock.address(),
+            Multisig::new(2, vec![key(1).0, key(2).0, key(3).0]).address()
+        );
+        let owners: HashSet<Address> = vec![lock.address()].into_iter().collect();
+        assert!(!signed_by(&[1], vec![lock.clone()]).is_authorized_exactly(&owners));
+        assert!(signed_by(&[1, 3], vec![lock.clone()]).is_authorized_exactly(&owners));
+        assert!(signed_by(&[1, 2, 3], vec![lock.clone()]).is_authorized_exactly(&owners));
+        // a signer that nothing needs
+        assert!(!signed_by(&[1, 3, 4], vec![lock.clone()]).is_authorized_exactly(&owners));
+        // the lock is not revealed
+        assert!(!signed_by(&[1, 3], vec![]).is_authorized_exactly(&owners));
+        // a single key owner next to the lock
+        let mut owners = owners;
+        owners.insert(key(4).1);
+        assert!(signed_by(&[1, 3, 4], vec![lock.clone()]).is_authorized_exactly(&owners));
+        // locks that can never be spent
+        let signers: HashSet<Address> = vec![key(1).1].into_iter().collect();
+        assert!(Multisig::new(1, vec![key(1).0]).is_satisfied_by(&signers));
+        assert!(!Multisig::new(0, vec![key(1).0]).is_satisfied_by(&signers));
+        assert!(!Multisig::new(2, vec![key(1).0]).is_satisfied_by(&signers));
+
+        let a = signed_by(&[1], vec![lock.clone()]);
+        let b = signed_by(&[3, 1], vec![lock.clone()]);
+        let combined = a.combine(&b).unwrap();
+        assert_eq!(combined.authorization.len(), 2);
+        assert!(combined.is_authorized_exactly(&[lock.address()].iter().cloned().collect()));
+        assert!(a.combine(&signed_by(&[1], vec![])).is_none());
+    }
+}
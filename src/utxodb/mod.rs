@@ -5,43 +5,239 @@ use bincode::{deserialize, serialize};
 use rocksdb::*;
 use std::collections::HashSet;
 
+const META_CF: &str = "META";
+const ADDRESS_CF: &str = "ADDRESS"; // address and coin id (Address, CoinId) to value of the coin (u64)
+const APPLIED_CF: &str = "APPLIED"; // hash of a transaction of the ledger to whether it applies (bool)
+
+// Keys in META_CF
+const CHECKPOINT_KEY: &[u8] = b"checkpoint"; // ledger tip reflected by the saved coins (u64, H256)
+const VOTER_CHECKPOINT_KEY: &[u8] = b"voter_checkpoint"; // voter ledger tips reflected by the saved coins (Vec<H256>)
+const CHECKPOINT_SEQUENCE_KEY: &[u8] = b"checkpoint_sequence"; // number of checkpoints so far (u64)
+const ADDRESS_INDEX_KEY: &[u8] = b"address_index"; // present if ADDRESS_CF is in step with the coins
+
+/// The coins that a transaction adds to the UTXO set, and those that it removes.
+pub type CoinDiff = (Vec<(CoinId, Output)>, Vec<CoinId>);
+
+/// Tuning of the RocksDB instance that stores the UTXO set.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tuning {
+    /// Number of buckets of the hash skip list memtable.
+    pub memtable_buckets: usize,
+    /// Size of the block cache in MB.
+    pub block_cache_mb: u64,
+    /// Number of background threads for flushes and compactions.
+    pub parallelism: i32,
+    /// Maximum number of memtable flushes running at the same time.
+    pub max_background_flushes: i32,
+    /// Maximum number of memtables, including those waiting to be flushed.
+    pub max_write_buffer_number: i32,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            memtable_buckets: 1 << 20,
+            block_cache_mb: 512,
+            parallelism: 16,
+            max_background_flushes: 2,
+            max_write_buffer_number: 32,
+        }
+    }
+}
+
+/// Get the path of one of the two copies of the database that we save at checkpoints, which we
+/// overwrite in turn so that we always have a complete one.
+fn checkpoint_path<P: AsRef<std::path::Path>>(path: P, sequence: u64) -> std::path::PathBuf {
+    let mut checkpoint = path.as_ref().as_os_str().to_owned();
+    checkpoint.push(format!(".checkpoint{}", sequence % 2));
+    checkpoint.into()
+}
+
 pub struct UtxoDatabase {
     pub db: rocksdb::DB, // coin id to output
+    /// Whether to maintain the index of coins by address.
+    address_index: bool,
+    /// Where the database is, so that we know where to save checkpoints.
+    path: std::path::PathBuf,
 }
 
 impl UtxoDatabase {
-    /// Open the database at the given path, and create a new one if one is missing.
-    fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, rocksdb::Error> {
-        let cfs = vec![];
+    /// Open the database at the given path, and create a new one if one is missing and `create` is
+    /// set.
+    fn open<P: AsRef<std::path::Path>>(
+        path: P,
+        address_index: bool,
+        tuning: &Tuning,
+        create: bool,
+    ) -> Result<Self, rocksdb::Error> {
+        let mut address_opts = Options::default();
+        address_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(32));
+        let cfs = vec![
+            ColumnFamilyDescriptor::new(META_CF, Options::default()),
+            ColumnFamilyDescriptor::new(ADDRESS_CF, address_opts),
+            ColumnFamilyDescriptor::new(APPLIED_CF, Options::default()),
+        ];
         let mut opts = Options::default();
         opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(32));
         opts.set_allow_concurrent_memtable_write(false);
         let memtable_opts = MemtableFactory::HashSkipList {
-            bucket_count: 1 << 20,
+            bucket_count: tuning.memtable_buckets,
             height: 8,
             branching_factor: 4,
         };
         opts.set_memtable_factory(memtable_opts);
         // https://github.com/facebook/rocksdb/blob/671d15cbdd3839acb54cb21a2aa82efca4917155/options/options.cc#L509
-        opts.optimize_for_point_lookup(512);
-        opts.create_if_missing(true);
+        opts.optimize_for_point_lookup(tuning.block_cache_mb);
+        opts.create_if_missing(create);
         opts.create_missing_column_families(true);
-        opts.increase_parallelism(16);
-        opts.set_max_background_flushes(2);
-        opts.set_max_write_buffer_number(32);
+        opts.increase_parallelism(tuning.parallelism);
+        opts.set_max_background_flushes(tuning.max_background_flushes);
+        opts.set_max_write_buffer_number(tuning.max_write_buffer_number);
 
-        let db = DB::open_cf_descriptors(&opts, path, cfs)?;
-        Ok(Self { db })
+        let db = DB::open_cf_descriptors(&opts, &path, cfs)?;
+        Ok(Self {
+            db,
+            address_index,
+            path: path.as_ref().to_path_buf(),
+        })
     }
 
-    /// Create a new database at the given path, and initialize the content.
-    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self, rocksdb::Error> {
+    /// Create a new database at the given path, and initialize the content. If `address_index` is
+    /// set, the coins are also indexed by address.
+    pub fn new<P: AsRef<std::path::Path>>(
+        path: P,
+        address_index: bool,
+        tuning: &Tuning,
+    ) -> Result<Self, rocksdb::Error> {
         DB::destroy(&Options::default(), &path)?;
-        let db = Self::open(&path)?;
+        for sequence in 0..2 {
+            DB::destroy(&Options::default(), checkpoint_path(&path, sequence))?;
+        }
+        let db = Self::open(&path, address_index, tuning, true)?;
+        if address_index {
+            let meta_cf = db.db.cf_handle(META_CF).unwrap();
+            db.db.put_cf(meta_cf, ADDRESS_INDEX_KEY, b"")?;
+        }
 
         Ok(db)
     }
 
+    /// Load an existing database at the given path, as of its last checkpoint. If `address_index`
+    /// is set and the database was not indexing coins by address, the index is built from the
+    /// coins.
+    pub fn load<P: AsRef<std::path::Path>>(
+        path: P,
+        address_index: bool,
+        tuning: &Tuning,
+    ) -> Result<Self, rocksdb::Error> {
+        // the coins are written without WAL, so what made it to the disk since the last
+        // checkpoint may be any mix of the coins before and after it. start over from the copy
+        // saved at the checkpoint instead. a copy that we can't open is incomplete
+        let mut latest: Option<(u64, std::path::PathBuf)> = None;
+        for sequence in 0..2 {
+            let checkpoint = checkpoint_path(&path, sequence);
+            if let Ok(saved) = Self::open(&checkpoint, address_index, tuning, false) {
+                let sequence = saved.checkpoint_sequence()?;
+                match latest {
+                    Some((s, _)) if s >= sequence => {}
+                    _ => latest = Some((sequence, checkpoint)),
+                }
+            }
+        }
+        if let Some((_, checkpoint)) = latest {
+            let saved = Self::open(&checkpoint, address_index, tuning, false)?;
+            DB::destroy(&Options::default(), &path)?;
+            rocksdb::checkpoint::Checkpoint::new(&saved.db)?.create_checkpoint(&path)?;
+        }
+
+        let db = Self::open(&path, address_index, tuning, true)?;
+        let meta_cf = db.db.cf_handle(META_CF).unwrap();
+        let indexed = db.db.get_pinned_cf(meta_cf, ADDRESS_INDEX_KEY)?.is_some();
+        if address_index && !indexed {
+            db.rebuild_address_index()?;
+            db.db.put_cf(meta_cf, ADDRESS_INDEX_KEY, b"")?;
+        } else if !address_index && indexed {
+            // the index goes stale from now on
+            db.db.delete_cf(meta_cf, ADDRESS_INDEX_KEY)?;
+        }
+        Ok(db)
+    }
+
+    /// Clear the index of coins by address, and fill it from the coins.
+    fn rebuild_address_index(&self) -> Result<(), rocksdb::Error> {
+        let address_cf = self.db.cf_handle(ADDRESS_CF).unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut iter_opt = rocksdb::ReadOptions::default();
+        iter_opt.set_total_order_seek(true);
+        for (k, _) in
+            self.db
+                .iterator_cf_opt(address_cf, &iter_opt, rocksdb::IteratorMode::Start)?
+        {
+            batch.delete_cf(address_cf, k)?;
+        }
+        for (coin, output) in self.coins() {
+            batch.put_cf(
+                address_cf,
+                serialize(&(output.recipient, coin)).unwrap(),
+                serialize(&output.value).unwrap(),
+            )?;
+        }
+        self.db.write(batch)?;
+        self.flush()
+    }
+
+    /// Whether the coins are indexed by address.
+    pub fn has_address_index(&self) -> bool {
+        self.address_index
+    }
+
+    /// Get up to `limit` coins owned by the given address, skipping the first `skip` ones. Only
+    /// available if the coins are indexed by address.
+    pub fn address_coins(
+        &self,
+        address: &Address,
+        skip: usize,
+        limit: usize,
+    ) -> Result<Vec<(CoinId, u64)>, rocksdb::Error> {
+        assert!(self.address_index, "coins are not indexed by address");
+        let address_cf = self.db.cf_handle(ADDRESS_CF).unwrap();
+        let prefix = serialize(address).unwrap();
+        let mut coins = vec![];
+        for (k, v) in self
+            .db
+            .prefix_iterator_cf(address_cf, &prefix)?
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .skip(skip)
+            .take(limit)
+        {
+            let (_, coin): (Address, CoinId) = deserialize(k.as_ref()).unwrap();
+            let value: u64 = deserialize(v.as_ref()).unwrap();
+            coins.push((coin, value));
+        }
+        Ok(coins)
+    }
+
+    /// Get the number of coins owned by the given address and their total value. Only available
+    /// if the coins are indexed by address.
+    pub fn address_balance(&self, address: &Address) -> Result<(usize, u64), rocksdb::Error> {
+        assert!(self.address_index, "coins are not indexed by address");
+        let address_cf = self.db.cf_handle(ADDRESS_CF).unwrap();
+        let prefix = serialize(address).unwrap();
+        let mut count = 0;
+        let mut balance = 0;
+        for (_, v) in self
+            .db
+            .prefix_iterator_cf(address_cf, &prefix)?
+            .take_while(|(k, _)| k.starts_with(&prefix))
+        {
+            let value: u64 = deserialize(v.as_ref()).unwrap();
+            count += 1;
+            balance += value;
+        }
+        Ok((count, balance))
+    }
+
     /// Check whether the given coin is in the UTXO set.
     pub fn contains(&self, coin: &CoinId) -> Result<bool, rocksdb::Error> {
         let result = self.db.get_pinned(serialize(&coin).unwrap())?;
@@ -51,6 +247,14 @@ impl UtxoDatabase {
         }
     }
 
+    /// Get the coin with the given identifier, or `None` if it is not in the UTXO set.
+    pub fn get(&self, coin: &CoinId) -> Result<Option<Output>, rocksdb::Error> {
+        match self.db.get_pinned(serialize(&coin).unwrap())? {
+            Some(d) => Ok(Some(deserialize(&d).unwrap())),
+            None => Ok(None),
+        }
+    }
+
     pub fn snapshot(&self) -> Result<Vec<u8>, rocksdb::Error> {
         let mut iter_opt = rocksdb::ReadOptions::default();
         iter_opt.set_prefix_same_as_start(false);
@@ -74,16 +278,28 @@ impl UtxoDatabase {
         Ok(checksum)
     }
 
+    /// Apply a transaction of the ledger, and pay its fee to `miner`, the miner of the block that
+    /// carries it. Returns the added and removed coins, which are empty if the transaction does not
+    /// apply. Either way, we record whether it applies along with the coins.
     pub fn add_transaction(
         &self,
         t: &Transaction,
         hash: H256,
-    ) -> Result<(Vec<(CoinId, Output)>, Vec<CoinId>), rocksdb::Error> {
+        miner: &Address,
+    ) -> Result<CoinDiff, rocksdb::Error> {
         let mut added_coins: Vec<(CoinId, Output)> = vec![];
         let mut removed_coins: Vec<CoinId> = vec![];
 
+        // validation rejects values that overflow, but never mint coins out of them anyway
+        let fee = match t.fee() {
+            Some(fee) => fee,
+            None => return self.reject_transaction(hash),
+        };
+
         // use batch for the transaction
         let mut batch = rocksdb::WriteBatch::default();
+        let address_cf = self.db.cf_handle(ADDRESS_CF).unwrap();
+        let applied_cf = self.db.cf_handle(APPLIED_CF).unwrap();
 
         // check whether the inputs used in this transaction are all unspent, and whether the value
         // field in inputs are correct, and whether all owners have signed the transaction
@@ -95,21 +311,22 @@ impl UtxoDatabase {
                     let coin_data: Output = deserialize(&d).unwrap();
                     owners.insert(coin_data.recipient);
                     if coin_data.value != input.value {
-                        return Ok((vec![], vec![]));
+                        return self.reject_transaction(hash);
+                    }
+                    if self.address_index {
+                        batch.delete_cf(
+                            address_cf,
+                            serialize(&(coin_data.recipient, input.coin)).unwrap(),
+                        )?;
                     }
                 }
-                None => return Ok((vec![], vec![])),
+                None => return self.reject_transaction(hash),
             }
             removed_coins.push(input.coin);
             batch.delete(&id_ser)?;
         }
-        let signed_users: HashSet<Address> = t
-            .authorization
-            .iter()
-            .map(|x| ring::digest::digest(&ring::digest::SHA256, &x.pubkey).into())
-            .collect();
-        if signed_users != owners {
-            return Ok((vec![], vec![]));
+        if !t.is_authorized_exactly(&owners) {
+            return self.reject_transaction(hash);
         }
 
         // now that we have confirmed that all inputs are unspent, we will add the outputs and
@@ -120,8 +337,36 @@ impl UtxoDatabase {
                 index: idx as u32,
             };
             batch.put(serialize(&id).unwrap(), serialize(&output).unwrap())?;
+            if self.address_index {
+                batch.put_cf(
+                    address_cf,
+                    serialize(&(output.recipient, id)).unwrap(),
+                    serialize(&output.value).unwrap(),
+                )?;
+            }
             added_coins.push((id, *output));
         }
+        if fee > 0 {
+            let id = t.fee_coin(hash);
+            let output = Output {
+                value: fee,
+                recipient: *miner,
+            };
+            batch.put(serialize(&id).unwrap(), serialize(&output).unwrap())?;
+            if self.address_index {
+                batch.put_cf(
+                    address_cf,
+                    serialize(&(output.recipient, id)).unwrap(),
+                    serialize(&output.value).unwrap(),
+                )?;
+            }
+            added_coins.push((id, output));
+        }
+        batch.put_cf(
+            applied_cf,
+            serialize(&hash).unwrap(),
+            serialize(&true).unwrap(),
+        )?;
         // write the transaction as a batch
         // TODO: we don't write to wal here, so should the program crash, the db will be in
         // an inconsistent state. The solution here is to manually flush the memtable to
@@ -135,31 +380,67 @@ impl UtxoDatabase {
         Ok((added_coins, removed_coins))
     }
 
+    /// Note that a transaction of the ledger does not apply, unless another copy of it in the
+    /// ledger already did.
+    fn reject_transaction(&self, hash: H256) -> Result<CoinDiff, rocksdb::Error> {
+        let applied_cf = self.db.cf_handle(APPLIED_CF).unwrap();
+        let key = serialize(&hash).unwrap();
+        if self.db.get_pinned_cf(applied_cf, &key)?.is_none() {
+            let mut batch = rocksdb::WriteBatch::default();
+            batch.put_cf(applied_cf, &key, serialize(&false).unwrap())?;
+            self.db.write_without_wal(batch)?;
+        }
+        Ok((vec![], vec![]))
+    }
+
     pub fn remove_transaction(
         &self,
         t: &Transaction,
         hash: H256,
-    ) -> Result<(Vec<(CoinId, Output)>, Vec<CoinId>), rocksdb::Error> {
+    ) -> Result<CoinDiff, rocksdb::Error> {
         let mut added_coins: Vec<(CoinId, Output)> = vec![];
         let mut removed_coins: Vec<CoinId> = vec![];
 
         // use batch when committing
         let mut batch = rocksdb::WriteBatch::default();
+        let address_cf = self.db.cf_handle(ADDRESS_CF).unwrap();
+        let applied_cf = self.db.cf_handle(APPLIED_CF).unwrap();
+        batch.delete_cf(applied_cf, serialize(&hash).unwrap())?;
 
         // check whether the outputs of this transaction are there. if so, this transaction was
         // valid when it was originally added
-        for (idx, _out) in t.output.iter().enumerate() {
+        for (idx, out) in t.output.iter().enumerate() {
             let id = CoinId {
                 hash,
                 index: idx as u32,
             };
             let id_ser = serialize(&id).unwrap();
             if self.db.get_pinned(&id_ser)?.is_none() {
+                // it did not apply, but still leaves the ledger
+                let mut batch = rocksdb::WriteBatch::default();
+                batch.delete_cf(applied_cf, serialize(&hash).unwrap())?;
+                self.db.write_without_wal(batch)?;
                 return Ok((vec![], vec![]));
             }
             batch.delete(&id_ser)?;
+            if self.address_index {
+                batch.delete_cf(address_cf, serialize(&(out.recipient, id)).unwrap())?;
+            }
             removed_coins.push(id);
         }
+        let fee_coin = t.fee_coin(hash);
+        let fee_coin_ser = serialize(&fee_coin).unwrap();
+        if let Some(d) = self.db.get_pinned(&fee_coin_ser)? {
+            let fee_output: Output = deserialize(&d).unwrap();
+            batch.delete(&fee_coin_ser)?;
+            if self.address_index {
+                batch.delete_cf(
+                    address_cf,
+                    serialize(&(fee_output.recipient, fee_coin)).unwrap(),
+                )?;
+            }
+            removed_coins.push(fee_coin);
+        }
 
         // now that we have checked that this transaction was valid when originally added, we will
         // add back the input and commit to database
@@ -169,6 +450,13 @@ impl UtxoDatabase {
                 recipient: input.owner,
             };
             batch.put(serialize(&input.coin).unwrap(), serialize(&out).unwrap())?;
+            if self.address_index {
+                batch.put_cf(
+                    address_cf,
+                    serialize(&(input.owner, input.coin)).unwrap(),
+                    serialize(&input.value).unwrap(),
+                )?;
+            }
             added_coins.push((input.coin, out));
         }
         // write the transaction as a batch
@@ -190,7 +478,254 @@ impl UtxoDatabase {
         flush_opt.set_wait(true);
         self.db.flush_opt(&flush_opt)
     }
+
+    /// Record that the coins reflect the ledger up to the given proposer level and leader, and
+    /// the rewards of the voter blocks up to the given voter ledger tips, and save a copy of the
+    /// database to load after a crash. All transactions and rewards up to there must have been
+    /// applied, and none may be applied until this returns.
+    pub fn set_checkpoint(
+        &self,
+        level: u64,
+        leader: H256,
+        voter_tips: &[H256],
+    ) -> Result<(), rocksdb::Error> {
+        let sequence = self.checkpoint_sequence()? + 1;
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put_cf(
+            meta_cf,
+            CHECKPOINT_SEQUENCE_KEY,
+            serialize(&sequence).unwrap(),
+        )?;
+        batch.put_cf(
+            meta_cf,
+            CHECKPOINT_KEY,
+            serialize(&(level, leader)).unwrap(),
+        )?;
+        batch.put_cf(
+            meta_cf,
+            VOTER_CHECKPOINT_KEY,
+            serialize(voter_tips).unwrap(),
+        )?;
+        self.db.write(batch)?;
+        // overwrite the older copy, so that we still have the newer one should we crash while
+        // saving. the copy shares the flushed files with the database
+        let checkpoint = checkpoint_path(&self.path, sequence);
+        DB::destroy(&Options::default(), &checkpoint)?;
+        rocksdb::checkpoint::Checkpoint::new(&self.db)?.create_checkpoint(&checkpoint)
+    }
+
+    /// Get the number of checkpoints so far.
+    fn checkpoint_sequence(&self) -> Result<u64, rocksdb::Error> {
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        match self.db.get_pinned_cf(meta_cf, CHECKPOINT_SEQUENCE_KEY)? {
+            Some(d) => Ok(deserialize(&d).unwrap()),
+            None => Ok(0),
+        }
+    }
+
+    /// Get the proposer level and leader of the last checkpoint.
+    pub fn checkpoint(&self) -> Result<Option<(u64, H256)>, rocksdb::Error> {
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        match self.db.get_pinned_cf(meta_cf, CHECKPOINT_KEY)? {
+            Some(d) => Ok(Some(deserialize(&d).unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the voter ledger tips of the last checkpoint.
+    pub fn voter_checkpoint(&self) -> Result<Option<Vec<H256>>, rocksdb::Error> {
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        match self.db.get_pinned_cf(meta_cf, VOTER_CHECKPOINT_KEY)? {
+            Some(d) => Ok(Some(deserialize(&d).unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    /// Check whether a transaction of the ledger applies to the UTXO set, or get `None` if it is
+    /// not in the ledger or we have not tried to apply it yet.
+    pub fn applied(&self, hash: &H256) -> Result<Option<bool>, rocksdb::Error> {
+        let applied_cf = self.db.cf_handle(APPLIED_CF).unwrap();
+        match self
+            .db
+            .get_pinned_cf(applied_cf, serialize(hash).unwrap())?
+        {
+            Some(d) => Ok(Some(deserialize(&d).unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    /// Iterate over all coins in the UTXO set.
+    pub fn coins(&self) -> impl Iterator<Item = (CoinId, Output)> + '_ {
+        let mut iter_opt = rocksdb::ReadOptions::default();
+        iter_opt.set_prefix_same_as_start(false);
+        iter_opt.set_total_order_seek(true);
+        self.db
+            .iterator_opt(rocksdb::IteratorMode::Start, &iter_opt)
+            .map(|(k, v)| {
+                (
+                    deserialize(k.as_ref()).unwrap(),
+                    deserialize(v.as_ref()).unwrap(),
+                )
+            })
+    }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+    use crate::crypto::hash::Hashable;
+    use crate::transaction::{Authorization, Input};
+
+    #[test]
+    fn overflowing_fee() {
+        let db = UtxoDatabase::new(
+            "/tmp/prism_test_utxodb_overflowing_fee.rocksdb",
+            false,
+            &Tuning::default(),
+        )
+        .unwrap();
+        let pubkey = vec![1u8; 32];
+        let owner: Address = ring::digest::digest(&ring::digest::SHA256, &pubkey).into();
+        let miner: Address = [9u8; 32].into();
+        let (added, _) = db
+            .add_transaction(&Transaction::coinbase(owner, 1), [5u8; 32].into(), &miner)
+            .unwrap();
+        let coin = added[0].0;
+
+        // the outputs wrap around to a total of 1, which would leave a fee of 0
+        let output = |value| Output {
+            value,
+            recipient: miner,
+        };
+        let spend = Transaction {
+            input: vec![Input {
+                coin,
+                value: 1,
+                owner,
+            }],
+            output: vec![output(u64::MAX), output(2)],
+            authorization: vec![Authorization {
+                pubkey,
+                signature: vec![],
+            }],
+            multisig: vec![],
+            hash: Default::default(),
+        };
+        assert_eq!(spend.fee(), None);
+        let (added, removed) = db.add_transaction(&spend, spend.hash(), &miner).unwrap();
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+        assert!(db.get(&coin).unwrap().is_some());
+    }
+
+    #[test]
+    fn address_index() {
+        let path = "/tmp/prism_test_utxodb_address_index.rocksdb";
+        let db = UtxoDatabase::new(path, true, &Tuning::default()).unwrap();
+        let pubkey = vec![1u8; 32];
+        let alice: Address = ring::digest::digest(&ring::digest::SHA256, &pubkey).into();
+        let bob: Address = [2u8; 32].into();
+        let miner: Address = [9u8; 32].into();
+        let coinbase = Transaction::coinbase(alice, 5);
+        let (added, _) = db
+            .add_transaction(&coinbase, coinbase.hash(), &miner)
+            .unwrap();
+        let coin = added[0].0;
+        assert_eq!(db.address_balance(&alice).unwrap(), (1, 5));
+        assert_eq!(db.address_coins(&alice, 0, 10).unwrap(), vec![(coin, 5)]);
+
+        // pay 3 to bob and get 2 back
+        let spend = Transaction {
+            input: vec![Input {
+                coin,
+                value: 5,
+                owner: alice,
+            }],
+            output: vec![
+                Output {
+                    value: 3,
+                    recipient: bob,
+                },
+                Output {
+                    value: 2,
+                    recipient: alice,
+                },
+            ],
+            authorization: vec![Authorization {
+                pubkey,
+                signature: vec![],
+            }],
+            multisig: vec![],
+            hash: Default::default(),
+        };
+        let (added, removed) = db.add_transaction(&spend, spend.hash(), &miner).unwrap();
+        assert_eq!(removed, vec![coin]);
+        assert_eq!(added.len(), 2);
+        assert_eq!(db.address_balance(&alice).unwrap(), (1, 2));
+        assert_eq!(db.address_balance(&bob).unwrap(), (1, 3));
+        assert_eq!(
+            db.address_coins(&bob, 0, 10).unwrap(),
+            vec![(added[0].0, 3)]
+        );
+        assert!(db.address_coins(&alice, 1, 10).unwrap().is_empty());
+
+        // the spend leaves the ledger
+        db.remove_transaction(&spend, spend.hash()).unwrap();
+        assert_eq!(db.address_balance(&alice).unwrap(), (1, 5));
+        assert_eq!(db.address_balance(&bob).unwrap(), (0, 0));
+        assert_eq!(db.address_coins(&alice, 0, 10).unwrap(), vec![(coin, 5)]);
+
+        // the index is dropped and built again from the coins
+        db.add_transaction(&spend, spend.hash(), &miner).unwrap();
+        drop(db);
+        drop(UtxoDatabase::load(path, false, &Tuning::default()).unwrap());
+        let db = UtxoDatabase::load(path, true, &Tuning::default()).unwrap();
+        assert_eq!(db.address_balance(&alice).unwrap(), (1, 2));
+        assert_eq!(db.address_balance(&bob).unwrap(), (1, 3));
+    }
+
+    #[test]
+    fn load_checkpoint() {
+        let path = "/tmp/prism_test_utxodb_load_checkpoint.rocksdb";
+        let owner: Address = [1u8; 32].into();
+        let miner: Address = [9u8; 32].into();
+        let coinbase = |value| {
+            let transaction = Transaction::coinbase(owner, value);
+            let hash = transaction.hash();
+            (transaction, hash)
+        };
+        let (first, first_hash) = coinbase(1);
+        let (second, second_hash) = coinbase(2);
+        let (third, third_hash) = coinbase(3);
+        let leader: H256 = [5u8; 32].into();
+        let voter_tips: Vec<H256> = vec![[6u8; 32].into(), [7u8; 32].into()];
+
+        let db = UtxoDatabase::new(path, false, &Tuning::default()).unwrap();
+        let (first_coins, _) = db.add_transaction(&first, first_hash, &miner).unwrap();
+        db.set_checkpoint(1, leader, &voter_tips).unwrap();
+        let (second_coins, _) = db.add_transaction(&second, second_hash, &miner).unwrap();
+        drop(db);
+
+        // the coin added after the checkpoint is gone, and replaying it brings it back
+        let db = UtxoDatabase::load(path, false, &Tuning::default()).unwrap();
+        assert_eq!(db.checkpoint().unwrap(), Some((1, leader)));
+        assert_eq!(db.voter_checkpoint().unwrap(), Some(voter_tips.clone()));
+        assert!(db.contains(&first_coins[0].0).unwrap());
+        assert!(!db.contains(&second_coins[0].0).unwrap());
+        assert_eq!(db.applied(&second_hash).unwrap(), None);
+        let (replayed, _) = db.add_transaction(&second, second_hash, &miner).unwrap();
+        assert_eq!(replayed, second_coins);
+        assert_eq!(db.applied(&second_hash).unwrap(), Some(true));
+
+        // the next checkpoint goes to the other copy, and is the one that we load
+        db.set_checkpoint(2, leader, &voter_tips).unwrap();
+        let (third_coins, _) = db.add_transaction(&third, third_hash, &miner).unwrap();
+        drop(db);
+        let db = UtxoDatabase::load(path, false, &Tuning::default()).unwrap();
+        assert_eq!(db.checkpoint().unwrap(), Some((2, leader)));
+        assert!(db.contains(&first_coins[0].0).unwrap());
+        assert!(db.contains(&second_coins[0].0).unwrap());
+        assert!(!db.contains(&third_coins[0].0).unwrap());
+    }
+}
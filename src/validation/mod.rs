@@ -1,14 +1,23 @@
 mod proposer_block;
 mod transaction;
 mod voter_block;
-use crate::block::{Block, Content};
+use crate::block::header::Header;
+use crate::block::{Block, Content, SortitionProof};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::config::*;
-use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::hash::H256;
 use crate::crypto::merkle::verify;
+use crate::transaction::{CoinId, Transaction};
+use crate::utxodb::UtxoDatabase;
+use std::time::SystemTime;
 extern crate bigint;
 
+/// Number of latest proposer ancestors whose median timestamp a block must be later than.
+const MEDIAN_TIME_BLOCKS: usize = 11;
+/// How far (in milliseconds) the timestamp of a block may be ahead of our clock.
+const MAX_FUTURE_BLOCK_TIME: u128 = 2 * 60 * 1000;
+
 /// The result of block validation.
 #[derive(Debug)]
 pub enum BlockResult {
@@ -16,6 +25,12 @@ pub enum BlockResult {
     Pass,
     /// The PoW doesn't pass.
     WrongPoW,
+    /// The difficulty doesn't follow the retargeting rule.
+    WrongDifficulty,
+    /// The timestamp is not later than the median timestamp of the latest proposer ancestors.
+    WrongTimestamp,
+    /// The timestamp is too far ahead of our clock. The block may become valid later.
+    FutureTimestamp,
     /// The sortition id and content type doesn't match.
     WrongSortitionId,
     /// The content Merkle proof is incorrect.
@@ -30,6 +45,7 @@ pub enum BlockResult {
     WrongVoteLevel,
     EmptyTransaction,
     ZeroValue,
+    ValueOverflow,
     InsufficientInput,
     WrongSignature,
 }
@@ -39,6 +55,14 @@ impl std::fmt::Display for BlockResult {
         match self {
             BlockResult::Pass => write!(f, "validation passed"),
             BlockResult::WrongPoW => write!(f, "PoW larger than difficulty"),
+            BlockResult::WrongDifficulty => write!(f, "difficulty not retargeted correctly"),
+            BlockResult::WrongTimestamp => {
+                write!(
+                    f,
+                    "timestamp not later than the median of proposer ancestors"
+                )
+            }
+            BlockResult::FutureTimestamp => write!(f, "timestamp too far in the future"),
             BlockResult::WrongSortitionId => write!(f, "Sortition id is not same as content type"),
             BlockResult::WrongSortitionProof => write!(f, "Sortition Merkle proof is incorrect"),
             BlockResult::MissingReferences(_) => write!(f, "referred blocks not in system"),
@@ -51,21 +75,167 @@ impl std::fmt::Display for BlockResult {
             BlockResult::ZeroValue => {
                 write!(f, "transaction input or output value contains a zero")
             }
+            BlockResult::ValueOverflow => {
+                write!(f, "transaction input or output value overflows")
+            }
             BlockResult::InsufficientInput => write!(f, "insufficient input"),
             BlockResult::WrongSignature => write!(f, "signature mismatch"),
         }
     }
 }
 
+/// The reason why a transaction is not admitted into the memory pool.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum TransactionRejection {
+    /// The input or the output is empty.
+    EmptyTransaction,
+    /// An input or output has zero value.
+    ZeroValue,
+    /// The outputs are worth more than the inputs.
+    InsufficientInput,
+    /// A signature does not verify.
+    WrongSignature,
+    /// An input is not signed by its owner.
+    UnauthorizedInput(CoinId),
+    /// An input coin is not in the UTXO set.
+    MissingInput(CoinId),
+    /// The value or the owner of an input does not match the coin in the UTXO set.
+    WrongInput(CoinId),
+    /// The transaction is already in the memory pool.
+    Duplicate,
+    /// An input is already spent by a transaction in the memory pool.
+    DoubleSpend(CoinId),
+    /// The memory pool is full.
+    MempoolFull,
+    /// The total value of the inputs or the outputs overflows.
+    ValueOverflow,
+}
+
+impl TransactionRejection {
+    /// Whether the transaction is invalid no matter what the ledger state is, i.e. whoever sends
+    /// it to us is at fault.
+    pub fn is_invalid(&self) -> bool {
+        match self {
+            TransactionRejection::EmptyTransaction
+            | TransactionRejection::ZeroValue
+            | TransactionRejection::InsufficientInput
+            | TransactionRejection::WrongSignature
+            | TransactionRejection::UnauthorizedInput(_)
+            | TransactionRejection::WrongInput(_)
+            | TransactionRejection::ValueOverflow => true,
+            TransactionRejection::MissingInput(_)
+            | TransactionRejection::Duplicate
+            | TransactionRejection::DoubleSpend(_)
+            | TransactionRejection::MempoolFull => false,
+        }
+    }
+}
+
+impl std::fmt::Display for TransactionRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransactionRejection::EmptyTransaction => {
+                write!(f, "empty transaction input or output")
+            }
+            TransactionRejection::ZeroValue => {
+                write!(f, "transaction input or output value contains a zero")
+            }
+            TransactionRejection::InsufficientInput => write!(f, "insufficient input"),
+            TransactionRejection::WrongSignature => write!(f, "signature mismatch"),
+            TransactionRejection::UnauthorizedInput(c) => {
+                write!(f, "input {:.8}:{} not signed by its owner", c.hash, c.index)
+            }
+            TransactionRejection::MissingInput(c) => {
+                write!(f, "input {:.8}:{} not in the UTXO set", c.hash, c.index)
+            }
+            TransactionRejection::WrongInput(c) => write!(
+                f,
+                "value or owner of input {:.8}:{} does not match the UTXO set",
+                c.hash, c.index
+            ),
+            TransactionRejection::Duplicate => write!(f, "already in the memory pool"),
+            TransactionRejection::DoubleSpend(c) => write!(
+                f,
+                "input {:.8}:{} already spent in the memory pool",
+                c.hash, c.index
+            ),
+            TransactionRejection::MempoolFull => write!(f, "memory pool is full"),
+            TransactionRejection::ValueOverflow => {
+                write!(f, "transaction input or output value overflows")
+            }
+        }
+    }
+}
+
+/// Check the transactions that don't need the ledger state, and return the result for each of
+/// them. The signatures are verified in one batch.
+pub fn check_transactions_stateless(
+    transactions: &[Transaction],
+) -> Vec<Result<(), TransactionRejection>> {
+    let mut results: Vec<Result<(), TransactionRejection>> = transactions
+        .iter()
+        .map(|t| {
+            if !transaction::check_non_empty(t) {
+                Err(TransactionRejection::EmptyTransaction)
+            } else if !transaction::check_non_zero(t) {
+                Err(TransactionRejection::ZeroValue)
+            } else if !transaction::check_no_overflow(t) {
+                Err(TransactionRejection::ValueOverflow)
+            } else if !transaction::check_sufficient_input(t) {
+                Err(TransactionRejection::InsufficientInput)
+            } else if let Some(coin) = transaction::unauthorized_input(t) {
+                Err(TransactionRejection::UnauthorizedInput(coin))
+            } else {
+                Ok(())
+            }
+        })
+        .collect();
+    // verify the signatures in one batch, and only look for the bad ones if the batch fails
+    let passed: Vec<usize> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| match r {
+            Ok(()) => Some(i),
+            Err(_) => None,
+        })
+        .collect();
+    let batch: Vec<Transaction> = passed.iter().map(|i| transactions[*i].clone()).collect();
+    if !transaction::check_signature_batch(&batch) {
+        for i in passed {
+            if !transaction::check_signature_batch(std::slice::from_ref(&transactions[i])) {
+                results[i] = Err(TransactionRejection::WrongSignature);
+            }
+        }
+    }
+    results
+}
+
+/// Check the inputs of a transaction against the UTXO set.
+pub fn check_transaction_inputs(
+    transaction: &Transaction,
+    utxodb: &UtxoDatabase,
+) -> Result<(), TransactionRejection> {
+    for input in &transaction.input {
+        match utxodb.get(&input.coin).unwrap() {
+            None => return Err(TransactionRejection::MissingInput(input.coin)),
+            Some(output) => {
+                if output.value != input.value || output.recipient != input.owner {
+                    return Err(TransactionRejection::WrongInput(input.coin));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 // check PoW and sortition id
-pub fn check_pow_sortition_id(block: &Block, config: &BlockchainConfig) -> BlockResult {
-    let sortition_id = config.sortition_hash(&block.hash(), &block.header.difficulty);
+pub fn check_pow_sortition_id<B: SortitionProof>(
+    block: &B,
+    config: &BlockchainConfig,
+) -> BlockResult {
+    let sortition_id = config.sortition_hash(&block.hash(), &block.header().difficulty);
     if let Some(sortition_id) = sortition_id {
-        let correct_sortition_id = match &block.content {
-            Content::Proposer(_) => PROPOSER_INDEX,
-            Content::Transaction(_) => TRANSACTION_INDEX,
-            Content::Voter(content) => content.chain_number + FIRST_VOTER_INDEX,
-        };
+        let correct_sortition_id = block.sortition_id();
         if sortition_id != correct_sortition_id {
             return BlockResult::WrongSortitionId;
         }
@@ -76,13 +246,16 @@ pub fn check_pow_sortition_id(block: &Block, config: &BlockchainConfig) -> Block
 }
 
 /// check sortition proof
-pub fn check_sortition_proof(block: &Block, config: &BlockchainConfig) -> BlockResult {
-    let sortition_id = config.sortition_hash(&block.hash(), &block.header.difficulty);
+pub fn check_sortition_proof<B: SortitionProof>(
+    block: &B,
+    config: &BlockchainConfig,
+) -> BlockResult {
+    let sortition_id = config.sortition_hash(&block.hash(), &block.header().difficulty);
     if let Some(sortition_id) = sortition_id {
         if !verify(
-            &block.header.content_merkle_root,
-            &block.content.hash(),
-            &block.sortition_proof,
+            &block.header().content_merkle_root,
+            &block.content_hash(),
+            block.sortition_proof(),
             sortition_id as usize,
             (config.voter_chains + FIRST_VOTER_INDEX) as usize,
         ) {
@@ -93,6 +266,118 @@ pub fn check_sortition_proof(block: &Block, config: &BlockchainConfig) -> BlockR
     }
     BlockResult::Pass
 }
+
+/// Check that the timestamp of a block is not too far ahead of our clock. Timestamps only matter
+/// for difficulty retargeting, so they are not checked if it is off.
+pub fn check_future_timestamp<B: SortitionProof>(
+    block: &B,
+    config: &BlockchainConfig,
+) -> BlockResult {
+    if config.difficulty_interval != 0
+        && block.header().timestamp > now_millis() + MAX_FUTURE_BLOCK_TIME
+    {
+        return BlockResult::FutureTimestamp;
+    }
+    BlockResult::Pass
+}
+
+/// Check that the timestamp of a block is later than the median timestamp of its latest proposer
+/// ancestors, so that miners can't set it back. Timestamps only matter for difficulty
+/// retargeting, so they are not checked if it is off. The parent must be available.
+pub fn check_timestamp(
+    block: &Block,
+    blockdb: &BlockDatabase,
+    config: &BlockchainConfig,
+) -> BlockResult {
+    if config.difficulty_interval == 0 {
+        return BlockResult::Pass;
+    }
+    let mut ancestors = vec![];
+    let mut hash = block.header.parent;
+    while ancestors.len() < MEDIAN_TIME_BLOCKS {
+        let header = match stored_header(&hash, blockdb) {
+            Ok(header) => header,
+            Err(missing) => return missing,
+        };
+        ancestors.push(header.timestamp);
+        if hash == config.proposer_genesis {
+            break;
+        }
+        hash = header.parent;
+    }
+    if block.header.timestamp <= median_time_past(&mut ancestors) {
+        return BlockResult::WrongTimestamp;
+    }
+    BlockResult::Pass
+}
+
+/// Get the median of the given timestamps, or 0 if there are none.
+fn median_time_past(timestamps: &mut [u128]) -> u128 {
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+}
+
+/// Get the current UNIX timestamp in milliseconds.
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// Get the difficulty of the blocks whose proposer parent is the given block. It is the difficulty
+/// of the parent, except at retarget levels, where it is scaled by how long the last
+/// `difficulty_interval` proposer levels took to mine. Fails if a proposer ancestor is missing
+/// from the block database.
+///
+/// The timestamps that set the time span are bounded by `check_timestamp` and
+/// `check_future_timestamp`, and consecutive windows share their boundary block, so miners can't
+/// stretch the time span of one window by shrinking that of another.
+pub fn expected_difficulty(
+    parent: &H256,
+    blockchain: &BlockChain,
+    blockdb: &BlockDatabase,
+    config: &BlockchainConfig,
+) -> Result<H256, BlockResult> {
+    let parent_header = stored_header(parent, blockdb)?;
+    let level = blockchain.proposer_level(parent).unwrap() + 1;
+    if !config.is_retarget_level(level) {
+        return Ok(parent_header.difficulty);
+    }
+    let mut ancestor_header = parent_header;
+    for _ in 0..config.difficulty_interval {
+        ancestor_header = stored_header(&ancestor_header.parent, blockdb)?;
+    }
+    let time_span = parent_header
+        .timestamp
+        .saturating_sub(ancestor_header.timestamp);
+    Ok(config.retarget(&parent_header.difficulty, time_span))
+}
+
+/// Check that the difficulty of a block follows the retargeting rule. The parent must be
+/// available.
+pub fn check_difficulty(
+    block: &Block,
+    blockchain: &BlockChain,
+    blockdb: &BlockDatabase,
+    config: &BlockchainConfig,
+) -> BlockResult {
+    match expected_difficulty(&block.header.parent, blockchain, blockdb, config) {
+        Ok(expected) if expected == block.header.difficulty => BlockResult::Pass,
+        Ok(_) => BlockResult::WrongDifficulty,
+        Err(missing) => missing,
+    }
+}
+
+/// Get the header of a stored block, or report the block as missing.
+fn stored_header(hash: &H256, blockdb: &BlockDatabase) -> Result<Header, BlockResult> {
+    match blockdb.get(hash) {
+        Err(e) => panic!("Block database error {}", e),
+        Ok(Some(block)) => Ok(block.header),
+        Ok(None) => Err(BlockResult::MissingReferences(vec![*hash])),
+    }
+}
+
 /// Validate a block that already passes pow and sortition test. See if parents/refs are missing.
 pub fn check_data_availability(
     block: &Block,
@@ -173,6 +458,9 @@ pub fn check_content_semantic(
                 if !transaction::check_non_zero(&transaction) {
                     return BlockResult::ZeroValue;
                 }
+                if !transaction::check_no_overflow(transaction) {
+                    return BlockResult::ValueOverflow;
+                }
                 if !transaction::check_sufficient_input(&transaction) {
                     return BlockResult::InsufficientInput;
                 }
@@ -208,3 +496,66 @@ fn check_transaction_block_exists(hash: H256, blockchain: &BlockChain) -> bool {
         Ok(b) => b,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::proposer_block;
+    use crate::crypto::hash::Hashable;
+
+    #[test]
+    fn timestamp_rules() {
+        let config = Genesis {
+            voter_chains: 1,
+            difficulty_interval: 10,
+            ..Default::default()
+        }
+        .config();
+        let blockdb = BlockDatabase::new(
+            "/tmp/prism_test_validation_timestamp_rules.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+        // a proposer chain mined one block per second
+        let mut parent = config.proposer_genesis;
+        for i in 1..=20 {
+            let block = proposer_block(parent, i * 1000, vec![], vec![]);
+            blockdb.insert(&block).unwrap();
+            parent = block.hash();
+        }
+        let at = |timestamp| proposer_block(parent, timestamp, vec![], vec![]);
+
+        // the median of the latest 11 proposer blocks is 15 seconds
+        let result = check_timestamp(&at(15_001), &blockdb, &config);
+        assert!(matches!(result, BlockResult::Pass));
+        let result = check_timestamp(&at(15_000), &blockdb, &config);
+        assert!(matches!(result, BlockResult::WrongTimestamp));
+        let result = check_timestamp(&at(1), &blockdb, &config);
+        assert!(matches!(result, BlockResult::WrongTimestamp));
+
+        let now = now_millis();
+        let result = check_future_timestamp(&at(now), &config);
+        assert!(matches!(result, BlockResult::Pass));
+        let result = check_future_timestamp(&at(now + 3600 * 1000), &config);
+        assert!(matches!(result, BlockResult::FutureTimestamp));
+
+        // timestamps are not checked if the difficulty is fixed
+        let fixed = Genesis::default().config();
+        let result = check_timestamp(&at(1), &blockdb, &fixed);
+        assert!(matches!(result, BlockResult::Pass));
+        let result = check_future_timestamp(&at(now + 3600 * 1000), &fixed);
+        assert!(matches!(result, BlockResult::Pass));
+    }
+
+    #[test]
+    fn median_timestamp() {
+        assert_eq!(median_time_past(&mut []), 0);
+        assert_eq!(median_time_past(&mut [0]), 0);
+        assert_eq!(median_time_past(&mut [5, 1, 3]), 3);
+        // a few timestamps far in the past or the future don't move the median
+        assert_eq!(
+            median_time_past(&mut [10, 11, 0, 12, 1_000_000, 13, 14]),
+            12
+        );
+    }
+}
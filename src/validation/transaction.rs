@@ -1,8 +1,9 @@
-use crate::transaction::Transaction;
+use crate::transaction::{Address, CoinId, Transaction};
 
 use ed25519_dalek::PublicKey;
 use ed25519_dalek::Signature;
 
+use std::collections::HashSet;
 use std::convert::TryFrom;
 
 /// Checks that input and output are non-empty
@@ -16,11 +17,28 @@ pub fn check_non_zero(transaction: &Transaction) -> bool {
         || transaction.output.iter().any(|x| x.value == 0))
 }
 
-/// Checks if input_sum >= output_sum
+/// Checks that neither input_sum nor output_sum overflows
+pub fn check_no_overflow(transaction: &Transaction) -> bool {
+    transaction.input_value().is_some() && transaction.output_value().is_some()
+}
+
+/// Checks if input_sum >= output_sum, and fails if either overflows
 pub fn check_sufficient_input(transaction: &Transaction) -> bool {
-    let input_sum: u64 = transaction.input.iter().map(|x| x.value).sum();
-    let output_sum: u64 = transaction.output.iter().map(|x| x.value).sum();
-    input_sum >= output_sum
+    match (transaction.input_value(), transaction.output_value()) {
+        (Some(input_sum), Some(output_sum)) => input_sum >= output_sum,
+        _ => false,
+    }
+}
+
+/// Finds an input whose owner does not sign the transaction, i.e. none of the public keys in the
+/// authorization hashes to the owner address, and no multisig lock that enough of them sign does
+pub fn unauthorized_input(transaction: &Transaction) -> Option<CoinId> {
+    let authorized: HashSet<Address> = transaction.authorized_addresses();
+    transaction
+        .input
+        .iter()
+        .find(|input| !authorized.contains(&input.owner))
+        .map(|input| input.coin)
 }
 
 pub fn check_signature_batch(transactions: &[Transaction]) -> bool {
@@ -38,8 +56,17 @@ pub fn check_signature_batch(transactions: &[Transaction]) -> bool {
 
     for (idx, tx) in transactions.iter().enumerate() {
         for a in &tx.authorization {
-            public_keys.push(PublicKey::from_bytes(&a.pubkey).unwrap());
-            signatures.push(Signature::try_from(&a.signature[..]).unwrap());
+            // malformed keys or signatures come from peers, so don't panic on them
+            match (
+                PublicKey::from_bytes(&a.pubkey),
+                Signature::try_from(&a.signature[..]),
+            ) {
+                (Ok(pubkey), Ok(signature)) => {
+                    public_keys.push(pubkey);
+                    signatures.push(signature);
+                }
+                _ => return false,
+            }
             messages.push(&raw_messages[idx]);
         }
     }
@@ -50,3 +77,52 @@ pub fn check_signature_batch(transactions: &[Transaction]) -> bool {
         Err(_) => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Input, Output};
+
+    #[test]
+    fn overflowing_values() {
+        let input = Input {
+            coin: CoinId {
+                hash: [1u8; 32].into(),
+                index: 0,
+            },
+            value: 1,
+            owner: [2u8; 32].into(),
+        };
+        let output = |value| Output {
+            value,
+            recipient: [3u8; 32].into(),
+        };
+        // the outputs would wrap around to a total of 1
+        let transaction = Transaction {
+            input: vec![input],
+            output: vec![output(u64::MAX), output(2)],
+            authorization: vec![],
+            multisig: vec![],
+            hash: Default::default(),
+        };
+        assert!(!check_no_overflow(&transaction));
+        assert!(!check_sufficient_input(&transaction));
+
+        let mut inputs = transaction.clone();
+        inputs.input = vec![
+            Input {
+                value: u64::MAX,
+                ..input
+            };
+            2
+        ];
+        inputs.output = vec![output(1)];
+        assert!(!check_no_overflow(&inputs));
+        assert!(!check_sufficient_input(&inputs));
+
+        let mut valid = transaction;
+        valid.output = vec![output(1)];
+        assert!(check_no_overflow(&valid));
+        assert!(check_sufficient_input(&valid));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::transaction::{CoinId, Output};
+use std::cmp::Reverse;
+use std::str::FromStr;
+
+/// Number of steps branch and bound takes to look for a selection without change before it gives
+/// up and falls back to largest first.
+const BRANCH_AND_BOUND_MAX_TRIES: usize = 100_000;
+
+/// How the wallet picks the coins to spend in a transaction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoinSelection {
+    /// Spend coins in the order they are stored, starting from the given coin. This is the
+    /// cheapest strategy since it stops reading coins once it has enough, and it is what the
+    /// transaction generator uses.
+    InOrder(Option<CoinId>),
+    /// Spend the largest coins first, which keeps the number of inputs small.
+    LargestFirst,
+    /// Spend the smallest coins first, which consolidates dust.
+    SmallestFirst,
+    /// Look for coins that add up to the exact value so that there is no change output, and fall
+    /// back to largest first if there are none.
+    BranchAndBound,
+}
+
+impl Default for CoinSelection {
+    fn default() -> Self {
+        CoinSelection::LargestFirst
+    }
+}
+
+impl FromStr for CoinSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in-order" => Ok(CoinSelection::InOrder(None)),
+            "largest-first" => Ok(CoinSelection::LargestFirst),
+            "smallest-first" => Ok(CoinSelection::SmallestFirst),
+            "branch-and-bound" => Ok(CoinSelection::BranchAndBound),
+            s => Err(format!("invalid coin selection strategy: {}", s)),
+        }
+    }
+}
+
+/// Pick coins worth at least `value` out of `coins`, or return `None` if they are not enough.
+pub fn select(
+    mut coins: Vec<(CoinId, Output)>,
+    value: u64,
+    strategy: CoinSelection,
+) -> Option<Vec<(CoinId, Output)>> {
+    match strategy {
+        CoinSelection::InOrder(_) => {}
+        CoinSelection::LargestFirst => coins.sort_by_key(|c| Reverse(c.1.value)),
+        CoinSelection::SmallestFirst => coins.sort_by_key(|c| c.1.value),
+        CoinSelection::BranchAndBound => {
+            coins.sort_by_key(|c| Reverse(c.1.value));
+            if let Some(selected) = branch_and_bound(&coins, value) {
+                return Some(selected.iter().map(|i| coins[*i]).collect());
+            }
+        }
+    }
+    let mut selected = vec![];
+    let mut sum = 0u64;
+    for coin in coins {
+        if sum >= value {
+            break;
+        }
+        sum += coin.1.value;
+        selected.push(coin);
+    }
+    if sum >= value {
+        Some(selected)
+    } else {
+        None
+    }
+}
+
+/// Search, largest coin first, for coins that add up to exactly `value`. `coins` must be sorted
+/// from the largest to the smallest. Returns the indices of the coins.
+fn branch_and_bound(coins: &[(CoinId, Output)], value: u64) -> Option<Vec<usize>> {
+    // remaining[i] is the total value of the coins from i on
+    let mut remaining = vec![0u64; coins.len() + 1];
+    for i in (0..coins.len()).rev() {
+        remaining[i] = remaining[i + 1] + coins[i].1.value;
+    }
+    let mut selected: Vec<usize> = vec![];
+    let mut sum = 0u64;
+    // the next coin to decide on
+    let mut next = 0;
+    for _ in 0..BRANCH_AND_BOUND_MAX_TRIES {
+        if sum == value {
+            return Some(selected);
+        }
+        if sum > value || sum + remaining[next] < value {
+            // dead end, so leave out the last coin we took instead
+            let last = selected.pop()?;
+            sum -= coins[last].1.value;
+            next = last + 1;
+        } else {
+            selected.push(next);
+            sum += coins[next].1.value;
+            next += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::H256;
+
+    fn coins(values: &[u64]) -> Vec<(CoinId, Output)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let coin = CoinId {
+                    hash: H256::default(),
+                    index: i as u32,
+                };
+                let output = Output {
+                    value: *v,
+                    recipient: H256::default(),
+                };
+                (coin, output)
+            })
+            .collect()
+    }
+
+    fn values(selected: Option<Vec<(CoinId, Output)>>) -> Vec<u64> {
+        selected.unwrap().iter().map(|c| c.1.value).collect()
+    }
+
+    #[test]
+    fn strategies() {
+        let c = coins(&[5, 10, 3, 7]);
+        assert_eq!(
+            values(select(c.clone(), 9, CoinSelection::InOrder(None))),
+            vec![5, 10]
+        );
+        assert_eq!(
+            values(select(c.clone(), 9, CoinSelection::LargestFirst)),
+            vec![10]
+        );
+        assert_eq!(
+            values(select(c.clone(), 9, CoinSelection::SmallestFirst)),
+            vec![3, 5, 7]
+        );
+        assert_eq!(
+            values(select(c.clone(), 12, CoinSelection::BranchAndBound)),
+            vec![7, 5]
+        );
+        // no exact match, so it falls back to largest first
+        assert_eq!(
+            values(select(c.clone(), 9, CoinSelection::BranchAndBound)),
+            vec![10]
+        );
+        assert!(select(c, 26, CoinSelection::BranchAndBound).is_none());
+    }
+}
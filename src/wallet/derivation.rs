@@ -0,0 +1,93 @@
+//! Deterministic derivation of ed25519 key pairs from a seed, following SLIP-0010. ed25519 only
+//! allows hardened derivation, so every index in a path is hardened.
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Length of the seeds we generate.
+pub const SEED_LEN: usize = 32;
+const MIN_SEED_LEN: usize = 16;
+const MAX_SEED_LEN: usize = 64;
+const HARDENED: u32 = 0x8000_0000;
+const MASTER_HMAC_KEY: &[u8] = b"ed25519 seed";
+
+/// Generate a random seed.
+pub fn generate_seed() -> Vec<u8> {
+    let mut seed = vec![0u8; SEED_LEN];
+    SystemRandom::new().fill(&mut seed).unwrap();
+    seed
+}
+
+/// Parse a seed in hex.
+pub fn parse_seed(hex_seed: &str) -> Result<Vec<u8>, String> {
+    let seed = hex::decode(hex_seed.trim()).map_err(|e| format!("error decoding seed: {}", e))?;
+    if seed.len() < MIN_SEED_LEN || seed.len() > MAX_SEED_LEN {
+        return Err(format!(
+            "seed must be {} to {} bytes long",
+            MIN_SEED_LEN, MAX_SEED_LEN
+        ));
+    }
+    Ok(seed)
+}
+
+/// Split an HMAC-SHA512 into the key and the chain code.
+fn hmac_sha512(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, key), data);
+    let mut secret = [0u8; 32];
+    let mut chain_code = [0u8; 32];
+    secret.copy_from_slice(&tag.as_ref()[..32]);
+    chain_code.copy_from_slice(&tag.as_ref()[32..]);
+    (secret, chain_code)
+}
+
+/// Derive the secret key at `path` from `seed`. The indices are hardened for us.
+fn derive_secret(seed: &[u8], path: &[u32]) -> [u8; 32] {
+    let (mut secret, mut chain_code) = hmac_sha512(MASTER_HMAC_KEY, seed);
+    for index in path {
+        let mut data = Vec::with_capacity(37);
+        data.push(0u8);
+        data.extend_from_slice(&secret);
+        data.extend_from_slice(&(index | HARDENED).to_be_bytes());
+        let (s, c) = hmac_sha512(&chain_code, &data);
+        secret = s;
+        chain_code = c;
+    }
+    secret
+}
+
+/// Derive the key pair at `path` from `seed`.
+pub fn derive(seed: &[u8], path: &[u32]) -> Keypair {
+    let secret = SecretKey::from_bytes(&derive_secret(seed, path)).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+/// Derive the key pair of the `index`-th receiving or change address of a wallet, which is at
+/// m/0'/index' for receiving and m/1'/index' for change.
+pub fn address_keypair(seed: &[u8], change: bool, index: u32) -> Keypair {
+    derive(seed, &[change as u32, index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slip10_vectors() {
+        // test vector 1 for ed25519 of SLIP-0010
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        assert_eq!(
+            hex::encode(derive_secret(&seed, &[])),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        assert_eq!(
+            hex::encode(derive_secret(&seed, &[0])),
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"
+        );
+        assert_eq!(
+            hex::encode(derive(&seed, &[0]).public.as_bytes()),
+            "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use ed25519_dalek::Keypair;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+/// Number of PBKDF2 iterations for new passphrases.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+/// What we encrypt to check a passphrase.
+const CHECK_PLAINTEXT: &[u8] = b"prism wallet";
+
+/// How to derive an encryption key from a passphrase.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KdfParams {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+}
+
+impl KdfParams {
+    /// Generate parameters with a random salt.
+    pub fn generate() -> Self {
+        let mut salt = vec![0u8; SALT_LEN];
+        SystemRandom::new().fill(&mut salt).unwrap();
+        Self {
+            salt,
+            iterations: PBKDF2_ITERATIONS,
+        }
+    }
+}
+
+/// An AES-256-GCM key derived from a passphrase.
+pub struct Cipher {
+    key: LessSafeKey,
+}
+
+impl Cipher {
+    pub fn derive(passphrase: &str, params: &KdfParams) -> Self {
+        let mut key = [0u8; 32];
+        // zero iterations is corrupted data, and we would rather use one than panic
+        let iterations = NonZeroU32::new(params.iterations).unwrap_or(NonZeroU32::new(1).unwrap());
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &params.salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        Self {
+            key: LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).unwrap()),
+        }
+    }
+
+    /// Encrypt `plaintext`, and bind it to `aad` so that it can't be moved elsewhere. Returns the
+    /// random nonce followed by the ciphertext.
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).unwrap();
+        let mut in_out = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut in_out,
+            )
+            .unwrap();
+        [&nonce[..], &in_out[..]].concat()
+    }
+
+    /// Decrypt what `seal` returns, or return `None` if the key or `aad` is wrong or the data is
+    /// corrupted.
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&sealed[..NONCE_LEN]);
+        let mut in_out = sealed[NONCE_LEN..].to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut in_out,
+            )
+            .ok()?;
+        Some(plaintext.to_vec())
+    }
+}
+
+/// How the wallet keys are encrypted, stored in the wallet.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Encryption {
+    pub kdf: KdfParams,
+    /// A known plaintext encrypted with the key, to tell whether a passphrase is right.
+    pub check: Vec<u8>,
+}
+
+impl Encryption {
+    /// Set up encryption with a new passphrase, and return the derived key along.
+    pub fn new(passphrase: &str) -> (Self, Cipher) {
+        let kdf = KdfParams::generate();
+        let cipher = Cipher::derive(passphrase, &kdf);
+        let check = cipher.seal(CHECK_PLAINTEXT, &[]);
+        (Self { kdf, check }, cipher)
+    }
+
+    /// Derive the key from a passphrase, or return `None` if the passphrase is wrong.
+    pub fn unlock(&self, passphrase: &str) -> Option<Cipher> {
+        let cipher = Cipher::derive(passphrase, &self.kdf);
+        match cipher.open(&self.check, &[]) {
+            Some(ref p) if p.as_slice() == CHECK_PLAINTEXT => Some(cipher),
+            _ => None,
+        }
+    }
+}
+
+/// A key pair encrypted with a passphrase, for moving keys in and out of wallets and for key files.
+/// Byte strings are in base64.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptedKeypair {
+    /// Address of the key pair in hex, so that we know what it is without the passphrase.
+    pub address: String,
+    /// Always `pbkdf2-sha256`.
+    pub kdf: String,
+    pub iterations: u32,
+    pub salt: String,
+    /// The key pair encrypted with AES-256-GCM, after the nonce.
+    pub sealed: String,
+}
+
+const EXPORT_KDF: &str = "pbkdf2-sha256";
+
+impl EncryptedKeypair {
+    pub fn seal(keypair: &Keypair, address: &str, passphrase: &str) -> Self {
+        let kdf = KdfParams::generate();
+        let cipher = Cipher::derive(passphrase, &kdf);
+        let sealed = cipher.seal(&keypair.to_bytes(), address.as_bytes());
+        Self {
+            address: address.to_string(),
+            kdf: EXPORT_KDF.to_string(),
+            iterations: kdf.iterations,
+            salt: base64::encode(&kdf.salt),
+            sealed: base64::encode(&sealed),
+        }
+    }
+
+    /// Decrypt the key pair, or return `None` if the passphrase is wrong or the data is corrupted.
+    pub fn open(&self, passphrase: &str) -> Option<Keypair> {
+        if self.kdf != EXPORT_KDF {
+            return None;
+        }
+        let kdf = KdfParams {
+            salt: base64::decode(&self.salt).ok()?,
+            iterations: self.iterations,
+        };
+        let sealed = base64::decode(&self.sealed).ok()?;
+        let cipher = Cipher::derive(passphrase, &kdf);
+        let bytes = cipher.open(&sealed, self.address.as_bytes())?;
+        Keypair::from_bytes(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn seal_and_open() {
+        let (encryption, cipher) = Encryption::new("correct horse");
+        assert!(encryption.unlock("battery staple").is_none());
+        let cipher2 = encryption.unlock("correct horse").unwrap();
+        let sealed = cipher.seal(b"secret", b"aad");
+        assert_eq!(cipher2.open(&sealed, b"aad").unwrap(), b"secret");
+        assert!(cipher2.open(&sealed, b"other").is_none());
+
+        let keypair = Keypair::generate(&mut OsRng);
+        let exported = EncryptedKeypair::seal(&keypair, "00", "pass");
+        assert!(exported.open("wrong").is_none());
+        assert_eq!(
+            exported.open("pass").unwrap().to_bytes()[..],
+            keypair.to_bytes()[..]
+        );
+    }
+}
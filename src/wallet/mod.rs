@@ -1,4 +1,6 @@
-use crate::transaction::{Address, Authorization, CoinId, Input, Output, Transaction};
+use crate::crypto::hash::{Hashable, H256};
+use crate::transaction::{Address, Authorization, CoinId, Input, Multisig, Output, Transaction};
+use crate::utxodb::UtxoDatabase;
 use bincode::serialize;
 use ed25519_dalek::{Keypair, Signer};
 use rand::rngs::OsRng;
@@ -8,10 +10,34 @@ use std::collections::HashMap;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{error, fmt};
 
+mod coin_selection;
+mod derivation;
+mod encryption;
+
+pub use coin_selection::CoinSelection;
+pub use derivation::{address_keypair, generate_seed, parse_seed};
+pub use encryption::EncryptedKeypair;
+use encryption::{Cipher, Encryption};
+
 pub const COIN_CF: &str = "COIN";
-pub const KEYPAIR_CF: &str = "KEYPAIR"; // &Address to &KeyPairPKCS8
+pub const KEYPAIR_CF: &str = "KEYPAIR"; // &Address to &KeyPairPKCS8, sealed if encrypted
+pub const ACCOUNT_CF: &str = "ACCOUNT"; // &Address to &AddressInfo
+pub const PENDING_CF: &str = "PENDING"; // &CoinId to hash of the transaction spending it
+pub const SPEND_CF: &str = "SPEND"; // hash of a transaction we created to &Spend
+pub const META_CF: &str = "META";
+pub const MULTISIG_CF: &str = "MULTISIG"; // address of a multisig lock to &Multisig
+pub const MULTISIG_COIN_CF: &str = "MULTISIG_COIN"; // coins locked to the multisig locks
+
+const ENCRYPTION_KEY: &str = "ENCRYPTION"; // &Encryption, if the key pairs are encrypted
+const SEED_KEY: &str = "SEED"; // seed of the key pairs, sealed if encrypted
+const NEXT_INDEX_KEY: &str = "NEXT_INDEX"; // &[u32; 2], next receiving and change index to derive
+const SEED_AAD: &[u8] = b"seed";
+
+/// The account that addresses belong to unless told otherwise.
+pub const DEFAULT_ACCOUNT: &str = "default";
 
 pub type Result<T> = std::result::Result<T, WalletError>;
 
@@ -20,14 +46,66 @@ pub struct Wallet {
     /// The underlying RocksDB handle.
     db: rocksdb::DB,
     /// Keep key pair (in pkcs8 bytes) in memory for performance, it's duplicated in database as well.
+    /// Empty while the wallet is locked.
     keypairs: Mutex<HashMap<Address, Keypair>>,
+    /// The account of each address, duplicated in database as well.
+    accounts: Mutex<HashMap<Address, AddressInfo>>,
+    /// Number of coins that are not being spent.
     counter: AtomicUsize,
+    /// Held while picking coins and marking them as pending, so that two transactions never spend
+    /// the same coin.
+    spending: Mutex<()>,
+    /// How the key pairs are encrypted in database, or `None` if they are not.
+    encryption: Mutex<Option<Encryption>>,
+    /// The key pairs are encrypted with while the wallet is unlocked, and when to lock it again.
+    unlocked: Mutex<Option<(Cipher, Option<Instant>)>>,
+    /// The seed new key pairs are derived from, duplicated in database as well. `None` while the
+    /// wallet is locked, or if the wallet is from before seeds, in which case key pairs are random.
+    seed: Mutex<Option<Vec<u8>>>,
+    /// The next receiving and change index to derive a key pair at.
+    next_index: Mutex<[u32; 2]>,
+    /// The multisig locks we follow the coins of, duplicated in database as well.
+    multisig: Mutex<HashMap<Address, Multisig>>,
+}
+
+/// A transaction we created, from when we create it until we are sure it stays in the ledger or
+/// we give up on it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Spend {
+    /// The coins it spends, which are pending until then.
+    pub coins: Vec<CoinId>,
+    /// When we created it, or when it last dropped out of the ledger, in UNIX seconds.
+    pub created: u64,
+    /// When it got into the ledger, in UNIX seconds, or `None` if it is not in the ledger.
+    pub confirmed: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// What an address is used for.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AddressInfo {
+    pub account: String,
+    /// Whether the address is generated to receive the change of a transaction.
+    pub change: bool,
 }
 
 #[derive(Debug)]
 pub enum WalletError {
     InsufficientBalance,
     MissingKeyPair,
+    MissingSeed,
+    InvalidMultisig,
+    UnknownMultisig,
+    Locked,
+    WrongPassphrase,
+    NotEncrypted,
+    AlreadyEncrypted,
     DBError(rocksdb::Error),
 }
 
@@ -36,6 +114,16 @@ impl fmt::Display for WalletError {
         match *self {
             WalletError::InsufficientBalance => write!(f, "insufficient balance"),
             WalletError::MissingKeyPair => write!(f, "missing key pair for the requested address"),
+            WalletError::MissingSeed => write!(f, "wallet has no seed"),
+            WalletError::InvalidMultisig => write!(
+                f,
+                "threshold must be between 1 and the number of public keys"
+            ),
+            WalletError::UnknownMultisig => write!(f, "unknown multisig address"),
+            WalletError::Locked => write!(f, "wallet is locked"),
+            WalletError::WrongPassphrase => write!(f, "wrong passphrase"),
+            WalletError::NotEncrypted => write!(f, "wallet is not encrypted"),
+            WalletError::AlreadyEncrypted => write!(f, "wallet is already encrypted"),
             WalletError::DBError(ref e) => e.fmt(f),
         }
     }
@@ -61,39 +149,289 @@ impl Wallet {
         let coin_cf = rocksdb::ColumnFamilyDescriptor::new(COIN_CF, rocksdb::Options::default());
         let keypair_cf =
             rocksdb::ColumnFamilyDescriptor::new(KEYPAIR_CF, rocksdb::Options::default());
+        let account_cf =
+            rocksdb::ColumnFamilyDescriptor::new(ACCOUNT_CF, rocksdb::Options::default());
+        let pending_cf =
+            rocksdb::ColumnFamilyDescriptor::new(PENDING_CF, rocksdb::Options::default());
+        let spend_cf = rocksdb::ColumnFamilyDescriptor::new(SPEND_CF, rocksdb::Options::default());
+        let meta_cf = rocksdb::ColumnFamilyDescriptor::new(META_CF, rocksdb::Options::default());
+        let multisig_cf =
+            rocksdb::ColumnFamilyDescriptor::new(MULTISIG_CF, rocksdb::Options::default());
+        let multisig_coin_cf =
+            rocksdb::ColumnFamilyDescriptor::new(MULTISIG_COIN_CF, rocksdb::Options::default());
         let mut db_opts = rocksdb::Options::default();
         db_opts.create_missing_column_families(true);
         db_opts.create_if_missing(true);
-        let handle = rocksdb::DB::open_cf_descriptors(&db_opts, path, vec![coin_cf, keypair_cf])?;
+        let handle = rocksdb::DB::open_cf_descriptors(
+            &db_opts,
+            path,
+            vec![
+                coin_cf,
+                keypair_cf,
+                account_cf,
+                pending_cf,
+                spend_cf,
+                meta_cf,
+                multisig_cf,
+                multisig_coin_cf,
+            ],
+        )?;
         Ok(Self {
             db: handle,
             keypairs: Mutex::new(HashMap::new()),
+            accounts: Mutex::new(HashMap::new()),
             counter: AtomicUsize::new(0),
+            spending: Mutex::new(()),
+            encryption: Mutex::new(None),
+            unlocked: Mutex::new(None),
+            seed: Mutex::new(None),
+            next_index: Mutex::new([0, 0]),
+            multisig: Mutex::new(HashMap::new()),
         })
     }
 
+    /// Create a new wallet at the given path, with a random seed.
     pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        Self::from_seed(path, &generate_seed())
+    }
+
+    /// Create a new wallet at the given path that derives its key pairs from `seed`. Call
+    /// `discover` to find the key pairs derived before and their coins.
+    pub fn from_seed<P: AsRef<std::path::Path>>(path: P, seed: &[u8]) -> Result<Self> {
         rocksdb::DB::destroy(&rocksdb::Options::default(), &path)?;
-        Self::open(path)
+        let wallet = Self::open(path)?;
+        let meta_cf = wallet.db.cf_handle(META_CF).unwrap();
+        wallet.db.put_cf(meta_cf, SEED_KEY, seed)?;
+        *wallet.seed.lock().unwrap() = Some(seed.to_vec());
+        Ok(wallet)
+    }
+
+    /// Load an existing wallet at the given path, including its key pairs, accounts and coins. An
+    /// encrypted wallet starts locked.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let wallet = Self::open(path)?;
+        let meta_cf = wallet.db.cf_handle(META_CF).unwrap();
+        let encryption: Option<Encryption> = wallet
+            .db
+            .get_pinned_cf(meta_cf, ENCRYPTION_KEY)?
+            .map(|v| bincode::deserialize(v.as_ref()).unwrap());
+        if encryption.is_none() {
+            *wallet.seed.lock().unwrap() = wallet
+                .db
+                .get_pinned_cf(meta_cf, SEED_KEY)?
+                .map(|v| v.to_vec());
+        }
+        if let Some(v) = wallet.db.get_pinned_cf(meta_cf, NEXT_INDEX_KEY)? {
+            *wallet.next_index.lock().unwrap() = bincode::deserialize(v.as_ref()).unwrap();
+        }
+        let keypair_cf = wallet.db.cf_handle(KEYPAIR_CF).unwrap();
+        let mut keypairs = wallet.keypairs.lock().unwrap();
+        let mut addrs = vec![];
+        for (k, v) in wallet
+            .db
+            .iterator_cf(keypair_cf, rocksdb::IteratorMode::Start)?
+        {
+            let addr: Address = bincode::deserialize(k.as_ref()).unwrap();
+            if encryption.is_none() {
+                let keypair = Keypair::from_bytes(v.as_ref()).unwrap();
+                keypairs.insert(addr, keypair);
+            }
+            addrs.push(addr);
+        }
+        drop(keypairs);
+        *wallet.encryption.lock().unwrap() = encryption;
+        let account_cf = wallet.db.cf_handle(ACCOUNT_CF).unwrap();
+        let mut accounts = wallet.accounts.lock().unwrap();
+        for (k, v) in wallet
+            .db
+            .iterator_cf(account_cf, rocksdb::IteratorMode::Start)?
+        {
+            let addr: Address = bincode::deserialize(k.as_ref()).unwrap();
+            let info: AddressInfo = bincode::deserialize(v.as_ref()).unwrap();
+            accounts.insert(addr, info);
+        }
+        // key pairs from before there were accounts
+        for addr in addrs {
+            accounts.entry(addr).or_insert_with(|| AddressInfo {
+                account: DEFAULT_ACCOUNT.to_string(),
+                change: false,
+            });
+        }
+        drop(accounts);
+        let multisig_cf = wallet.db.cf_handle(MULTISIG_CF).unwrap();
+        let mut multisig = wallet.multisig.lock().unwrap();
+        for (k, v) in wallet
+            .db
+            .iterator_cf(multisig_cf, rocksdb::IteratorMode::Start)?
+        {
+            let addr: Address = bincode::deserialize(k.as_ref()).unwrap();
+            multisig.insert(addr, bincode::deserialize(v.as_ref()).unwrap());
+        }
+        drop(multisig);
+        wallet.count_coins()?;
+        Ok(wallet)
+    }
+
+    /// Count the coins that are not being spent.
+    fn count_coins(&self) -> Result<()> {
+        let coin_cf = self.db.cf_handle(COIN_CF).unwrap();
+        let mut coins = 0;
+        for (k, _) in self.db.iterator_cf(coin_cf, rocksdb::IteratorMode::Start)? {
+            if !self.is_pending(k.as_ref())? {
+                coins += 1;
+            }
+        }
+        self.counter.store(coins, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Rebuild the coins of the wallet from the given UTXO set. Coins that are being spent stay
+    /// pending.
+    pub fn rescan(&self, utxodb: &UtxoDatabase) -> Result<()> {
+        let mut batch = rocksdb::WriteBatch::default();
+        for cf_name in &[COIN_CF, MULTISIG_COIN_CF] {
+            let cf = self.db.cf_handle(cf_name).unwrap();
+            for (k, _) in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start)? {
+                batch.delete_cf(cf, k)?;
+            }
+        }
+        for (coin_id, coin_data) in utxodb.coins() {
+            if let Some(cf) = self.coin_cf(&coin_data.recipient) {
+                batch.put_cf(
+                    cf,
+                    serialize(&coin_id).unwrap(),
+                    serialize(&coin_data).unwrap(),
+                )?;
+            }
+        }
+        self.db.write(batch)?;
+        self.count_coins()?;
+        Ok(())
     }
 
     pub fn number_of_coins(&self) -> usize {
         self.counter.load(Ordering::Relaxed)
     }
 
-    /// Generate a new key pair
+    /// Generate a new key pair in the default account
     pub fn generate_keypair(&self) -> Result<Address> {
-        let _cf = self.db.cf_handle(KEYPAIR_CF).unwrap();
-        let mut csprng = OsRng;
-        let keypair: Keypair = Keypair::generate(&mut csprng);
-        self.load_keypair(keypair)
+        self.new_address(DEFAULT_ACCOUNT)
+    }
+
+    /// Generate a new key pair to receive coins in the given account
+    pub fn new_address(&self, account: &str) -> Result<Address> {
+        let info = AddressInfo {
+            account: account.to_string(),
+            change: false,
+        };
+        self.insert_keypair(self.next_keypair(false)?, info)
+    }
+
+    /// Derive the next receiving or change key pair from the seed, or generate a random one if the
+    /// wallet has no seed.
+    fn next_keypair(&self, change: bool) -> Result<Keypair> {
+        self.check_unlocked()?;
+        let seed = self.seed.lock().unwrap();
+        let seed = match &*seed {
+            Some(s) => s,
+            None if self.has_seed()? => return Err(WalletError::Locked),
+            None => return Ok(Keypair::generate(&mut OsRng)),
+        };
+        let mut next_index = self.next_index.lock().unwrap();
+        let index = next_index[change as usize];
+        next_index[change as usize] += 1;
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        self.db
+            .put_cf(meta_cf, NEXT_INDEX_KEY, serialize(&*next_index).unwrap())?;
+        Ok(address_keypair(seed, change, index))
+    }
+
+    fn has_seed(&self) -> Result<bool> {
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        Ok(self.db.get_pinned_cf(meta_cf, SEED_KEY)?.is_some())
+    }
+
+    /// Get the seed the key pairs are derived from, to back up the wallet.
+    pub fn seed(&self) -> Result<Vec<u8>> {
+        self.check_unlocked()?;
+        match &*self.seed.lock().unwrap() {
+            Some(s) => Ok(s.clone()),
+            None => Err(WalletError::MissingSeed),
+        }
     }
 
+    /// Derive key pairs from the seed until `gap` receiving and `gap` change addresses in a row
+    /// have no coins in the UTXO set, and rebuild the coins of the wallet. This recovers the key
+    /// pairs and coins of a wallet restored from its seed. Addresses it finds go to the default
+    /// account.
+    pub fn discover(&self, utxodb: &UtxoDatabase, gap: u32) -> Result<()> {
+        match self.seed() {
+            Ok(_) => {}
+            // nothing to derive, but the coins still need rebuilding
+            Err(WalletError::MissingSeed) => return self.rescan(utxodb),
+            Err(e) => return Err(e),
+        }
+        let mut derived: HashMap<Address, (bool, u32)> = HashMap::new();
+        // number of indices up to the last one with coins
+        let mut used = [0u32; 2];
+        loop {
+            for change in &[false, true] {
+                while self.next_index.lock().unwrap()[*change as usize]
+                    < used[*change as usize] + gap
+                {
+                    let index = self.next_index.lock().unwrap()[*change as usize];
+                    let info = AddressInfo {
+                        account: DEFAULT_ACCOUNT.to_string(),
+                        change: *change,
+                    };
+                    let addr = self.insert_keypair(self.next_keypair(*change)?, info)?;
+                    derived.insert(addr, (*change, index));
+                }
+            }
+            self.rescan(utxodb)?;
+            let mut now_used = used;
+            for (_, coin) in self.spendable_coins(None, None)? {
+                if let Some((change, index)) = derived.get(&coin.recipient) {
+                    let used = &mut now_used[*change as usize];
+                    *used = (*used).max(index + 1);
+                }
+            }
+            if now_used == used {
+                return Ok(());
+            }
+            used = now_used;
+        }
+    }
+
+    /// Load a key pair into the default account, or leave it in its account if we have it already
     pub fn load_keypair(&self, keypair: Keypair) -> Result<Address> {
+        let info = AddressInfo {
+            account: DEFAULT_ACCOUNT.to_string(),
+            change: false,
+        };
+        self.insert_keypair(keypair, info)
+    }
+
+    fn insert_keypair(&self, keypair: Keypair, info: AddressInfo) -> Result<Address> {
         let cf = self.db.cf_handle(KEYPAIR_CF).unwrap();
+        let account_cf = self.db.cf_handle(ACCOUNT_CF).unwrap();
         let addr: Address =
             ring::digest::digest(&ring::digest::SHA256, &keypair.public.as_bytes().as_ref()).into();
-        self.db.put_cf(cf, &addr, &keypair.to_bytes().to_vec())?;
+        // hold the lock so that the wallet does not get encrypted in between
+        let encryption = self.encryption.lock().unwrap();
+        let stored = if encryption.is_some() {
+            self.with_cipher(|cipher| cipher.seal(&keypair.to_bytes(), addr.as_ref()))?
+        } else {
+            keypair.to_bytes().to_vec()
+        };
+        let mut accounts = self.accounts.lock().unwrap();
+        let info = accounts.entry(addr).or_insert(info);
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put_cf(cf, addr, stored)?;
+        batch.put_cf(account_cf, addr, serialize(&info).unwrap())?;
+        self.db.write(batch)?;
+        drop(accounts);
+        drop(encryption);
         let mut keypairs = self.keypairs.lock().unwrap();
         keypairs.insert(addr, keypair);
         Ok(addr)
@@ -101,104 +439,626 @@ impl Wallet {
 
     /// Get the list of addresses for which we have a key pair
     pub fn addresses(&self) -> Result<Vec<Address>> {
-        let keypairs = self.keypairs.lock().unwrap();
-        let addrs = keypairs.keys().cloned().collect();
+        let accounts = self.accounts.lock().unwrap();
+        let addrs = accounts.keys().cloned().collect();
         Ok(addrs)
     }
 
-    fn contains_keypair(&self, addr: &Address) -> bool {
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.lock().unwrap().is_some()
+    }
+
+    /// Whether the key pairs are encrypted and we don't have the passphrase
+    pub fn is_locked(&self) -> bool {
+        self.check_unlocked().is_err()
+    }
+
+    /// Run `f` with the key the key pairs are encrypted with, or return an error if the wallet is
+    /// locked. Locks the wallet if it has been unlocked for long enough.
+    fn with_cipher<T, F: FnOnce(&Cipher) -> T>(&self, f: F) -> Result<T> {
+        let mut unlocked = self.unlocked.lock().unwrap();
+        match &*unlocked {
+            Some((_, Some(until))) if Instant::now() >= *until => {
+                *unlocked = None;
+                self.forget_secrets();
+                Err(WalletError::Locked)
+            }
+            Some((cipher, _)) => Ok(f(cipher)),
+            None => Err(WalletError::Locked),
+        }
+    }
+
+    /// Return an error if the wallet is locked
+    fn check_unlocked(&self) -> Result<()> {
+        if self.is_encrypted() {
+            self.with_cipher(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Encrypt the key pairs with a passphrase, and lock the wallet.
+    pub fn encrypt(&self, passphrase: &str) -> Result<()> {
+        let mut encryption = self.encryption.lock().unwrap();
+        if encryption.is_some() {
+            return Err(WalletError::AlreadyEncrypted);
+        }
+        let (new_encryption, cipher) = Encryption::new(passphrase);
+        let keypair_cf = self.db.cf_handle(KEYPAIR_CF).unwrap();
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        let keypairs = self.keypairs.lock().unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        for (addr, keypair) in keypairs.iter() {
+            batch.put_cf(
+                keypair_cf,
+                addr,
+                cipher.seal(&keypair.to_bytes(), addr.as_ref()),
+            )?;
+        }
+        if let Some(seed) = &*self.seed.lock().unwrap() {
+            batch.put_cf(meta_cf, SEED_KEY, cipher.seal(seed, SEED_AAD))?;
+        }
+        batch.put_cf(meta_cf, ENCRYPTION_KEY, serialize(&new_encryption).unwrap())?;
+        self.db.write(batch)?;
+        // the plaintext key pairs and seed are still in the WAL and the table files. flush and
+        // compact every column family, so that rocksdb deletes the WAL and the old table files
+        self.db.flush()?;
+        for cf in &[
+            COIN_CF,
+            KEYPAIR_CF,
+            ACCOUNT_CF,
+            PENDING_CF,
+            SPEND_CF,
+            META_CF,
+            MULTISIG_CF,
+            MULTISIG_COIN_CF,
+        ] {
+            let cf = self.db.cf_handle(cf).unwrap();
+            self.db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+        }
+        drop(keypairs);
+        self.forget_secrets();
+        *encryption = Some(new_encryption);
+        Ok(())
+    }
+
+    /// Decrypt the key pairs so that we can sign transactions and generate new addresses. If
+    /// `timeout` is given, the wallet locks again after that long. A timeout too long to keep track
+    /// of counts as none.
+    pub fn unlock(&self, passphrase: &str, timeout: Option<Duration>) -> Result<()> {
+        let expiry = timeout.and_then(|t| Instant::now().checked_add(t));
+        let encryption = self.encryption.lock().unwrap();
+        let cipher = match &*encryption {
+            Some(e) => e.unlock(passphrase).ok_or(WalletError::WrongPassphrase)?,
+            None => return Err(WalletError::NotEncrypted),
+        };
+        let keypair_cf = self.db.cf_handle(KEYPAIR_CF).unwrap();
+        let mut decrypted = HashMap::new();
+        for (k, v) in self
+            .db
+            .iterator_cf(keypair_cf, rocksdb::IteratorMode::Start)?
+        {
+            let addr: Address = bincode::deserialize(k.as_ref()).unwrap();
+            let bytes = cipher
+                .open(v.as_ref(), addr.as_ref())
+                .expect("Wallet key pair is corrupted");
+            decrypted.insert(addr, Keypair::from_bytes(&bytes).unwrap());
+        }
+        let meta_cf = self.db.cf_handle(META_CF).unwrap();
+        let seed = self.db.get_pinned_cf(meta_cf, SEED_KEY)?.map(|v| {
+            cipher
+                .open(v.as_ref(), SEED_AAD)
+                .expect("Wallet seed is corrupted")
+        });
+        let mut unlocked = self.unlocked.lock().unwrap();
+        *self.keypairs.lock().unwrap() = decrypted;
+        *self.seed.lock().unwrap() = seed;
+        *unlocked = Some((cipher, expiry));
+        Ok(())
+    }
+
+    /// Forget the decrypted key pairs and the passphrase.
+    pub fn lock(&self) -> Result<()> {
+        if !self.is_encrypted() {
+            return Err(WalletError::NotEncrypted);
+        }
+        let mut unlocked = self.unlocked.lock().unwrap();
+        *unlocked = None;
+        self.forget_secrets();
+        Ok(())
+    }
+
+    /// Forget the decrypted key pairs and seed when the wallet gets locked.
+    fn forget_secrets(&self) {
+        self.keypairs.lock().unwrap().clear();
+        *self.seed.lock().unwrap() = None;
+    }
+
+    /// Export the key pair of an address, encrypted with a passphrase.
+    pub fn export_keypair(&self, addr: &Address, passphrase: &str) -> Result<EncryptedKeypair> {
+        self.check_unlocked()?;
         let keypairs = self.keypairs.lock().unwrap();
-        if keypairs.contains_key(addr) {
-            return true;
+        let keypair = keypairs.get(addr).ok_or(WalletError::MissingKeyPair)?;
+        Ok(EncryptedKeypair::seal(
+            keypair,
+            &addr.to_string(),
+            passphrase,
+        ))
+    }
+
+    /// Import a key pair exported with `export_keypair` into an account.
+    pub fn import_keypair(
+        &self,
+        key: &EncryptedKeypair,
+        passphrase: &str,
+        account: &str,
+    ) -> Result<Address> {
+        let keypair = key.open(passphrase).ok_or(WalletError::WrongPassphrase)?;
+        let info = AddressInfo {
+            account: account.to_string(),
+            change: false,
+        };
+        self.insert_keypair(keypair, info)
+    }
+
+    /// Get the names of the accounts, in alphabetical order
+    pub fn accounts(&self) -> Vec<String> {
+        let accounts = self.accounts.lock().unwrap();
+        let mut names: Vec<String> = accounts.values().map(|i| i.account.clone()).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Get the addresses of an account that are meant to receive coins, i.e. not for change
+    pub fn account_addresses(&self, account: &str) -> Vec<Address> {
+        let accounts = self.accounts.lock().unwrap();
+        accounts
+            .iter()
+            .filter(|(_, i)| i.account == account && !i.change)
+            .map(|(a, _)| *a)
+            .collect()
+    }
+
+    /// Get the account and use of an address, or `None` if it is not ours
+    pub fn address_info(&self, addr: &Address) -> Option<AddressInfo> {
+        let accounts = self.accounts.lock().unwrap();
+        accounts.get(addr).cloned()
+    }
+
+    fn in_account(&self, addr: &Address, account: Option<&str>) -> bool {
+        match account {
+            None => true,
+            Some(account) => {
+                let accounts = self.accounts.lock().unwrap();
+                match accounts.get(addr) {
+                    Some(i) => i.account == account,
+                    None => false,
+                }
+            }
+        }
+    }
+
+    fn contains_keypair(&self, addr: &Address) -> bool {
+        // the key pairs are not in memory while the wallet is locked, but the accounts are
+        let accounts = self.accounts.lock().unwrap();
+        accounts.contains_key(addr)
+    }
+
+    /// Get the column family to keep coins sent to `recipient` in, or `None` if they are not ours.
+    fn coin_cf(&self, recipient: &Address) -> Option<&rocksdb::ColumnFamily> {
+        if self.contains_keypair(recipient) {
+            self.db.cf_handle(COIN_CF)
+        } else if self.multisig.lock().unwrap().contains_key(recipient) {
+            self.db.cf_handle(MULTISIG_COIN_CF)
+        } else {
+            None
         }
-        false
     }
 
     pub fn apply_diff(&self, add: &[(CoinId, Output)], remove: &[CoinId]) -> Result<()> {
         let mut batch = rocksdb::WriteBatch::default();
         let cf = self.db.cf_handle(COIN_CF).unwrap();
+        let multisig_coin_cf = self.db.cf_handle(MULTISIG_COIN_CF).unwrap();
         for coin in add {
-            if self.contains_keypair(&coin.1.recipient) {
+            if self
+                .multisig
+                .lock()
+                .unwrap()
+                .contains_key(&coin.1.recipient)
+            {
                 let key = serialize(&coin.0).unwrap();
                 let val = serialize(&coin.1).unwrap();
+                batch.put_cf(multisig_coin_cf, &key, &val)?;
+            } else if self.contains_keypair(&coin.1.recipient) {
+                let key = serialize(&coin.0).unwrap();
+                let val = serialize(&coin.1).unwrap();
+                // a coin may come back pending when the transaction spending it is deconfirmed
+                if !self.is_pending(&key)? {
+                    self.counter.fetch_add(1, Ordering::Relaxed);
+                }
                 batch.put_cf(cf, &key, &val)?;
-                self.counter.fetch_add(1, Ordering::Relaxed);
             }
         }
+        // pending coins stay marked until the spend is settled, in case the transaction spending
+        // them is deconfirmed
         for coin in remove {
             let key = serialize(&coin).unwrap();
             batch.delete_cf(cf, &key)?;
+            batch.delete_cf(multisig_coin_cf, &key)?;
         }
         self.db.write(batch)?;
         Ok(())
     }
 
-    /// Returns the sum of values of all the coin in the wallet
+    fn is_pending(&self, coin: &[u8]) -> Result<bool> {
+        let pending_cf = self.db.cf_handle(PENDING_CF).unwrap();
+        Ok(self.db.get_pinned_cf(pending_cf, coin)?.is_some())
+    }
+
+    /// Returns the sum of values of all the coin in the wallet that are not being spent
     pub fn balance(&self) -> Result<u64> {
+        self.balance_of(None)
+    }
+
+    /// Returns the sum of values of the coins in an account that are not being spent
+    pub fn account_balance(&self, account: &str) -> Result<u64> {
+        self.balance_of(Some(account))
+    }
+
+    fn balance_of(&self, account: Option<&str>) -> Result<u64> {
+        Ok(self
+            .spendable_coins(account, None)?
+            .iter()
+            .map(|(_, o)| o.value)
+            .sum())
+    }
+
+    /// Returns the sum of values of the coins that are being spent
+    pub fn pending_balance(&self) -> Result<u64> {
         let cf = self.db.cf_handle(COIN_CF).unwrap();
-        let iter = self.db.iterator_cf(cf, rocksdb::IteratorMode::Start)?;
-        let balance = iter
-            .map(|(_, v)| {
+        let pending_cf = self.db.cf_handle(PENDING_CF).unwrap();
+        let mut balance = 0;
+        for (k, _) in self
+            .db
+            .iterator_cf(pending_cf, rocksdb::IteratorMode::Start)?
+        {
+            if let Some(v) = self.db.get_pinned_cf(cf, k)? {
                 let coin_data: Output = bincode::deserialize(v.as_ref()).unwrap();
-                coin_data.value
-            })
-            .sum::<u64>();
+                balance += coin_data.value;
+            }
+        }
         Ok(balance)
     }
 
-    /// Create a transaction using the wallet coins
-    pub fn create_transaction(
+    /// Get the coins of an account (or of all accounts) that are not being spent, in the order they
+    /// are stored. If `value` is given, stop once the coins add up to it.
+    fn spendable_coins(
         &self,
-        recipient: Address,
-        value: u64,
-        previous_used_coin: Option<CoinId>,
-    ) -> Result<Transaction> {
-        let mut coins_to_use: Vec<CoinId> = vec![];
-        let mut inputs: Vec<Input> = vec![];
-        let mut value_sum = 0u64;
+        account: Option<&str>,
+        value: Option<u64>,
+    ) -> Result<Vec<(CoinId, Output)>> {
+        self.spendable_coins_from(account, value, None)
+    }
+
+    fn spendable_coins_from(
+        &self,
+        account: Option<&str>,
+        value: Option<u64>,
+        from: Option<CoinId>,
+    ) -> Result<Vec<(CoinId, Output)>> {
         let cf = self.db.cf_handle(COIN_CF).unwrap();
-        let iter = match previous_used_coin {
-            Some(c) => {
-                let prev_key = serialize(&c).unwrap();
-                self.db.iterator_cf(
-                    cf,
-                    rocksdb::IteratorMode::From(&prev_key, rocksdb::Direction::Forward),
-                )?
-            }
+        let from = from.map(|c| serialize(&c).unwrap());
+        let iter = match &from {
+            Some(prev_key) => self.db.iterator_cf(
+                cf,
+                rocksdb::IteratorMode::From(prev_key, rocksdb::Direction::Forward),
+            )?,
             None => self.db.iterator_cf(cf, rocksdb::IteratorMode::Start)?,
         };
-        // iterate through our wallet
+        let mut coins = vec![];
+        let mut sum = 0u64;
         for (k, v) in iter {
+            if self.is_pending(k.as_ref())? {
+                continue;
+            }
+            let coin_data: Output = bincode::deserialize(v.as_ref()).unwrap();
+            if !self.in_account(&coin_data.recipient, account) {
+                continue;
+            }
             let coin_id: CoinId = bincode::deserialize(k.as_ref()).unwrap();
+            sum += coin_data.value;
+            coins.push((coin_id, coin_data));
+            if let Some(value) = value {
+                if sum >= value {
+                    break;
+                }
+            }
+        }
+        Ok(coins)
+    }
+
+    /// Get the transactions we created that are not settled yet, with their hashes.
+    pub fn spends(&self) -> Result<Vec<(H256, Spend)>> {
+        let spend_cf = self.db.cf_handle(SPEND_CF).unwrap();
+        let mut spends = vec![];
+        for (k, v) in self
+            .db
+            .iterator_cf(spend_cf, rocksdb::IteratorMode::Start)?
+        {
+            let hash: H256 = bincode::deserialize(k.as_ref()).unwrap();
+            let spend: Spend = bincode::deserialize(v.as_ref()).unwrap();
+            spends.push((hash, spend));
+        }
+        Ok(spends)
+    }
+
+    fn get_spend(&self, hash: &H256) -> Result<Option<Spend>> {
+        let spend_cf = self.db.cf_handle(SPEND_CF).unwrap();
+        Ok(self
+            .db
+            .get_pinned_cf(spend_cf, serialize(hash).unwrap())?
+            .map(|v| bincode::deserialize(v.as_ref()).unwrap()))
+    }
+
+    /// Note that the given transactions are now in the ledger. Transactions we did not create are
+    /// ignored.
+    pub fn confirm_spends(&self, hashes: &[H256]) -> Result<()> {
+        let spend_cf = self.db.cf_handle(SPEND_CF).unwrap();
+        let _spending = self.spending.lock().unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        for hash in hashes {
+            if let Some(mut spend) = self.get_spend(hash)? {
+                spend.confirmed = Some(now());
+                batch.put_cf(
+                    spend_cf,
+                    serialize(hash).unwrap(),
+                    serialize(&spend).unwrap(),
+                )?;
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Note that the given transactions dropped out of the ledger. Their coins stay pending, since
+    /// the transactions may get back into the ledger, until the spends expire.
+    pub fn deconfirm_spends(&self, hashes: &[H256]) -> Result<()> {
+        let spend_cf = self.db.cf_handle(SPEND_CF).unwrap();
+        let pending_cf = self.db.cf_handle(PENDING_CF).unwrap();
+        let _spending = self.spending.lock().unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        for hash in hashes {
+            if let Some(mut spend) = self.get_spend(hash)? {
+                spend.confirmed = None;
+                spend.created = now();
+                let raw_hash = serialize(hash).unwrap();
+                for coin in &spend.coins {
+                    batch.put_cf(pending_cf, serialize(coin).unwrap(), &raw_hash)?;
+                }
+                batch.put_cf(spend_cf, &raw_hash, serialize(&spend).unwrap())?;
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Give up on a transaction we created, e.g. because the memory pool rejected it, and return
+    /// its coins to the wallet.
+    pub fn abandon_transaction(&self, transaction: &Transaction) -> Result<()> {
+        self.settle_spend(&transaction.hash())
+    }
+
+    /// Forget a transaction we created, and return the coins it spends that are still in the
+    /// wallet. Call it either to give up on the transaction, or once it is in the ledger for good.
+    pub fn settle_spend(&self, hash: &H256) -> Result<()> {
+        let spend_cf = self.db.cf_handle(SPEND_CF).unwrap();
+        let pending_cf = self.db.cf_handle(PENDING_CF).unwrap();
+        let coin_cf = self.db.cf_handle(COIN_CF).unwrap();
+        let _spending = self.spending.lock().unwrap();
+        let spend = match self.get_spend(hash)? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut returned = 0;
+        for coin in &spend.coins {
+            let key = serialize(coin).unwrap();
+            if let Some(v) = self.db.get_pinned_cf(pending_cf, &key)? {
+                // the coin may be pending for another transaction if we gave up on this one before
+                let spender: H256 = bincode::deserialize(v.as_ref()).unwrap();
+                if spender == *hash {
+                    batch.delete_cf(pending_cf, &key)?;
+                    if self.db.get_pinned_cf(coin_cf, &key)?.is_some() {
+                        returned += 1;
+                    }
+                }
+            }
+        }
+        batch.delete_cf(spend_cf, serialize(hash).unwrap())?;
+        self.db.write(batch)?;
+        self.counter.fetch_add(returned, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Get the public key of one of our addresses, to share for multisig locks.
+    pub fn public_key(&self, addr: &Address) -> Result<Vec<u8>> {
+        self.check_unlocked()?;
+        let keypairs = self.keypairs.lock().unwrap();
+        match keypairs.get(addr) {
+            Some(k) => Ok(k.public.to_bytes().to_vec()),
+            None => Err(WalletError::MissingKeyPair),
+        }
+    }
+
+    /// Follow the coins locked to a multisig lock, usually one that some of our key pairs are in.
+    /// Call `rescan` to find the coins locked to it before.
+    pub fn add_multisig(&self, lock: Multisig) -> Result<Address> {
+        if !lock.is_satisfied_by(&lock.members()) {
+            return Err(WalletError::InvalidMultisig);
+        }
+        let addr = lock.address();
+        let cf = self.db.cf_handle(MULTISIG_CF).unwrap();
+        self.db.put_cf(cf, addr, serialize(&lock).unwrap())?;
+        self.multisig.lock().unwrap().insert(addr, lock);
+        Ok(addr)
+    }
+
+    /// Get the multisig locks we follow, with their addresses.
+    pub fn multisig_locks(&self) -> Vec<(Address, Multisig)> {
+        let multisig = self.multisig.lock().unwrap();
+        multisig.iter().map(|(a, m)| (*a, m.clone())).collect()
+    }
+
+    /// Get the coins locked to a multisig lock.
+    pub fn multisig_coins(&self, lock_addr: &Address) -> Result<Vec<(CoinId, Output)>> {
+        let cf = self.db.cf_handle(MULTISIG_COIN_CF).unwrap();
+        let mut coins = vec![];
+        for (k, v) in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start)? {
             let coin_data: Output = bincode::deserialize(v.as_ref()).unwrap();
-            value_sum += coin_data.value;
-            coins_to_use.push(coin_id);
-            inputs.push(Input {
-                coin: coin_id,
+            if coin_data.recipient == *lock_addr {
+                coins.push((bincode::deserialize(k.as_ref()).unwrap(), coin_data));
+            }
+        }
+        Ok(coins)
+    }
+
+    /// Create a transaction paying `value` to `recipient` and `fee` to the miners out of the coins
+    /// locked to a multisig lock, with the change locked to it again, and sign it with our key
+    /// pairs in the lock. The other members sign it with `sign_transaction` until it has enough
+    /// signatures, and the copies they sign separately merge with `Transaction::combine`.
+    pub fn create_multisig_transaction(
+        &self,
+        lock_addr: &Address,
+        recipient: Address,
+        value: u64,
+        fee: u64,
+    ) -> Result<Transaction> {
+        let lock = match self.multisig.lock().unwrap().get(lock_addr) {
+            Some(l) => l.clone(),
+            None => return Err(WalletError::UnknownMultisig),
+        };
+        let coins = self.multisig_coins(lock_addr)?;
+        let total = value.saturating_add(fee);
+        let coins = match coin_selection::select(coins, total, CoinSelection::LargestFirst) {
+            Some(c) => c,
+            None => return Err(WalletError::InsufficientBalance),
+        };
+        let value_sum: u64 = coins.iter().map(|(_, o)| o.value).sum();
+        let input: Vec<Input> = coins
+            .iter()
+            .map(|(coin_id, coin_data)| Input {
+                coin: *coin_id,
                 value: coin_data.value,
                 owner: coin_data.recipient,
-            }); // coins that will be used for this transaction
-            if value_sum >= value {
-                // if we already have enough money, break
-                break;
+            })
+            .collect();
+        let mut output = vec![Output { recipient, value }];
+        if value_sum > total {
+            output.push(Output {
+                recipient: *lock_addr,
+                value: value_sum - total,
+            });
+        }
+        let unsigned = Transaction {
+            input,
+            output,
+            authorization: vec![],
+            multisig: vec![lock],
+            hash: RefCell::new(None),
+        };
+        self.sign_transaction(&unsigned)
+    }
+
+    /// Add our signatures to a transaction: for the inputs of our key pairs, and for the inputs
+    /// locked to a multisig lock that our key pairs are in. Keys that already signed are skipped.
+    pub fn sign_transaction(&self, transaction: &Transaction) -> Result<Transaction> {
+        self.check_unlocked()?;
+        let mut signers = transaction.signers();
+        let mut keys: Vec<Address> = vec![];
+        for input in &transaction.input {
+            keys.push(input.owner);
+            if let Some(lock) = transaction
+                .multisig
+                .iter()
+                .find(|m| m.address() == input.owner)
+            {
+                keys.extend(lock.members());
             }
         }
-        if value_sum < value {
-            // we don't have enough money in wallet
-            return Err(WalletError::InsufficientBalance);
+        let raw_inputs = bincode::serialize(&transaction.input).unwrap();
+        let raw_outputs = bincode::serialize(&transaction.output).unwrap();
+        let raw_unsigned = [&raw_inputs[..], &raw_outputs[..]].concat();
+        let mut authorization = transaction.authorization.clone();
+        let keypairs = self.keypairs.lock().unwrap();
+        for key in keys {
+            if let Some(v) = keypairs.get(&key) {
+                if signers.insert(key) {
+                    authorization.push(Authorization {
+                        pubkey: v.public.to_bytes().to_vec(),
+                        signature: v.sign(&raw_unsigned).to_bytes().to_vec(),
+                    });
+                }
+            }
         }
-        // if we have enough money in our wallet, create tx
-        // remove used coin from wallet
-        self.apply_diff(&[], &coins_to_use)?;
+        Ok(Transaction {
+            input: transaction.input.clone(),
+            output: transaction.output.clone(),
+            authorization,
+            multisig: transaction.multisig.clone(),
+            hash: RefCell::new(None),
+        })
+    }
+
+    /// Create a transaction paying `value` to `recipient` and `fee` to the miners out of the coins
+    /// of an account, and send the change to a new address of the same account. The coins spent are
+    /// marked as pending until the transaction is settled or abandoned.
+    pub fn create_transaction(
+        &self,
+        account: &str,
+        recipient: Address,
+        value: u64,
+        fee: u64,
+        selection: CoinSelection,
+    ) -> Result<Transaction> {
+        self.check_unlocked()?;
+        let total = value.saturating_add(fee);
+        let spending = self.spending.lock().unwrap();
+        let coins = match selection {
+            // no need to read all the coins
+            CoinSelection::InOrder(from) => {
+                self.spendable_coins_from(Some(account), Some(total), from)?
+            }
+            _ => self.spendable_coins(Some(account), None)?,
+        };
+        let coins = match coin_selection::select(coins, total, selection) {
+            Some(c) => c,
+            // we don't have enough money in wallet
+            None => return Err(WalletError::InsufficientBalance),
+        };
+        let value_sum: u64 = coins.iter().map(|(_, o)| o.value).sum();
+        // coins that will be used for this transaction
+        let inputs: Vec<Input> = coins
+            .iter()
+            .map(|(coin_id, coin_data)| Input {
+                coin: *coin_id,
+                value: coin_data.value,
+                owner: coin_data.recipient,
+            })
+            .collect();
 
         // create the output
         let mut output = vec![Output { recipient, value }];
-        if value_sum > value {
+        if value_sum > total {
             // transfer the remaining value back to self
-            let recipient = self.addresses()?[0];
+            let info = AddressInfo {
+                account: account.to_string(),
+                change: true,
+            };
+            let recipient = self.insert_keypair(self.next_keypair(true)?, info)?;
             output.push(Output {
                 recipient,
-                value: value_sum - value,
+                value: value_sum - total,
             });
         }
 
@@ -207,6 +1067,7 @@ impl Wallet {
             input: inputs,
             output,
             authorization: vec![],
+            multisig: vec![],
             hash: RefCell::new(None),
         };
         let mut authorization = vec![];
@@ -227,14 +1088,135 @@ impl Wallet {
             }
             drop(keypairs);
         }
-        self.counter
-            .fetch_sub(unsigned.input.len(), Ordering::Relaxed);
-        Ok(Transaction {
+        let transaction = Transaction {
             authorization,
             ..unsigned
-        })
+        };
+
+        // mark the coins as pending instead of removing them, so that we get them back if the
+        // transaction does not make it into the ledger
+        let pending_cf = self.db.cf_handle(PENDING_CF).unwrap();
+        let spend_cf = self.db.cf_handle(SPEND_CF).unwrap();
+        let hash = serialize(&transaction.hash()).unwrap();
+        let spend = Spend {
+            coins: transaction.input.iter().map(|i| i.coin).collect(),
+            created: now(),
+            confirmed: None,
+        };
+        let mut batch = rocksdb::WriteBatch::default();
+        for coin in &spend.coins {
+            batch.put_cf(pending_cf, serialize(coin).unwrap(), &hash)?;
+        }
+        batch.put_cf(spend_cf, &hash, serialize(&spend).unwrap())?;
+        self.db.write(batch)?;
+        drop(spending);
+        self.counter
+            .fetch_sub(transaction.input.len(), Ordering::Relaxed);
+        Ok(transaction)
     }
 }
 
 #[cfg(test)]
-pub mod tests {}
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_coins_return() {
+        let wallet = Wallet::new("/tmp/prism_test_wallet_pending_coins_return.rocksdb").unwrap();
+        let addr = wallet.new_address(DEFAULT_ACCOUNT).unwrap();
+        let coin = |n: u8, value| {
+            (
+                CoinId {
+                    hash: [n; 32].into(),
+                    index: 0,
+                },
+                Output {
+                    value,
+                    recipient: addr,
+                },
+            )
+        };
+        wallet.apply_diff(&[coin(1, 10), coin(2, 20)], &[]).unwrap();
+        let recipient: Address = [9u8; 32].into();
+        let pay = |value| {
+            wallet
+                .create_transaction(
+                    DEFAULT_ACCOUNT,
+                    recipient,
+                    value,
+                    0,
+                    CoinSelection::SmallestFirst,
+                )
+                .unwrap()
+        };
+
+        // the coins come back when we give up on the transaction
+        let transaction = pay(10);
+        assert_eq!(wallet.balance().unwrap(), 20);
+        assert_eq!(wallet.pending_balance().unwrap(), 10);
+        assert_eq!(wallet.number_of_coins(), 1);
+        wallet.abandon_transaction(&transaction).unwrap();
+        assert_eq!(wallet.balance().unwrap(), 30);
+        assert_eq!(wallet.pending_balance().unwrap(), 0);
+        assert_eq!(wallet.number_of_coins(), 2);
+
+        // and when the transaction drops out of the ledger and we give up on it
+        let transaction = pay(10);
+        let hash = transaction.hash();
+        wallet.confirm_spends(&[hash]).unwrap();
+        wallet.apply_diff(&[], &[coin(1, 10).0]).unwrap();
+        wallet.deconfirm_spends(&[hash]).unwrap();
+        wallet.apply_diff(&[coin(1, 10)], &[]).unwrap();
+        assert_eq!(wallet.pending_balance().unwrap(), 10);
+        assert_eq!(wallet.number_of_coins(), 1);
+        wallet.settle_spend(&hash).unwrap();
+        assert_eq!(wallet.balance().unwrap(), 30);
+        assert_eq!(wallet.number_of_coins(), 2);
+
+        // but not once the transaction has spent them
+        let transaction = pay(10);
+        let hash = transaction.hash();
+        wallet.apply_diff(&[], &[coin(1, 10).0]).unwrap();
+        wallet.settle_spend(&hash).unwrap();
+        assert_eq!(wallet.balance().unwrap(), 20);
+        assert_eq!(wallet.pending_balance().unwrap(), 0);
+        assert_eq!(wallet.number_of_coins(), 1);
+    }
+
+    #[test]
+    fn encrypt_erases_plaintext() {
+        let path = "/tmp/prism_test_wallet_encrypt_erases_plaintext.rocksdb";
+        let wallet = Wallet::new(path).unwrap();
+        let addr = wallet.new_address(DEFAULT_ACCOUNT).unwrap();
+        let secret = wallet.keypairs.lock().unwrap()[&addr].secret.to_bytes();
+        let seed = wallet.seed.lock().unwrap().clone().unwrap();
+        wallet.encrypt("passphrase").unwrap();
+        drop(wallet);
+
+        let contains = |data: &[u8], needle: &[u8]| data.windows(needle.len()).any(|w| w == needle);
+        for entry in std::fs::read_dir(path).unwrap() {
+            let data = std::fs::read(entry.unwrap().path()).unwrap();
+            assert!(!contains(&data, &secret));
+            assert!(!contains(&data, &seed));
+        }
+        let wallet = Wallet::load(path).unwrap();
+        wallet.unlock("passphrase", None).unwrap();
+        assert_eq!(
+            wallet.keypairs.lock().unwrap()[&addr].secret.to_bytes(),
+            secret
+        );
+    }
+
+    #[test]
+    fn unlock_for_very_long() {
+        let path = "/tmp/prism_test_wallet_unlock_for_very_long.rocksdb";
+        let wallet = Wallet::new(path).unwrap();
+        wallet.encrypt("passphrase").unwrap();
+        wallet
+            .unlock("passphrase", Some(Duration::from_secs(u64::MAX)))
+            .unwrap();
+        assert!(!wallet.is_locked());
+        wallet.lock().unwrap();
+        assert!(wallet.is_locked());
+    }
+}
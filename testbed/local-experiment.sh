@@ -82,12 +82,26 @@ for (( i = 0 ; i < $num_nodes ; i++ )); do
 	$cmd 2> ${i}.addr 1> ${i}.pkcs8
 done
 
-# build funding command
-funding_cmd=""
+# build genesis file
+allocations=""
 for (( i = 0 ; i < $num_nodes ; i++ )); do
 	addr=`cat ${i}.addr`
-	funding_cmd="$funding_cmd --fund-addr $addr"
+	if [ -n "$allocations" ]; then
+		allocations="$allocations, "
+	fi
+	allocations="$allocations{\"address\": \"$addr\", \"coins\": 100000, \"value\": 100}"
 done
+cat > genesis.json << EOF
+{
+	"voter_chains": ${VOTER_CHAINS},
+	"tx_throughput": ${throughput_param},
+	"proposer_mining_rate": ${MINING_RATE},
+	"voter_mining_rate": ${MINING_RATE},
+	"confirm_confidence": 20.0,
+	"adversary_ratio": 0.33,
+	"allocations": [$allocations]
+}
+EOF
 
 p2p_port=6000
 api_port=7000
@@ -100,14 +114,30 @@ for (( i = 0; i < $num_nodes; i++ )); do
 	p2p=`expr $p2p_port + $i`
 	api=`expr $api_port + $i`
 	vis=`expr $vis_port + $i`
-	command="$binary_path --p2p 127.0.0.1:${p2p} --api 127.0.0.1:${api} --visual 127.0.0.1:${vis} --blockdb /tmp/prism-${i}-blockdb.rocksdb --blockchaindb /tmp/prism-${i}-blockchaindb.rocksdb --utxodb /tmp/prism-${i}-utxodb.rocksdb --walletdb /tmp/prism-${i}-wallet.rocksdb -vvvvv --load-key ${i}.pkcs8 --fund-coins=100000 --voter-chains=${VOTER_CHAINS} --tx-throughput=${throughput_param} --proposer-mining-rate=${MINING_RATE} --voter-mining-rate=${MINING_RATE} --confirm-confidence=20.0 --adversary-ratio=0.33"
-
+	peers=""
 	for (( j = 0; j < $i; j++ )); do
 		peer_port=`expr $p2p_port + $j`
-		command="$command -c 127.0.0.1:${peer_port}"
+		if [ -n "$peers" ]; then
+			peers="$peers, "
+		fi
+		peers="$peers\"127.0.0.1:${peer_port}\""
 	done
-
-	command="$command $funding_cmd"
+	cat > ${i}.toml << EOF
+verbose = 5
+p2p = "127.0.0.1:${p2p}"
+api = "127.0.0.1:${api}"
+visual = "127.0.0.1:${vis}"
+connect = [$peers]
+blockdb = "/tmp/prism-${i}-blockdb.rocksdb"
+blockchaindb = "/tmp/prism-${i}-blockchaindb.rocksdb"
+utxodb = "/tmp/prism-${i}-utxodb.rocksdb"
+walletdb = "/tmp/prism-${i}-wallet.rocksdb"
+peerdb = "/tmp/prism-${i}-peers.rocksdb"
+load_key = ["${i}.pkcs8"]
+genesis = "genesis.json"
+EOF
+
+	command="$binary_path --config ${i}.toml"
 	export RUST_BACKTRACE=1
 	$command &> ${i}.log &
 	pid="$!"
@@ -157,4 +187,6 @@ for (( i = 0; i < $num_nodes; i++ )); do
 	rm -f $i.log
 	rm -f $i.addr
 	rm -f $i.pkcs8
+	rm -f $i.toml
 done
+rm -f genesis.json
@@ -4,7 +4,23 @@ import json
 import subprocess
 
 template = """
-/home/ubuntu/payload/binary/prism --p2p {ip}:{p2p_port} --api {ip}:{api_port} --visual {ip}:{vis_port} --blockdb /tmp/prism/{node_name}-blockdb.rocksdb --blockchaindb /tmp/prism/{node_name}-blockchaindb.rocksdb --utxodb /tmp/prism/{node_name}-utxodb.rocksdb --walletdb /tmp/prism/{node_name}-wallet.rocksdb -vv --load-key /home/ubuntu/payload/prism-payload/{node_name}.pkcs8 {peer_opt} {fund_opt} --fund-coins=40000 --mempool-size=50000
+/home/ubuntu/payload/binary/prism --config /home/ubuntu/payload/prism-payload/{node_name}.toml
+"""
+
+config_template = """
+verbose = 2
+p2p = "{ip}:{p2p_port}"
+api = "{ip}:{api_port}"
+visual = "{ip}:{vis_port}"
+connect = [{peers}]
+blockdb = "/tmp/prism/{node_name}-blockdb.rocksdb"
+blockchaindb = "/tmp/prism/{node_name}-blockchaindb.rocksdb"
+utxodb = "/tmp/prism/{node_name}-utxodb.rocksdb"
+walletdb = "/tmp/prism/{node_name}-wallet.rocksdb"
+peerdb = "/tmp/prism/{node_name}-peers.rocksdb"
+load_key = ["/home/ubuntu/payload/prism-payload/{node_name}.pkcs8"]
+genesis = "/home/ubuntu/payload/prism-payload/genesis.json"
+mempool_size = 50000
 """
 
 instances_file = sys.argv[1]
@@ -49,33 +65,40 @@ for node in topo['nodes']:
         instance_idx = 0
 
 # generate wallet keypair for each node
-fund_addrs = []
+allocations = []
 prism_bin = "../target/debug/prism"
 for name, node in nodes.items():
     result = subprocess.run([prism_bin, "keygen", "--addr"], capture_output=True, text=True)
     keypair = result.stdout
     address = result.stderr
     node["address"] = address.strip()
-    fund_addrs.append("--fund-addr {}".format(address.strip()))
+    allocations.append({"address": address.strip(), "coins": 40000, "value": 100})
     os.makedirs("payload/{}/prism-payload".format(node['host']), exist_ok=True)
     with open("payload/{}/prism-payload/{}.pkcs8".format(node['host'], name), "w") as f:
         f.write(keypair.strip())
-fund_opt = " ".join(fund_addrs)
+
+# write the genesis file, which every node starts from
+genesis = {"allocations": allocations}
+for host in set(node['host'] for node in nodes.values()):
+    with open("payload/{}/prism-payload/genesis.json".format(host), "w") as f:
+        json.dump(genesis, f)
 
 
-# generate startup script for each node
+# generate config file and startup script for each node
 for name, node in nodes.items():
     peers = []
     for c in topo['connections']:
         if c['from'] == name:
             dst = c['to']
-            peers.append('-c {}:{}'.format(nodes[dst]['ip'], nodes[dst]['p2p_port']))
-    peer_opt = ' '.join(peers)
-    startup_str = template.format(
+            peers.append('"{}:{}"'.format(nodes[dst]['ip'], nodes[dst]['p2p_port']))
+    config_str = config_template.format(
             node_name=name, ip=node['ip'], api_port=node['api_port'],
-            p2p_port=node['p2p_port'], peer_opt=peer_opt,
-            vis_port=node['vis_port'], fund_opt=fund_opt).strip()
+            p2p_port=node['p2p_port'], peers=', '.join(peers),
+            vis_port=node['vis_port']).strip()
+    startup_str = template.format(node_name=name).strip()
     os.makedirs("payload/{}/prism-payload".format(node['host']), exist_ok=True)
+    with open("payload/{}/prism-payload/{}.toml".format(node['host'], name), "w") as f:
+        f.write(config_str + "\n")
     with open("payload/{}/prism-payload/{}.sh".format(node['host'], name), "w") as f:
         f.write(startup_str)
 